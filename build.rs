@@ -0,0 +1,26 @@
+use std::{path::Path, process::Command};
+
+fn main() {
+    let git_hash = std::env::var("SPTZX_GIT_HASH")
+        .ok()
+        .filter(|hash| !hash.is_empty())
+        .or_else(|| {
+            Command::new("git")
+                .args(["rev-parse", "--short=12", "HEAD"])
+                .output()
+                .ok()
+                .filter(|output| output.status.success())
+                .and_then(|output| String::from_utf8(output.stdout).ok())
+                .map(|hash| hash.trim().to_string())
+                .filter(|hash| !hash.is_empty())
+        })
+        .unwrap_or_else(|| "unknown".to_string());
+
+    println!("cargo:rustc-env=SPTZX_GIT_HASH={}", git_hash);
+    println!("cargo:rerun-if-env-changed=SPTZX_GIT_HASH");
+    for path in [".git/HEAD", ".git/refs", ".git/packed-refs"] {
+        if Path::new(path).exists() {
+            println!("cargo:rerun-if-changed={}", path);
+        }
+    }
+}
@@ -1,411 +1,9964 @@
 use axum::{
-    extract::{DefaultBodyLimit, Multipart, Query, State, Path as AxumPath},
-    http::{header, HeaderMap, StatusCode},
-    response::{IntoResponse, Response},
-    routing::{get, post},
-    Json, Router,
+    body::Body,
+    extract::{multipart::MultipartError, FromRequestParts, rejection::JsonRejection, ws::{Message, WebSocket, WebSocketUpgrade}, ConnectInfo, DefaultBodyLimit, Multipart, Query, RawQuery, State, Path as AxumPath},
+    http::{header, request::Parts, HeaderMap, HeaderValue, Method, StatusCode},
+    middleware,
+    response::{Html, IntoResponse, Response},
+    routing::{delete, get, post, put},
+    Extension, Json, Router,
 };
+use axum_server::{accept::Accept, tls_rustls::{RustlsAcceptor, RustlsConfig}};
 use chrono::Utc;
-use futures_util::StreamExt;
+use futures_util::{StreamExt, TryStreamExt};
 use hmac::{Hmac, Mac};
 use serde::{Deserialize, Serialize};
-use sha2::Sha256;
+use sha2::{Digest, Sha256};
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     env,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
     path::PathBuf,
-    sync::Arc,
-    time::Duration,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant, SystemTime},
 };
 use tokio::{
     fs::{self, File},
-    io::{AsyncWriteExt, BufWriter},
-    sync::RwLock,
-    time::sleep,
+    io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt, BufWriter},
+    sync::{broadcast, mpsc, RwLock},
 };
-use tower_http::trace::TraceLayer;
-use tracing::{error, info, warn};
+use tokio_util::{
+    io::ReaderStream,
+    time::{delay_queue, DelayQueue},
+};
+use tower_http::{
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, RequestId, SetRequestIdLayer},
+    trace::TraceLayer,
+};
+use tracing::{error, field::Empty, info, warn, Instrument};
+use tracing_subscriber::{filter::filter_fn, layer::SubscriberExt, util::SubscriberInitExt, Layer};
 use uuid::Uuid;
 
-#[derive(Debug, Clone)]
+const HEADER_SNIFF_LEN: usize = 64 * 1024;
+const FORCE_ATTACHMENT_MIME: [&str; 7] = [
+    "text/html",
+    "application/xhtml+xml",
+    "image/svg+xml",
+    "text/javascript",
+    "application/javascript",
+    "text/xml",
+    "application/xml",
+];
+const MIME_SIGNATURES: [(usize, &[u8], &str); 11] = [
+    (0, b"%PDF-", "application/pdf"),
+    (0, b"PK\x03\x04", "application/zip"),
+    (0, b"\x1f\x8b", "application/gzip"),
+    (0, b"7z\xbc\xaf\x27\x1c", "application/x-7z-compressed"),
+    (0, b"Rar!\x1a\x07", "application/vnd.rar"),
+    (0, b"ID3", "audio/mpeg"),
+    (0, b"OggS", "audio/ogg"),
+    (0, b"fLaC", "audio/flac"),
+    (8, b"WAVE", "audio/wav"),
+    (0, b"\x1a\x45\xdf\xa3", "video/webm"),
+    (4, b"ftyp", "video/mp4"),
+];
+const PURGE_CONCURRENCY: usize = 16;
+const MAX_SIGN_BATCH: usize = 100;
+const EVENT_BUFFER: usize = 256;
+const PIPE_CHANNEL_DEPTH: usize = 16;
+const MAX_FILES_PER_UPLOAD: usize = 1;
+const TAR_MAX_OCTAL: u64 = 0o77777777777;
+const CONNECTION_LIMIT_RESPONSE: &[u8] =
+    b"HTTP/1.1 503 Service Unavailable\r\nretry-after: 1\r\nconnection: close\r\ncontent-length: 0\r\n\r\n";
+const QR_DEFAULT_SIZE: u32 = 256;
+const QR_MIN_SIZE: u32 = 64;
+const QR_MAX_SIZE: u32 = 1024;
+const GUNZIP_SLICE_LEN: usize = 8 * 1024;
+const MIN_BUFFER_SIZE: usize = 4 * 1024;
+const MAX_BUFFER_SIZE: usize = 64 * 1024 * 1024;
+const DEFAULT_SECRET_KEY: &str = "sptzx-change-me-in-production";
+const MIN_PRODUCTION_SECRET_LEN: usize = 32;
+
+#[derive(Debug, Clone, Serialize)]
 struct Config {
+    #[serde(serialize_with = "redact")]
     secret_key: String,
+    production: bool,
     upload_dir: String,
+    storage_pools: Vec<String>,
+    pool_strategy: PoolStrategy,
+    pool_reserve: u64,
+    min_free_bytes: u64,
+    max_total_bytes: Option<u64>,
+    max_files_per_owner: Option<usize>,
+    evict_on_full: bool,
+    cleanup_strategy: CleanupStrategy,
+    max_filename_len: usize,
+    max_description_len: usize,
+    download_name_template: String,
+    download_filename: DownloadFilename,
+    allow_public: bool,
+    id_from_content: bool,
+    sharded_layout: bool,
+    allow_worm: bool,
+    decode_upload_encoding: bool,
+    viewer: bool,
+    health_build_info: bool,
+    max_parts: usize,
+    multi_upload_mode: MultiUploadMode,
+    max_field_size: usize,
+    max_fields_total: usize,
+    archive_max_entries: usize,
+    archive_max_bytes: u64,
+    max_json_body: usize,
+    max_signed_query: usize,
+    obscure_errors: bool,
+    delete_tokens: bool,
+    obscure_delay: u64,
     max_file_size: usize,
+    min_file_size: u64,
     file_lifetime: u64,
+    max_file_lifetime: u64,
+    share_ttl: u64,
+    export_max_bytes: u64,
+    cleanup_interval: u64,
+    cleanup_concurrency: usize,
+    verify_interval: u64,
+    verify_concurrency: usize,
+    verify_quarantine: bool,
+    quarantine_dir: Option<String>,
+    quarantine_retention: u64,
+    grace_period: u64,
     buffer_size: usize,
+    read_buffer: usize,
     bind_addr: String,
-    base_url: String,
+    dual_stack: bool,
+    listen_backlog: i32,
+    max_connections: usize,
+    max_concurrent_downloads: usize,
+    tls_cert: Option<String>,
+    tls_key: Option<String>,
+    base_url: Option<String>,
+    route_prefix: String,
+    trust_host: bool,
+    trusted_hosts: Vec<String>,
+    trusted_proxies: Vec<String>,
     workers: usize,
+    metadata_backend: MetadataBackend,
+    metadata_path: String,
+    shutdown_drain: u64,
+    upload_timeout: u64,
+    download_timeout: u64,
+    pipe: PipeMode,
+    fsync: FsyncPolicy,
+    pipe_timeout: u64,
+    thumbnails: ThumbnailMode,
+    thumbnail_size: u32,
+    transcode_formats: Vec<String>,
+    transcode_max_dimension: u32,
+    strip_exif: bool,
+    clamav_addr: Option<String>,
+    clamav_fail_closed: bool,
+    clamav_timeout: u64,
+    allowed_mime_types: Vec<String>,
+    allowed_extensions: Vec<String>,
+    blocked_extensions: Vec<String>,
+    inline_mime_types: Vec<String>,
+    inline_text: bool,
+    sniff_mime: bool,
+    sniff_generic_types: Vec<String>,
+    allowed_referers: Vec<String>,
+    referer_allow_empty: bool,
+    referer_allow_attachment: bool,
+    max_registry_entries: Option<usize>,
+    #[serde(serialize_with = "redact_option")]
+    admin_token: Option<String>,
+    #[serde(serialize_with = "redact_option")]
+    jwt_secret: Option<String>,
+    jwt_jwks_url: Option<String>,
+    delete_webhook_url: Option<String>,
+    #[serde(serialize_with = "redact_option")]
+    delete_webhook_secret: Option<String>,
+    delete_webhook_retries: u32,
+    jwt_claim: String,
+    jwt_audience: Option<String>,
+    cache_control: Option<String>,
+    max_cache_age: Option<i64>,
+    cache_revalidate_below: i64,
+    cache_policy: Vec<(String, String)>,
+    precompress: bool,
+    signing: bool,
+    signing_version: String,
+    max_link_uses: u64,
+    audit_log: Option<String>,
+    fallback_image: Option<String>,
+    #[serde(serialize_with = "serialize_level")]
+    access_log: Option<tracing::Level>,
+    security_headers: bool,
+    idempotency_ttl: u64,
+    idempotency_max_keys: usize,
+    extra_headers: Vec<(String, String)>,
+    rate_limit: u32,
+    owner_rate_limit: u32,
+    signature_fail_limit: u32,
+    signature_fail_cooldown: u64,
+    password_fail_limit: u32,
+    password_fail_cooldown: u64,
+    class_size_limits: HashMap<&'static str, u64>,
+    #[serde(serialize_with = "redact_api_keys")]
+    api_keys: HashMap<String, String>,
+    anon_owner: String,
+    anon_policy: AnonPolicy,
+    anon_max_file_size: u64,
+    anon_max_ttl: u64,
+    owner_ttls: HashMap<String, u64>,
+    ttl_by_type: Vec<(String, u64)>,
+    #[serde(serialize_with = "redact_owner_keys")]
+    owner_signing_keys: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum AnonPolicy {
+    Allow,
+    Deny,
+    Quota,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum PoolStrategy {
+    MostFree,
+    RoundRobin,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum CleanupStrategy {
+    Ttl,
+    Lru,
+    Size,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum MultiUploadMode {
+    AllOrNothing,
+    BestEffort,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum MetadataBackend {
+    Memory,
+    Sqlite,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum FsyncPolicy {
+    None,
+    Data,
+    Full,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum PipeMode {
+    Off,
+    Abort,
+    Spool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum DownloadFilename {
+    Sanitized,
+    Original,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum ThumbnailMode {
+    Off,
+    Lazy,
+    Eager,
+}
+
+fn redact<S: serde::Serializer>(_: &String, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str("[redacted]")
+}
+
+fn redact_option<S: serde::Serializer>(value: &Option<String>, serializer: S) -> Result<S::Ok, S::Error> {
+    value.as_ref().map(|_| "[redacted]").serialize(serializer)
+}
+
+fn redact_api_keys<S: serde::Serializer>(keys: &HashMap<String, String>, serializer: S) -> Result<S::Ok, S::Error> {
+    let owners: std::collections::BTreeSet<&String> = keys.values().collect();
+    owners.serialize(serializer)
+}
+
+fn redact_owner_keys<S: serde::Serializer>(keys: &HashMap<String, String>, serializer: S) -> Result<S::Ok, S::Error> {
+    let owners: std::collections::BTreeSet<&String> = keys.keys().collect();
+    owners.serialize(serializer)
+}
+
+fn serialize_level<S: serde::Serializer>(level: &Option<tracing::Level>, serializer: S) -> Result<S::Ok, S::Error> {
+    level.map(|level| level.as_str().to_ascii_lowercase()).serialize(serializer)
+}
+
+fn clamp_buffer(name: &str, value: usize) -> usize {
+    let clamped = value.clamp(MIN_BUFFER_SIZE, MAX_BUFFER_SIZE);
+    if clamped != value {
+        warn!("⚠️ buffer_clamped | {} | {} -> {} (allowed {}..={})", name, value, clamped, MIN_BUFFER_SIZE, MAX_BUFFER_SIZE);
+    }
+    clamped
 }
 
 impl Config {
     fn from_env() -> Self {
+        let file_lifetime: u64 = env::var("SPTZX_FILE_LIFETIME")
+            .unwrap_or_else(|_| "300".to_string())
+            .parse()
+            .unwrap_or(300);
+
+        let upload_dir = env::var("SPTZX_UPLOAD_DIR")
+            .unwrap_or_else(|_| "./uploads".to_string());
+
         Self {
             secret_key: env::var("SPTZX_SECRET_KEY")
-                .unwrap_or_else(|_| "sptzx-change-me-in-production".to_string()),
-            upload_dir: env::var("SPTZX_UPLOAD_DIR")
-                .unwrap_or_else(|_| "./uploads".to_string()),
+                .ok()
+                .filter(|v| !v.is_empty())
+                .unwrap_or_else(|| DEFAULT_SECRET_KEY.to_string()),
+            production: env::var("SPTZX_ENV")
+                .map(|v| v == "production")
+                .unwrap_or(false),
+            metadata_backend: match env::var("SPTZX_METADATA_BACKEND").unwrap_or_default().as_str() {
+                "sqlite" => MetadataBackend::Sqlite,
+                _ => MetadataBackend::Memory,
+            },
+            metadata_path: env::var("SPTZX_METADATA_PATH")
+                .unwrap_or_else(|_| format!("{}/registry.db", upload_dir)),
+            storage_pools: env::var("SPTZX_STORAGE_POOLS")
+                .ok()
+                .map(|v| {
+                    v.split(',')
+                        .map(|p| p.trim().to_string())
+                        .filter(|p| !p.is_empty())
+                        .collect::<Vec<_>>()
+                })
+                .filter(|pools| !pools.is_empty())
+                .unwrap_or_else(|| vec![upload_dir.clone()]),
+            pool_strategy: match env::var("SPTZX_POOL_STRATEGY").unwrap_or_default().as_str() {
+                "round_robin" => PoolStrategy::RoundRobin,
+                _ => PoolStrategy::MostFree,
+            },
+            pool_reserve: env::var("SPTZX_POOL_RESERVE")
+                .unwrap_or_else(|_| "0".to_string())
+                .parse()
+                .unwrap_or(0),
+            max_total_bytes: env::var("SPTZX_MAX_TOTAL_BYTES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|v| *v > 0),
+            max_files_per_owner: env::var("SPTZX_MAX_FILES_PER_OWNER")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|v| *v > 0),
+            evict_on_full: env::var("SPTZX_EVICT_ON_FULL")
+                .map(|v| v == "true")
+                .unwrap_or(false),
+            cleanup_strategy: match env::var("SPTZX_CLEANUP_STRATEGY").unwrap_or_default().as_str() {
+                "lru" => CleanupStrategy::Lru,
+                "size" => CleanupStrategy::Size,
+                _ => CleanupStrategy::Ttl,
+            },
+            min_free_bytes: env::var("SPTZX_MIN_FREE_BYTES")
+                .unwrap_or_else(|_| "0".to_string())
+                .parse()
+                .unwrap_or(0),
+            max_parts: env::var("SPTZX_MAX_PARTS")
+                .unwrap_or_else(|_| "16".to_string())
+                .parse()
+                .unwrap_or(16),
+            multi_upload_mode: match env::var("SPTZX_MULTI_UPLOAD_MODE").unwrap_or_default().as_str() {
+                "best-effort" => MultiUploadMode::BestEffort,
+                _ => MultiUploadMode::AllOrNothing,
+            },
+            max_field_size: env::var("SPTZX_MAX_FIELD_SIZE")
+                .unwrap_or_else(|_| "8192".to_string())
+                .parse()
+                .unwrap_or(8192),
+            max_fields_total: env::var("SPTZX_MAX_FIELDS_TOTAL")
+                .unwrap_or_else(|_| "65536".to_string())
+                .parse()
+                .unwrap_or(65536),
+            archive_max_entries: env::var("SPTZX_ARCHIVE_MAX_ENTRIES")
+                .unwrap_or_else(|_| "100".to_string())
+                .parse()
+                .unwrap_or(100),
+            archive_max_bytes: env::var("SPTZX_ARCHIVE_MAX_BYTES")
+                .unwrap_or_else(|_| "1073741824".to_string())
+                .parse()
+                .unwrap_or(1073741824),
+            max_signed_query: env::var("SPTZX_MAX_SIGNED_QUERY")
+                .unwrap_or_else(|_| "4096".to_string())
+                .parse()
+                .unwrap_or(4096),
+            obscure_errors: env::var("SPTZX_OBSCURE_ERRORS").map(|v| v == "true").unwrap_or(false),
+            delete_tokens: env::var("SPTZX_DELETE_TOKENS").map(|v| v == "true").unwrap_or(false),
+            obscure_delay: env::var("SPTZX_OBSCURE_DELAY_MS")
+                .unwrap_or_else(|_| "100".to_string())
+                .parse()
+                .unwrap_or(100),
+            max_json_body: env::var("SPTZX_MAX_JSON_BODY")
+                .unwrap_or_else(|_| "1048576".to_string())
+                .parse()
+                .unwrap_or(1048576),
+            viewer: env::var("SPTZX_VIEWER")
+                .map(|v| v == "true")
+                .unwrap_or(false),
+            health_build_info: env::var("SPTZX_HEALTH_BUILD_INFO")
+                .map(|v| v != "false")
+                .unwrap_or(true),
+            allow_public: env::var("SPTZX_ALLOW_PUBLIC")
+                .map(|v| v == "true")
+                .unwrap_or(false),
+            id_from_content: env::var("SPTZX_ID_FROM_CONTENT")
+                .map(|v| v == "true")
+                .unwrap_or(false),
+            sharded_layout: env::var("SPTZX_CONTENT_LAYOUT").is_ok_and(|v| v == "sharded"),
+            allow_worm: env::var("SPTZX_ALLOW_WORM")
+                .map(|v| v == "true")
+                .unwrap_or(false),
+            decode_upload_encoding: env::var("SPTZX_DECODE_UPLOAD_ENCODING")
+                .map(|v| v == "true")
+                .unwrap_or(false),
+            max_filename_len: env::var("SPTZX_MAX_FILENAME_LEN")
+                .unwrap_or_else(|_| "255".to_string())
+                .parse::<usize>()
+                .unwrap_or(255)
+                .max(8),
+            max_description_len: env::var("SPTZX_MAX_DESCRIPTION_LEN")
+                .unwrap_or_else(|_| "500".to_string())
+                .parse()
+                .unwrap_or(500),
+            download_name_template: env::var("SPTZX_DOWNLOAD_NAME_TEMPLATE")
+                .ok()
+                .filter(|v| !v.is_empty())
+                .unwrap_or_else(|| "{name}".to_string()),
+            download_filename: match env::var("SPTZX_DOWNLOAD_FILENAME").unwrap_or_default().as_str() {
+                "original" => DownloadFilename::Original,
+                _ => DownloadFilename::Sanitized,
+            },
+            upload_dir,
             max_file_size: env::var("SPTZX_MAX_FILE_SIZE")
                 .unwrap_or_else(|_| "536870912".to_string())
                 .parse()
                 .unwrap_or(536870912),
-            file_lifetime: env::var("SPTZX_FILE_LIFETIME")
-                .unwrap_or_else(|_| "300".to_string())
+            min_file_size: env::var("SPTZX_MIN_FILE_SIZE")
+                .unwrap_or_else(|_| "0".to_string())
+                .parse()
+                .unwrap_or(0),
+            file_lifetime,
+            max_file_lifetime: env::var("SPTZX_MAX_FILE_LIFETIME")
+                .unwrap_or_else(|_| "86400".to_string())
+                .parse()
+                .unwrap_or(86400)
+                .max(file_lifetime),
+            share_ttl: env::var("SPTZX_SHARE_TTL")
+                .unwrap_or_else(|_| "0".to_string())
+                .parse()
+                .unwrap_or(0),
+            export_max_bytes: env::var("SPTZX_EXPORT_MAX_BYTES")
+                .unwrap_or_else(|_| "1073741824".to_string())
+                .parse()
+                .unwrap_or(1073741824),
+            cleanup_interval: env::var("SPTZX_CLEANUP_INTERVAL")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or_else(|| (file_lifetime / 2).min(60))
+                .max(1),
+            grace_period: env::var("SPTZX_GRACE_PERIOD")
+                .unwrap_or_else(|_| "0".to_string())
+                .parse()
+                .unwrap_or(0),
+            cleanup_concurrency: env::var("SPTZX_CLEANUP_CONCURRENCY")
+                .unwrap_or_else(|_| "4".to_string())
                 .parse()
-                .unwrap_or(300),
-            buffer_size: env::var("SPTZX_BUFFER_SIZE")
-                .unwrap_or_else(|_| "2097152".to_string())
+                .unwrap_or(4)
+                .max(1),
+            verify_interval: env::var("SPTZX_VERIFY_INTERVAL")
+                .unwrap_or_else(|_| "0".to_string())
                 .parse()
-                .unwrap_or(2097152),
+                .unwrap_or(0),
+            verify_concurrency: env::var("SPTZX_VERIFY_CONCURRENCY")
+                .unwrap_or_else(|_| "2".to_string())
+                .parse()
+                .unwrap_or(2)
+                .max(1),
+            verify_quarantine: env::var("SPTZX_VERIFY_QUARANTINE")
+                .map(|v| v == "true")
+                .unwrap_or(false),
+            quarantine_dir: env::var("SPTZX_QUARANTINE_DIR").ok().filter(|v| !v.is_empty()),
+            quarantine_retention: env::var("SPTZX_QUARANTINE_RETENTION")
+                .unwrap_or_else(|_| "2592000".to_string())
+                .parse()
+                .unwrap_or(2592000),
+            buffer_size: clamp_buffer(
+                "SPTZX_BUFFER_SIZE",
+                env::var("SPTZX_BUFFER_SIZE")
+                    .unwrap_or_else(|_| "2097152".to_string())
+                    .parse()
+                    .unwrap_or(2097152),
+            ),
+            read_buffer: clamp_buffer(
+                "SPTZX_READ_BUFFER",
+                env::var("SPTZX_READ_BUFFER")
+                    .unwrap_or_else(|_| "65536".to_string())
+                    .parse()
+                    .unwrap_or(65536),
+            ),
             bind_addr: env::var("SPTZX_BIND_ADDR")
                 .unwrap_or_else(|_| "0.0.0.0:3000".to_string()),
+            dual_stack: env::var("SPTZX_DUAL_STACK")
+                .map(|v| v == "true")
+                .unwrap_or(false),
+            listen_backlog: env::var("SPTZX_LISTEN_BACKLOG")
+                .unwrap_or_else(|_| "1024".to_string())
+                .parse::<i32>()
+                .unwrap_or(1024)
+                .max(1),
+            max_concurrent_downloads: env::var("SPTZX_MAX_CONCURRENT_DOWNLOADS")
+                .unwrap_or_else(|_| "0".to_string())
+                .parse()
+                .unwrap_or(0),
+            max_connections: env::var("SPTZX_MAX_CONNECTIONS")
+                .unwrap_or_else(|_| "0".to_string())
+                .parse()
+                .unwrap_or(0),
+            tls_cert: env::var("SPTZX_TLS_CERT").ok().filter(|v| !v.is_empty()),
+            tls_key: env::var("SPTZX_TLS_KEY").ok().filter(|v| !v.is_empty()),
+            route_prefix: env::var("SPTZX_ROUTE_PREFIX")
+                .unwrap_or_default()
+                .trim()
+                .trim_end_matches('/')
+                .to_string(),
             base_url: env::var("SPTZX_BASE_URL")
-                .unwrap_or_else(|_| "http://localhost:3000".to_string()),
+                .ok()
+                .map(|v| v.trim_end_matches('/').to_string())
+                .filter(|v| !v.is_empty()),
+            trust_host: env::var("SPTZX_TRUST_HOST")
+                .map(|v| v == "true")
+                .unwrap_or(false),
+            trusted_hosts: env::var("SPTZX_TRUSTED_HOSTS")
+                .unwrap_or_default()
+                .split(',')
+                .map(|v| v.trim().to_ascii_lowercase())
+                .filter(|v| !v.is_empty())
+                .collect(),
+            trusted_proxies: env::var("SPTZX_TRUSTED_PROXIES")
+                .unwrap_or_default()
+                .split(',')
+                .map(|v| v.trim().to_ascii_lowercase())
+                .filter(|v| !v.is_empty())
+                .collect(),
             workers: env::var("SPTZX_WORKERS")
                 .unwrap_or_else(|_| "16".to_string())
                 .parse()
                 .unwrap_or(16),
+            shutdown_drain: env::var("SPTZX_SHUTDOWN_DRAIN")
+                .unwrap_or_else(|_| "5".to_string())
+                .parse()
+                .unwrap_or(5),
+            upload_timeout: env::var("SPTZX_UPLOAD_TIMEOUT")
+                .unwrap_or_else(|_| "60".to_string())
+                .parse()
+                .unwrap_or(60),
+            download_timeout: env::var("SPTZX_DOWNLOAD_TIMEOUT")
+                .unwrap_or_else(|_| "60".to_string())
+                .parse()
+                .unwrap_or(60),
+            fsync: match env::var("SPTZX_FSYNC").unwrap_or_default().as_str() {
+                "data" => FsyncPolicy::Data,
+                "full" => FsyncPolicy::Full,
+                _ => FsyncPolicy::None,
+            },
+            pipe: match env::var("SPTZX_PIPE").unwrap_or_default().as_str() {
+                "abort" => PipeMode::Abort,
+                "spool" => PipeMode::Spool,
+                _ => PipeMode::Off,
+            },
+            pipe_timeout: env::var("SPTZX_PIPE_TIMEOUT")
+                .unwrap_or_else(|_| "60".to_string())
+                .parse()
+                .unwrap_or(60)
+                .max(1),
+            thumbnails: match env::var("SPTZX_THUMBNAILS").unwrap_or_default().as_str() {
+                "eager" => ThumbnailMode::Eager,
+                "lazy" => ThumbnailMode::Lazy,
+                _ => ThumbnailMode::Off,
+            },
+            thumbnail_size: env::var("SPTZX_THUMBNAIL_SIZE")
+                .unwrap_or_else(|_| "256".to_string())
+                .parse()
+                .unwrap_or(256)
+                .max(1),
+            transcode_formats: env::var("SPTZX_TRANSCODE_FORMATS")
+                .unwrap_or_default()
+                .split(',')
+                .map(|v| v.trim().to_lowercase())
+                .filter(|v| image::ImageFormat::from_extension(v).is_some_and(|f| f.writing_enabled()))
+                .collect(),
+            transcode_max_dimension: env::var("SPTZX_TRANSCODE_MAX_DIMENSION")
+                .unwrap_or_else(|_| "4096".to_string())
+                .parse()
+                .unwrap_or(4096),
+            strip_exif: env::var("SPTZX_STRIP_EXIF")
+                .map(|v| v == "true")
+                .unwrap_or(false),
+            clamav_addr: env::var("SPTZX_CLAMAV_ADDR").ok().filter(|v| !v.is_empty()),
+            clamav_fail_closed: env::var("SPTZX_CLAMAV_FAIL_MODE")
+                .map(|v| v != "open")
+                .unwrap_or(true),
+            clamav_timeout: env::var("SPTZX_CLAMAV_TIMEOUT")
+                .unwrap_or_else(|_| "30".to_string())
+                .parse()
+                .unwrap_or(30),
+            allowed_mime_types: env::var("SPTZX_ALLOWED_MIME_TYPES")
+                .unwrap_or_default()
+                .split(',')
+                .map(|v| v.trim().to_ascii_lowercase())
+                .filter(|v| !v.is_empty())
+                .collect(),
+            allowed_extensions: env::var("SPTZX_ALLOWED_EXTENSIONS")
+                .unwrap_or_default()
+                .split(',')
+                .map(|v| v.trim().trim_start_matches('.').to_ascii_lowercase())
+                .filter(|v| !v.is_empty())
+                .collect(),
+            blocked_extensions: env::var("SPTZX_BLOCKED_EXTENSIONS")
+                .unwrap_or_default()
+                .split(',')
+                .map(|v| v.trim().trim_start_matches('.').to_ascii_lowercase())
+                .filter(|v| !v.is_empty())
+                .collect(),
+            inline_mime_types: env::var("SPTZX_INLINE_MIME")
+                .unwrap_or_else(|_| "image/*,video/*,audio/*".to_string())
+                .split(',')
+                .map(|v| v.trim().to_ascii_lowercase())
+                .filter(|v| !v.is_empty())
+                .collect(),
+            inline_text: env::var("SPTZX_INLINE_TEXT").map(|v| v == "true").unwrap_or(false),
+            sniff_mime: env::var("SPTZX_SNIFF_MIME").map(|v| v == "true").unwrap_or(false),
+            sniff_generic_types: env::var("SPTZX_SNIFF_GENERIC_TYPES")
+                .unwrap_or_else(|_| "application/octet-stream,binary/octet-stream,application/unknown,application/x-download".to_string())
+                .split(',')
+                .map(|v| v.trim().to_ascii_lowercase())
+                .filter(|v| !v.is_empty())
+                .collect(),
+            allowed_referers: env::var("SPTZX_ALLOWED_REFERERS")
+                .unwrap_or_default()
+                .split(',')
+                .map(|v| v.trim().to_ascii_lowercase())
+                .filter(|v| !v.is_empty())
+                .collect(),
+            referer_allow_empty: env::var("SPTZX_REFERER_ALLOW_EMPTY")
+                .map(|v| v != "false")
+                .unwrap_or(true),
+            referer_allow_attachment: env::var("SPTZX_REFERER_ALLOW_ATTACHMENT")
+                .map(|v| v != "false")
+                .unwrap_or(true),
+            max_registry_entries: env::var("SPTZX_MAX_REGISTRY_ENTRIES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|v| *v > 0),
+            admin_token: env::var("SPTZX_ADMIN_TOKEN").ok().filter(|v| !v.is_empty()),
+            jwt_secret: env::var("SPTZX_JWT_SECRET").ok().filter(|v| !v.is_empty()),
+            jwt_jwks_url: env::var("SPTZX_JWT_JWKS_URL").ok().filter(|v| !v.is_empty()),
+            delete_webhook_url: env::var("SPTZX_DELETE_WEBHOOK_URL").ok().filter(|v| !v.is_empty()),
+            delete_webhook_secret: env::var("SPTZX_DELETE_WEBHOOK_SECRET").ok().filter(|v| !v.is_empty()),
+            delete_webhook_retries: env::var("SPTZX_DELETE_WEBHOOK_RETRIES")
+                .unwrap_or_else(|_| "3".to_string())
+                .parse()
+                .unwrap_or(3),
+            jwt_claim: env::var("SPTZX_JWT_CLAIM").unwrap_or_else(|_| "sub".to_string()),
+            jwt_audience: env::var("SPTZX_JWT_AUDIENCE").ok().filter(|v| !v.is_empty()),
+            cache_control: env::var("SPTZX_CACHE_CONTROL").ok().filter(|v| !v.is_empty()),
+            max_cache_age: env::var("SPTZX_MAX_CACHE_AGE").ok().and_then(|v| v.parse().ok()).filter(|v| *v > 0),
+            cache_revalidate_below: env::var("SPTZX_CACHE_REVALIDATE_BELOW")
+                .unwrap_or_else(|_| "60".to_string())
+                .parse()
+                .unwrap_or(60),
+            cache_policy: env::var("SPTZX_CACHE_POLICY")
+                .unwrap_or_default()
+                .split(',')
+                .filter(|v| !v.trim().is_empty())
+                .map(|v| match v.rsplit_once(':') {
+                    Some((pattern, policy)) => (pattern.trim().to_ascii_lowercase(), policy.trim().to_ascii_lowercase()),
+                    None => (v.trim().to_ascii_lowercase(), String::new()),
+                })
+                .collect(),
+            precompress: env::var("SPTZX_PRECOMPRESS")
+                .map(|v| v == "true")
+                .unwrap_or(false),
+            rate_limit: env::var("SPTZX_RATE_LIMIT")
+                .unwrap_or_else(|_| "0".to_string())
+                .parse()
+                .unwrap_or(0),
+            owner_rate_limit: env::var("SPTZX_OWNER_RATE_LIMIT")
+                .unwrap_or_else(|_| "0".to_string())
+                .parse()
+                .unwrap_or(0),
+            signature_fail_limit: env::var("SPTZX_SIGNATURE_FAIL_LIMIT")
+                .unwrap_or_else(|_| "0".to_string())
+                .parse()
+                .unwrap_or(0),
+            signature_fail_cooldown: env::var("SPTZX_SIGNATURE_FAIL_COOLDOWN")
+                .unwrap_or_else(|_| "300".to_string())
+                .parse()
+                .unwrap_or(300)
+                .max(1),
+            password_fail_limit: env::var("SPTZX_PASSWORD_FAIL_LIMIT")
+                .unwrap_or_else(|_| "5".to_string())
+                .parse()
+                .unwrap_or(5),
+            password_fail_cooldown: env::var("SPTZX_PASSWORD_FAIL_COOLDOWN")
+                .unwrap_or_else(|_| "300".to_string())
+                .parse()
+                .unwrap_or(300)
+                .max(1),
+            audit_log: env::var("SPTZX_AUDIT_LOG").ok().filter(|v| !v.is_empty()),
+            fallback_image: env::var("SPTZX_FALLBACK_IMAGE").ok().filter(|v| !v.is_empty()),
+            access_log: match env::var("SPTZX_ACCESS_LOG").unwrap_or_default().as_str() {
+                "off" => None,
+                "debug" => Some(tracing::Level::DEBUG),
+                _ => Some(tracing::Level::INFO),
+            },
+            idempotency_ttl: env::var("SPTZX_IDEMPOTENCY_TTL")
+                .unwrap_or_else(|_| "3600".to_string())
+                .parse()
+                .unwrap_or(3600),
+            idempotency_max_keys: env::var("SPTZX_IDEMPOTENCY_MAX_KEYS")
+                .unwrap_or_else(|_| "10000".to_string())
+                .parse()
+                .unwrap_or(10000),
+            security_headers: env::var("SPTZX_SECURITY_HEADERS")
+                .map(|v| v == "true")
+                .unwrap_or(false),
+            extra_headers: env::var("SPTZX_EXTRA_HEADERS")
+                .unwrap_or_default()
+                .split('|')
+                .filter(|v| !v.trim().is_empty())
+                .map(|v| match v.split_once(':') {
+                    Some((name, value)) => (name.trim().to_string(), value.trim().to_string()),
+                    None => (v.trim().to_string(), String::new()),
+                })
+                .collect(),
+            signing: env::var("SPTZX_SIGNING")
+                .map(|v| v != "off")
+                .unwrap_or(true),
+            signing_version: env::var("SPTZX_SIGNING_VERSION").unwrap_or_else(|_| "v1".to_string()),
+            max_link_uses: env::var("SPTZX_MAX_LINK_USES")
+                .unwrap_or_else(|_| "0".to_string())
+                .parse()
+                .unwrap_or(0),
+            class_size_limits: MIME_CLASSES
+                .iter()
+                .filter_map(|class| {
+                    env::var(format!("SPTZX_MAX_SIZE_{}", class.to_ascii_uppercase()))
+                        .ok()
+                        .and_then(|v| v.parse().ok())
+                        .map(|limit| (*class, limit))
+                })
+                .collect(),
+            api_keys: env::var("SPTZX_API_KEYS")
+                .unwrap_or_default()
+                .split(',')
+                .filter_map(|entry| entry.trim().split_once(':'))
+                .map(|(owner, key)| (key.trim().to_string(), owner.trim().to_string()))
+                .filter(|(key, owner)| !key.is_empty() && !owner.is_empty())
+                .collect(),
+            owner_signing_keys: env::var("SPTZX_OWNER_SIGNING_KEYS")
+                .unwrap_or_default()
+                .split(',')
+                .filter_map(|entry| entry.trim().split_once(':'))
+                .map(|(owner, key)| (owner.trim().to_string(), key.trim().to_string()))
+                .filter(|(owner, key)| !owner.is_empty() && !key.is_empty())
+                .collect(),
+            owner_ttls: env::var("SPTZX_OWNER_TTLS")
+                .unwrap_or_default()
+                .split(',')
+                .filter_map(|entry| entry.trim().split_once(':'))
+                .filter_map(|(owner, ttl)| Some((owner.trim().to_string(), ttl.trim().parse::<u64>().ok()?)))
+                .filter(|(owner, ttl)| !owner.is_empty() && *ttl > 0)
+                .collect(),
+            ttl_by_type: env::var("SPTZX_TTL_BY_TYPE")
+                .unwrap_or_default()
+                .split(',')
+                .filter_map(|entry| entry.trim().rsplit_once(':'))
+                .filter_map(|(pattern, ttl)| Some((pattern.trim().to_ascii_lowercase(), ttl.trim().parse::<u64>().ok()?)))
+                .filter(|(pattern, ttl)| !pattern.is_empty() && *ttl > 0)
+                .collect(),
+            anon_owner: env::var("SPTZX_ANON_OWNER")
+                .ok()
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty())
+                .unwrap_or_else(|| "default".to_string()),
+            anon_policy: match env::var("SPTZX_ANON_POLICY").unwrap_or_default().as_str() {
+                "deny" => AnonPolicy::Deny,
+                "quota" => AnonPolicy::Quota,
+                _ => AnonPolicy::Allow,
+            },
+            anon_max_file_size: env::var("SPTZX_ANON_MAX_FILE_SIZE")
+                .unwrap_or_else(|_| "10485760".to_string())
+                .parse()
+                .unwrap_or(10485760),
+            anon_max_ttl: env::var("SPTZX_ANON_MAX_TTL")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(file_lifetime)
+                .max(1),
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.production {
+            if self.secret_key == DEFAULT_SECRET_KEY {
+                return Err("SPTZX_SECRET_KEY must be set when SPTZX_ENV=production".to_string());
+            }
+            if self.secret_key.len() < MIN_PRODUCTION_SECRET_LEN {
+                return Err(format!(
+                    "SPTZX_SECRET_KEY must be at least {} bytes when SPTZX_ENV=production (try: openssl rand -hex 32)",
+                    MIN_PRODUCTION_SECRET_LEN
+                ));
+            }
+        }
+        self.bind_addr
+            .parse::<SocketAddr>()
+            .map_err(|e| format!("invalid SPTZX_BIND_ADDR '{}': {} (use e.g. 0.0.0.0:3000 or [::]:3000)", self.bind_addr, e))?;
+        if self.cleanup_strategy != CleanupStrategy::Ttl && self.max_total_bytes.is_none() {
+            return Err("SPTZX_CLEANUP_STRATEGY=lru or size needs SPTZX_MAX_TOTAL_BYTES".to_string());
+        }
+        validate_name_template(&self.download_name_template)?;
+        if let Some(url) = self.delete_webhook_url.as_deref().filter(|u| !u.starts_with("http://") && !u.starts_with("https://")) {
+            return Err(format!("invalid SPTZX_DELETE_WEBHOOK_URL '{}' (use an http:// or https:// URL)", url));
+        }
+        if self.delete_webhook_url.is_some() && self.delete_webhook_secret.is_none() {
+            return Err("SPTZX_DELETE_WEBHOOK_URL needs SPTZX_DELETE_WEBHOOK_SECRET".to_string());
+        }
+        if self.sharded_layout && !self.id_from_content {
+            return Err("SPTZX_CONTENT_LAYOUT=sharded needs SPTZX_ID_FROM_CONTENT=true".to_string());
+        }
+        if self.anon_policy != AnonPolicy::Allow
+            && self.api_keys.is_empty()
+            && self.jwt_secret.is_none()
+            && self.jwt_jwks_url.is_none()
+        {
+            return Err("SPTZX_ANON_POLICY=deny or quota needs SPTZX_API_KEYS or JWT auth".to_string());
+        }
+        if !SIGNATURE_VERSIONS.contains(&self.signing_version.as_str()) {
+            return Err(format!(
+                "invalid SPTZX_SIGNING_VERSION '{}' (use v1 for HMAC-SHA256 or v2 for HMAC-SHA512)",
+                self.signing_version
+            ));
+        }
+        if !self.route_prefix.is_empty() {
+            let valid = self.route_prefix.starts_with('/')
+                && self.route_prefix[1..]
+                    .split('/')
+                    .all(|segment| !segment.is_empty() && segment != "." && segment != ".." && segment.bytes().all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.' | b'~')));
+            if !valid {
+                return Err(format!(
+                    "invalid SPTZX_ROUTE_PREFIX '{}' (use e.g. /files or /cdn/v1)",
+                    self.route_prefix
+                ));
+            }
+        }
+        for (pattern, policy) in &self.cache_policy {
+            if !CACHE_POLICIES.contains(&policy.as_str()) {
+                return Err(format!(
+                    "invalid policy '{}' for '{}' in SPTZX_CACHE_POLICY (use one of: {})",
+                    policy,
+                    pattern,
+                    CACHE_POLICIES.join(", ")
+                ));
+            }
         }
+        if let Some(invalid) = self.trusted_proxies.iter().find(|cidr| parse_cidr(cidr).is_none()) {
+            return Err(format!("invalid CIDR '{}' in SPTZX_TRUSTED_PROXIES (use e.g. 10.0.0.0/8 or ::1)", invalid));
+        }
+        if self.tls_cert.is_some() != self.tls_key.is_some() {
+            return Err("SPTZX_TLS_CERT and SPTZX_TLS_KEY must be set together".to_string());
+        }
+        for (name, value) in &self.extra_headers {
+            let parsed = header::HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| format!("invalid header name '{}' in SPTZX_EXTRA_HEADERS", name))?;
+            if PROTECTED_HEADERS.contains(&parsed) {
+                return Err(format!("SPTZX_EXTRA_HEADERS cannot set '{}'", parsed));
+            }
+            if value.is_empty() || HeaderValue::from_str(value).is_err() {
+                return Err(format!("invalid value for '{}' in SPTZX_EXTRA_HEADERS (use 'Name: Value|Name: Value')", name));
+            }
+        }
+        Ok(())
     }
 }
 
 #[derive(Debug, Clone)]
 struct AppState {
-    file_registry: Arc<RwLock<HashMap<String, FileMetadata>>>,
+    file_registry: Arc<Registry>,
     config: Arc<Config>,
+    expiry_tx: mpsc::UnboundedSender<(String, i64)>,
+    draining: Arc<AtomicBool>,
+    ready: Arc<AtomicBool>,
+    build: Arc<BuildInfo>,
+    readers: Arc<ReaderTracker>,
+    ip_limiter: Option<Arc<RateLimiter>>,
+    owner_limiter: Option<Arc<RateLimiter>>,
+    signature_failures: Option<Arc<FailureTracker>>,
+    password_failures: Option<Arc<FailureTracker>>,
+    connections: Option<Arc<ConnectionLimit>>,
+    link_uses: Arc<std::sync::Mutex<HashMap<String, LinkUses>>>,
+    audit_tx: Option<mpsc::UnboundedSender<AuditRecord>>,
+    events: broadcast::Sender<FileEvent>,
+    idempotency: Arc<IdempotencyCache>,
+    unwritable_pools: Arc<std::sync::Mutex<HashSet<String>>>,
+    pool_cursor: Arc<AtomicUsize>,
+    stored_bytes: Arc<AtomicU64>,
+    owner_files: Arc<std::sync::Mutex<HashMap<String, usize>>>,
+    jwt: Option<Arc<JwtVerifier>>,
+    delete_webhook: Option<Arc<DeleteWebhook>>,
+    fallback_image: Option<Arc<FallbackImage>>,
+    pipes: Arc<std::sync::Mutex<HashMap<String, PipeSlot>>>,
+    metrics: Arc<Metrics>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct FileMetadata {
-    file_id: String,
-    original_name: String,
-    disk_path: String,
-    mime_type: String,
-    size: u64,
-    uploaded_at: i64,
-    owner: String,
+impl AppState {
+    fn new(
+        config: Arc<Config>,
+        expiry_tx: mpsc::UnboundedSender<(String, i64)>,
+        audit_tx: Option<mpsc::UnboundedSender<AuditRecord>>,
+        fallback_image: Option<Arc<FallbackImage>>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Self {
+            file_registry: Arc::new(Registry::open(&config)?),
+            config: config.clone(),
+            expiry_tx,
+            draining: Arc::new(AtomicBool::new(false)),
+            ready: Arc::new(AtomicBool::new(false)),
+            build: Arc::new(BuildInfo::current()),
+            readers: Arc::new(ReaderTracker::default()),
+            ip_limiter: RateLimiter::new(config.rate_limit),
+            owner_limiter: RateLimiter::new(config.owner_rate_limit),
+            signature_failures: FailureTracker::new(config.signature_fail_limit, config.signature_fail_cooldown),
+            password_failures: FailureTracker::new(config.password_fail_limit, config.password_fail_cooldown),
+            connections: ConnectionLimit::new(config.max_connections),
+            metrics: Arc::new(Metrics::default()),
+            link_uses: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            audit_tx,
+            events: broadcast::channel(EVENT_BUFFER).0,
+            idempotency: Arc::new(IdempotencyCache::new(config.idempotency_ttl, config.idempotency_max_keys)),
+            unwritable_pools: Arc::new(std::sync::Mutex::new(HashSet::new())),
+            pool_cursor: Arc::new(AtomicUsize::new(0)),
+            stored_bytes: Arc::new(AtomicU64::new(0)),
+            owner_files: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            jwt: JwtVerifier::new(&config),
+            delete_webhook: DeleteWebhook::new(&config),
+            fallback_image,
+            pipes: Arc::new(std::sync::Mutex::new(HashMap::new())),
+        })
+    }
 }
 
-#[derive(Debug, Deserialize)]
-struct SignedUrlParams {
-    #[serde(rename = "sz-version")]
-    version: String,
-    #[serde(rename = "sz-owner")]
-    owner: String,
-    #[serde(rename = "sz-date")]
-    date: String,
-    #[serde(rename = "sz-expires")]
-    expires: String,
-    #[serde(rename = "sz-region")]
-    region: String,
-    #[serde(rename = "sz-mode")]
-    mode: String,
-    #[serde(rename = "sz-type")]
-    file_type: String,
-    #[serde(rename = "sz-id")]
-    id: String,
-    #[serde(rename = "sz-nonce")]
-    nonce: String,
-    #[serde(rename = "sz-signature")]
-    signature: String,
-}
+type PipeChunk = Result<bytes::Bytes, std::io::Error>;
 
-#[derive(Debug, Serialize)]
-struct UploadResponse {
-    id: String,
-    name: String,
-    size: u64,
-    mime: String,
-    view: String,
-    download: String,
-    ttl: u64,
+#[derive(Debug, Clone, Copy)]
+struct UploadLimits {
+    max_file_size: u64,
+    max_lifetime: u64,
+    default_ttl: u64,
 }
 
-#[derive(Debug, Serialize)]
-struct ErrorResponse {
-    error: String,
-}
+impl UploadLimits {
+    fn resolve(config: &Config, authenticated: bool) -> Result<Self, ApiError> {
+        let limits = Self {
+            max_file_size: config.max_file_size as u64,
+            max_lifetime: config.max_file_lifetime,
+            default_ttl: config.file_lifetime,
+        };
+        match (authenticated, config.anon_policy) {
+            (true, _) | (false, AnonPolicy::Allow) => Ok(limits),
+            (false, AnonPolicy::Deny) => Err(ApiError::new(ErrorCode::Unauthorized)),
+            (false, AnonPolicy::Quota) => Ok(Self {
+                max_file_size: limits.max_file_size.min(config.anon_max_file_size),
+                max_lifetime: limits.max_lifetime.min(config.anon_max_ttl),
+                default_ttl: limits.default_ttl.min(config.anon_max_ttl),
+            }),
+        }
+    }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    tracing_subscriber::fmt()
-        .with_target(false)
-        .with_level(true)
-        .compact()
-        .init();
+    fn for_type(self, mime_type: &str, config: &Config) -> Self {
+        match config.ttl_by_type.iter().find(|(pattern, _)| mime_matches(mime_type, std::slice::from_ref(pattern))) {
+            Some((_, ttl)) => Self { default_ttl: (*ttl).min(self.max_lifetime), ..self },
+            None => self,
+        }
+    }
 
-    let config = Arc::new(Config::from_env());
+    fn for_owner(self, owner: &str, config: &Config) -> Self {
+        match config.owner_ttls.get(owner) {
+            Some(ttl) => Self { default_ttl: (*ttl).min(self.max_lifetime), ..self },
+            None => self,
+        }
+    }
+}
 
-    fs::create_dir_all(&config.upload_dir).await?;
+#[derive(Debug)]
+struct PipeSlot {
+    created: Instant,
+    owner: String,
+    content_type: String,
+    limits: UploadLimits,
+    tx: Option<mpsc::Sender<PipeChunk>>,
+    rx: Option<mpsc::Receiver<PipeChunk>>,
+}
 
-    let state = AppState {
-        file_registry: Arc::new(RwLock::new(HashMap::new())),
-        config: config.clone(),
-    };
+#[derive(Debug)]
+struct BuildInfo {
+    version: &'static str,
+    git_hash: &'static str,
+    started_at: i64,
+    started: Instant,
+}
 
-    let app = Router::new()
-        .route("/", get(health_check))
-        .route("/upload", post(upload_handler))
-        .route("/file/:id", get(serve_file))
-        .layer(DefaultBodyLimit::max(config.max_file_size))
-        .layer(TraceLayer::new_for_http())
-        .with_state(state.clone());
+impl BuildInfo {
+    fn current() -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION"),
+            git_hash: env!("SPTZX_GIT_HASH"),
+            started_at: Utc::now().timestamp(),
+            started: Instant::now(),
+        }
+    }
 
-    tokio::spawn(cleanup_expired_files(state.clone()));
+    fn annotate(&self, mut body: serde_json::Value) -> serde_json::Value {
+        if let Some(fields) = body.as_object_mut() {
+            fields.insert("version".into(), self.version.into());
+            fields.insert("git_hash".into(), self.git_hash.into());
+            fields.insert("started_at".into(), self.started_at.into());
+            fields.insert("uptime_seconds".into(), self.started.elapsed().as_secs().into());
+        }
+        body
+    }
+}
 
-    let listener = tokio::net::TcpListener::bind(&config.bind_addr).await?;
-    info!("🚀 Sptzx listening on {} | Workers: {} | Buffer: {}MB | Max: {}MB | TTL: {}s", 
-        config.bind_addr, 
-        config.workers,
-        config.buffer_size / 1024 / 1024,
-        config.max_file_size / 1024 / 1024,
-        config.file_lifetime
-    );
-    
-    axum::serve(listener, app)
-        .tcp_nodelay(true)
-        .await?;
+#[derive(Debug)]
+struct FallbackImage {
+    mime_type: HeaderValue,
+    body: bytes::Bytes,
+}
 
-    Ok(())
+#[derive(Debug)]
+struct JwtVerifier {
+    claim: String,
+    audience: Option<String>,
+    secret: Option<String>,
+    jwks_url: Option<String>,
+    jwks: std::sync::RwLock<jsonwebtoken::jwk::JwkSet>,
 }
 
-async fn health_check() -> Json<serde_json::Value> {
-    Json(serde_json::json!({"status":"ok"}))
+#[derive(Debug)]
+struct DeleteWebhook {
+    client: reqwest::Client,
+    url: String,
+    secret: String,
+    retries: u32,
 }
 
-async fn upload_handler(
-    State(state): State<AppState>,
-    mut multipart: Multipart,
-) -> Result<Json<UploadResponse>, (StatusCode, Json<ErrorResponse>)> {
-    let file_id = Uuid::new_v4().to_string();
-    let mut original_filename = String::from("unknown");
-    let mut total_size: u64 = 0;
-    
-    let disk_path = PathBuf::from(&state.config.upload_dir).join(format!("{}.bin", file_id));
+#[derive(Debug, Serialize)]
+struct DeletionNotice<'a> {
+    event: &'static str,
+    file_id: &'a str,
+    owner: &'a str,
+    reason: &'static str,
+    cause: &'static str,
+    timestamp: i64,
+}
 
-    let file = File::create(&disk_path).await.map_err(|_| {
-        (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse { error: "file_create_failed".to_string() }))
-    })?;
+impl DeleteWebhook {
+    fn new(config: &Config) -> Option<Arc<Self>> {
+        let url = config.delete_webhook_url.clone()?;
+        let secret = config.delete_webhook_secret.clone()?;
+        let client = reqwest::Client::builder().timeout(Duration::from_secs(10)).build().ok()?;
+        Some(Arc::new(Self { client, url, secret, retries: config.delete_webhook_retries }))
+    }
 
-    let mut writer = BufWriter::with_capacity(state.config.buffer_size, file);
+    fn notify(self: &Arc<Self>, metadata: &FileMetadata, cause: &'static str) {
+        let reason = match cause {
+            "expired" => "ttl",
+            "deleted" | "purged" => "manual",
+            _ => "eviction",
+        };
+        let timestamp = Utc::now().timestamp();
+        let notice = DeletionNotice { event: "delete", file_id: &metadata.file_id, owner: &metadata.owner, reason, cause, timestamp };
+        let body = serde_json::to_string(&notice).unwrap_or_default();
+        let signature = compute_hmac(&format!("{}.{}", timestamp, body), &self.secret);
+        let (hook, file_id) = (self.clone(), metadata.file_id.clone());
+        tokio::spawn(async move { hook.deliver(&file_id, timestamp, body, signature).await });
+    }
 
-    while let Some(field) = multipart.next_field().await.map_err(|_| {
-        (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: "invalid_multipart".to_string() }))
-    })? {
-        if let Some(name) = field.file_name() {
-            original_filename = sanitize_filename(name);
+    async fn deliver(&self, file_id: &str, timestamp: i64, body: String, signature: String) {
+        for attempt in 0..=self.retries {
+            if attempt > 0 {
+                tokio::time::sleep(Duration::from_secs(1 << (attempt - 1).min(6))).await;
+            }
+            let sent = self
+                .client
+                .post(&self.url)
+                .header(header::CONTENT_TYPE, "application/json")
+                .header("x-sptzx-timestamp", timestamp)
+                .header("x-sptzx-signature", format!("sha256={}", signature))
+                .body(body.clone())
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+            match sent {
+                Ok(_) => return,
+                Err(e) => warn!("⚠️ delete_webhook_failed | {} | attempt {} | {}", file_id, attempt + 1, e),
+            }
         }
+        error!("❌ delete_webhook_gave_up | {} | {} attempts", file_id, self.retries + 1);
+    }
+}
 
-        let mut stream = field;
-        while let Some(chunk) = stream.next().await {
-            let data = chunk.map_err(|_| {
-                (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: "chunk_read_failed".to_string() }))
-            })?;
-
-            total_size += data.len() as u64;
+impl JwtVerifier {
+    fn new(config: &Config) -> Option<Arc<Self>> {
+        if config.jwt_secret.is_none() && config.jwt_jwks_url.is_none() {
+            return None;
+        }
+        Some(Arc::new(Self {
+            claim: config.jwt_claim.clone(),
+            audience: config.jwt_audience.clone(),
+            secret: config.jwt_secret.clone(),
+            jwks_url: config.jwt_jwks_url.clone(),
+            jwks: std::sync::RwLock::new(jsonwebtoken::jwk::JwkSet { keys: Vec::new() }),
+        }))
+    }
 
-            if total_size > state.config.max_file_size as u64 {
-                let _ = fs::remove_file(&disk_path).await;
-                return Err((StatusCode::PAYLOAD_TOO_LARGE, Json(ErrorResponse { error: "file_too_large".to_string() })));
+    async fn refresh(&self) {
+        let Some(url) = &self.jwks_url else {
+            return;
+        };
+        let fetched = async {
+            reqwest::get(url).await?.error_for_status()?.json::<jsonwebtoken::jwk::JwkSet>().await
+        }
+        .await;
+        match fetched {
+            Ok(set) => {
+                info!("🔑 jwks_loaded | {} | {} keys", url, set.keys.len());
+                *self.jwks.write().unwrap() = set;
             }
-
-            writer.write_all(&data).await.map_err(|_| {
-                (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse { error: "write_failed".to_string() }))
-            })?;
+            Err(e) => warn!("⚠️ jwks_fetch_failed | {} | {}", url, e),
         }
     }
 
-    writer.flush().await.map_err(|_| {
-        (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse { error: "flush_failed".to_string() }))
-    })?;
-
-    let mime_type = mime_guess::from_path(&original_filename).first_or_octet_stream().to_string();
+    fn owner(&self, token: &str) -> Result<String, ApiError> {
+        let header = jsonwebtoken::decode_header(token).map_err(|_| ApiError::new(ErrorCode::InvalidToken))?;
+        let key = match header.alg {
+            jsonwebtoken::Algorithm::HS256 => self
+                .secret
+                .as_ref()
+                .map(|secret| jsonwebtoken::DecodingKey::from_secret(secret.as_bytes())),
+            jsonwebtoken::Algorithm::RS256 => {
+                let jwks = self.jwks.read().unwrap();
+                let jwk = match &header.kid {
+                    Some(kid) => jwks.find(kid),
+                    None => jwks.keys.first(),
+                };
+                jwk.and_then(|jwk| jsonwebtoken::DecodingKey::from_jwk(jwk).ok())
+            }
+            _ => None,
+        }
+        .ok_or_else(|| ApiError::new(ErrorCode::InvalidToken))?;
 
-    let metadata = FileMetadata {
-        file_id: file_id.clone(),
-        original_name: original_filename.clone(),
-        disk_path: disk_path.to_string_lossy().to_string(),
-        mime_type: mime_type.clone(),
-        size: total_size,
-        uploaded_at: Utc::now().timestamp(),
-        owner: "default".to_string(),
-    };
+        let mut validation = jsonwebtoken::Validation::new(header.alg);
+        match &self.audience {
+            Some(audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false,
+        }
 
-    state.file_registry.write().await.insert(file_id.clone(), metadata.clone());
+        let claims = jsonwebtoken::decode::<HashMap<String, serde_json::Value>>(token, &key, &validation)
+            .map_err(|e| match e.kind() {
+                jsonwebtoken::errors::ErrorKind::ExpiredSignature => ApiError::new(ErrorCode::TokenExpired),
+                _ => {
+                    warn!("⚠️ invalid_token | {}", e);
+                    ApiError::new(ErrorCode::InvalidToken)
+                }
+            })?
+            .claims;
+        match claims.get(&self.claim) {
+            Some(serde_json::Value::String(owner)) if !owner.is_empty() => Ok(owner.clone()),
+            Some(serde_json::Value::Number(owner)) => Ok(owner.to_string()),
+            _ => Err(ApiError::new(ErrorCode::InvalidToken)
+                .with_details(serde_json::json!({ "missing_claim": self.claim }))),
+        }
+    }
+}
 
-    info!("✅ {} | {} | {}", original_filename, total_size, mime_type);
+async fn refresh_jwks(verifier: Arc<JwtVerifier>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(600));
+    loop {
+        interval.tick().await;
+        verifier.refresh().await;
+    }
+}
 
-    let view_url = generate_signed_url(&file_id, "inline", &metadata, &state.config);
-    let download_url = generate_signed_url(&file_id, "attachment", &metadata, &state.config);
+#[derive(Debug)]
+struct IdempotencyCache {
+    ttl: Duration,
+    max_keys: usize,
+    entries: std::sync::Mutex<HashMap<(String, String), IdempotencyEntry>>,
+}
 
-    let state_clone = state.clone();
-    let file_id_clone = file_id.clone();
-    tokio::spawn(async move {
-        sleep(Duration::from_secs(state_clone.config.file_lifetime)).await;
-        delete_file(&state_clone, &file_id_clone).await;
-    });
+#[derive(Debug)]
+enum IdempotencyEntry {
+    Pending,
+    Done(Instant, Box<UploadResponse>),
+}
 
-    Ok(Json(UploadResponse {
-        id: file_id,
-        name: original_filename,
-        size: total_size,
-        mime: mime_type,
-        view: view_url,
-        download: download_url,
-        ttl: state.config.file_lifetime,
-    }))
+struct IdempotencyReservation {
+    cache: Arc<IdempotencyCache>,
+    scoped: (String, String),
+    completed: bool,
 }
 
-async fn serve_file(
-    State(state): State<AppState>,
-    AxumPath(file_id): AxumPath<String>,
-    Query(params): Query<HashMap<String, String>>,
-) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
-    let signed_params = parse_signed_params(&params)?;
+impl IdempotencyReservation {
+    fn complete(mut self, response: UploadResponse) {
+        self.completed = true;
+        self.cache.store(&self.scoped, response);
+    }
+}
 
-    if !verify_signature(&signed_params, &state.config) {
-        warn!("⚠️ invalid_sig | {}", file_id);
-        return Err((StatusCode::FORBIDDEN, Json(ErrorResponse { error: "invalid_signature".to_string() })));
+impl Drop for IdempotencyReservation {
+    fn drop(&mut self) {
+        if !self.completed {
+            self.cache.entries.lock().unwrap().remove(&self.scoped);
+        }
     }
+}
 
-    let expires_timestamp = signed_params.expires.parse::<i64>().map_err(|_| {
-        (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: "invalid_expires".to_string() }))
-    })?;
+impl IdempotencyCache {
+    fn new(ttl: u64, max_keys: usize) -> Self {
+        Self { ttl: Duration::from_secs(ttl), max_keys, entries: std::sync::Mutex::new(HashMap::new()) }
+    }
 
-    if Utc::now().timestamp() > expires_timestamp {
-        warn!("⚠️ expired | {}", file_id);
-        return Err((StatusCode::FORBIDDEN, Json(ErrorResponse { error: "link_expired".to_string() })));
+    fn get(&self, owner: &str, key: &str) -> Option<UploadResponse> {
+        let mut entries = self.entries.lock().unwrap();
+        let scoped = (owner.to_string(), key.to_string());
+        match entries.get(&scoped) {
+            Some(IdempotencyEntry::Done(stored_at, response)) if stored_at.elapsed() < self.ttl => Some((**response).clone()),
+            Some(IdempotencyEntry::Done(..)) => {
+                entries.remove(&scoped);
+                None
+            }
+            _ => None,
+        }
     }
 
-    if signed_params.id != file_id {
-        return Err((StatusCode::FORBIDDEN, Json(ErrorResponse { error: "id_mismatch".to_string() })));
+    fn reserve(self: &Arc<Self>, owner: &str, key: &str) -> Result<Option<IdempotencyReservation>, ApiError> {
+        if self.max_keys == 0 {
+            return Ok(None);
+        }
+        let scoped = (owner.to_string(), key.to_string());
+        let mut entries = self.entries.lock().unwrap();
+        if matches!(entries.get(&scoped), Some(IdempotencyEntry::Pending)) {
+            return Err(ApiError::new(ErrorCode::IdempotencyInFlight));
+        }
+        entries.insert(scoped.clone(), IdempotencyEntry::Pending);
+        Ok(Some(IdempotencyReservation { cache: self.clone(), scoped, completed: false }))
     }
 
-    let registry = state.file_registry.read().await;
-    let metadata = registry.get(&file_id).ok_or_else(|| {
-        (StatusCode::NOT_FOUND, Json(ErrorResponse { error: "file_not_found".to_string() }))
-    })?;
+    fn store(&self, scoped: &(String, String), response: UploadResponse) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, entry| match entry {
+            IdempotencyEntry::Done(stored_at, _) => stored_at.elapsed() < self.ttl,
+            IdempotencyEntry::Pending => true,
+        });
+        while entries.len() >= self.max_keys {
+            let oldest = entries
+                .iter()
+                .filter_map(|(k, entry)| match entry {
+                    IdempotencyEntry::Done(stored_at, _) if k != scoped => Some((*stored_at, k)),
+                    _ => None,
+                })
+                .min_by_key(|(stored_at, _)| *stored_at)
+                .map(|(_, k)| k.clone());
+            let Some(oldest) = oldest else {
+                break;
+            };
+            entries.remove(&oldest);
+        }
+        entries.insert(scoped.clone(), IdempotencyEntry::Done(Instant::now(), Box::new(response)));
+    }
+}
 
-    let file_content = fs::read(&metadata.disk_path).await.map_err(|_| {
-        error!("❌ read_failed | {}", file_id);
-        (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse { error: "read_failed".to_string() }))
-    })?;
+#[derive(Debug, Serialize)]
+struct AuditRecord {
+    timestamp: i64,
+    action: &'static str,
+    file_id: String,
+    owner: String,
+    client_ip: String,
+    status: u16,
+    result: String,
+    bytes: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    files: Option<usize>,
+    request_id: String,
+}
 
-    let mut headers = HeaderMap::new();
-    headers.insert(header::CONTENT_TYPE, metadata.mime_type.parse().unwrap());
+#[derive(Debug, Clone, Serialize)]
+struct FileEvent {
+    event: &'static str,
+    file_id: String,
+    owner: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mime: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<&'static str>,
+    timestamp: i64,
+}
 
-    let disposition = if is_viewable_mime(&metadata.mime_type) && signed_params.mode == "inline" {
-        format!("inline; filename=\"{}\"", metadata.original_name)
-    } else {
-        format!("attachment; filename=\"{}\"", metadata.original_name)
-    };
-    headers.insert(header::CONTENT_DISPOSITION, disposition.parse().unwrap());
-    headers.insert(header::CONTENT_LENGTH, metadata.size.to_string().parse().unwrap());
-    headers.insert(header::CACHE_CONTROL, "public, max-age=300".parse().unwrap());
-    headers.insert(header::ACCEPT_RANGES, "bytes".parse().unwrap());
+#[derive(Debug, Serialize)]
+struct PurgeResponse {
+    deleted: usize,
+    bytes: u64,
+    protected: usize,
+}
+
+#[derive(Debug, Clone)]
+struct ServedOwner(String);
 
-    info!("📤 {} | {}", metadata.original_name, metadata.mime_type);
+#[derive(Debug, Clone, Copy)]
+struct ClientIp(IpAddr);
 
-    Ok((StatusCode::OK, headers, file_content).into_response())
+#[axum::async_trait]
+impl FromRequestParts<AppState> for ClientIp {
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let peer = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip())
+            .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+        Ok(ClientIp(client_ip(&state.config, peer, &parts.headers)))
+    }
 }
 
-fn generate_signed_url(file_id: &str, mode: &str, metadata: &FileMetadata, config: &Config) -> String {
-    let version = "v1";
-    let owner = &metadata.owner;
-    let date = Utc::now().format("%Y%m%d").to_string();
-    let expires = (Utc::now().timestamp() + config.file_lifetime as i64).to_string();
-    let region = "global";
-    let file_type = &metadata.mime_type;
-    let nonce = Uuid::new_v4().to_string();
+#[derive(Debug)]
+struct RateLimiter {
+    per_minute: u32,
+    buckets: std::sync::Mutex<HashMap<String, Bucket>>,
+}
 
-    let string_to_sign = format!(
-        "{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}",
-        version, owner, date, expires, region, mode, file_type, file_id, nonce
-    );
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
 
-    let signature = compute_hmac(&string_to_sign, &config.secret_key);
+impl RateLimiter {
+    fn new(per_minute: u32) -> Option<Arc<Self>> {
+        (per_minute > 0).then(|| Arc::new(Self { per_minute, buckets: std::sync::Mutex::new(HashMap::new()) }))
+    }
 
-    format!(
-        "{}/file/{}?sz-version={}&sz-owner={}&sz-date={}&sz-expires={}&sz-region={}&sz-mode={}&sz-type={}&sz-id={}&sz-nonce={}&sz-signature={}",
-        config.base_url, file_id, version, owner, date, expires, region, mode, file_type, file_id, nonce, signature
-    )
+    fn refill_rate(&self) -> f64 {
+        self.per_minute as f64 / 60.0
+    }
+
+    fn check(&self, key: &str) -> Result<(), u64> {
+        let now = Instant::now();
+        let capacity = self.per_minute as f64;
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets
+            .entry(key.to_string())
+            .or_insert(Bucket { tokens: capacity, updated: now });
+        bucket.tokens = (bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * self.refill_rate()).min(capacity);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(((1.0 - bucket.tokens) / self.refill_rate()).ceil() as u64)
+        }
+    }
+
+    fn evict_idle(&self) -> usize {
+        let now = Instant::now();
+        let capacity = self.per_minute as f64;
+        let rate = self.refill_rate();
+        let mut buckets = self.buckets.lock().unwrap();
+        let before = buckets.len();
+        buckets.retain(|_, b| b.tokens + now.duration_since(b.updated).as_secs_f64() * rate < capacity);
+        before - buckets.len()
+    }
 }
 
-fn compute_hmac(data: &str, secret: &str) -> String {
-    type HmacSha256 = Hmac<Sha256>;
-    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).unwrap();
-    mac.update(data.as_bytes());
-    hex::encode(mac.finalize().into_bytes())
+#[derive(Debug)]
+struct LinkUses {
+    count: u64,
+    expires_at: i64,
 }
 
-fn verify_signature(params: &SignedUrlParams, config: &Config) -> bool {
-    let string_to_sign = format!(
-        "{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}",
-        params.version, params.owner, params.date, params.expires,
-        params.region, params.mode, params.file_type, params.id, params.nonce
-    );
-    compute_hmac(&string_to_sign, &config.secret_key) == params.signature
+#[derive(Debug)]
+struct FailureTracker {
+    limit: u32,
+    cooldown: Duration,
+    entries: std::sync::Mutex<HashMap<String, FailureEntry>>,
 }
 
-fn parse_signed_params(
-    params: &HashMap<String, String>,
-) -> Result<SignedUrlParams, (StatusCode, Json<ErrorResponse>)> {
-    let get_param = |key: &str| {
-        params.get(key).cloned().ok_or_else(|| {
-            (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: format!("missing_{}", key) }))
+#[derive(Debug)]
+struct FailureEntry {
+    failures: u32,
+    since: Instant,
+}
+
+impl FailureTracker {
+    fn new(limit: u32, cooldown: u64) -> Option<Arc<Self>> {
+        (limit > 0).then(|| {
+            Arc::new(Self { limit, cooldown: Duration::from_secs(cooldown), entries: std::sync::Mutex::new(HashMap::new()) })
         })
-    };
+    }
 
-    Ok(SignedUrlParams {
-        version: get_param("sz-version")?,
-        owner: get_param("sz-owner")?,
-        date: get_param("sz-date")?,
-        expires: get_param("sz-expires")?,
-        region: get_param("sz-region")?,
-        mode: get_param("sz-mode")?,
-        file_type: get_param("sz-type")?,
-        id: get_param("sz-id")?,
-        nonce: get_param("sz-nonce")?,
-        signature: get_param("sz-signature")?,
-    })
+    fn check(&self, key: &str) -> Result<(), u64> {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(key) {
+            Some(entry) if entry.failures >= self.limit && entry.since.elapsed() < self.cooldown => {
+                Err((self.cooldown - entry.since.elapsed()).as_secs().max(1))
+            }
+            Some(entry) if entry.since.elapsed() >= self.cooldown => {
+                entries.remove(key);
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn record_failure(&self, key: &str) -> bool {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.entry(key.to_string()).or_insert(FailureEntry { failures: 0, since: Instant::now() });
+        if entry.since.elapsed() >= self.cooldown {
+            *entry = FailureEntry { failures: 0, since: Instant::now() };
+        }
+        entry.failures += 1;
+        if entry.failures == self.limit {
+            entry.since = Instant::now();
+            return true;
+        }
+        false
+    }
+
+    fn record_success(&self, key: &str) {
+        self.entries.lock().unwrap().remove(key);
+    }
+
+    fn blocked(&self) -> usize {
+        self.entries
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|e| e.failures >= self.limit && e.since.elapsed() < self.cooldown)
+            .count()
+    }
+
+    fn evict_idle(&self) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let before = entries.len();
+        entries.retain(|_, e| e.since.elapsed() < self.cooldown);
+        before - entries.len()
+    }
 }
 
-fn is_viewable_mime(mime_type: &str) -> bool {
-    mime_type.starts_with("image/") || mime_type.starts_with("video/") || mime_type.starts_with("audio/")
+const DURATION_BUCKETS: [f64; 12] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+const SIZE_BUCKETS: [f64; 9] = [
+    1024.0, 16384.0, 65536.0, 262144.0, 1048576.0, 4194304.0, 16777216.0, 67108864.0, 268435456.0,
+];
+
+#[derive(Debug)]
+struct Histogram {
+    bounds: &'static [f64],
+    buckets: Vec<AtomicU64>,
+    count: AtomicU64,
+    sum: AtomicU64,
 }
 
-fn sanitize_filename(filename: &str) -> String {
-    filename.chars()
-        .filter(|c| c.is_alphanumeric() || *c == '.' || *c == '-' || *c == '_')
-        .take(255)
-        .collect()
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Self {
+            bounds,
+            buckets: bounds.iter().map(|_| AtomicU64::new(0)).collect(),
+            count: AtomicU64::new(0),
+            sum: AtomicU64::new(0f64.to_bits()),
+        }
+    }
+
+    fn observe(&self, value: f64) {
+        if let Some(bucket) = self.bounds.iter().position(|bound| value <= *bound) {
+            self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        let _ = self
+            .sum
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| Some((f64::from_bits(bits) + value).to_bits()));
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        out.push_str(&format!("# HELP {} {}\n# TYPE {} histogram\n", name, help, name));
+        let mut cumulative = 0;
+        for (bound, bucket) in self.bounds.iter().zip(&self.buckets) {
+            cumulative += bucket.load(Ordering::Relaxed);
+            out.push_str(&format!("{}_bucket{{le=\"{}\"}} {}\n", name, bound, cumulative));
+        }
+        let count = self.count.load(Ordering::Relaxed);
+        out.push_str(&format!("{}_bucket{{le=\"+Inf\"}} {}\n", name, count));
+        out.push_str(&format!("{}_sum {}\n", name, f64::from_bits(self.sum.load(Ordering::Relaxed))));
+        out.push_str(&format!("{}_count {}\n", name, count));
+    }
+}
+
+#[derive(Debug)]
+struct Metrics {
+    upload_duration: Histogram,
+    upload_bytes: Histogram,
+    download_ttfb: Histogram,
+    download_duration: Histogram,
+    download_bytes: Histogram,
 }
 
-async fn delete_file(state: &AppState, file_id: &str) {
-    let mut registry = state.file_registry.write().await;
-    if let Some(metadata) = registry.remove(file_id) {
-        match fs::remove_file(&metadata.disk_path).await {
-            Ok(_) => info!("🗑️ {} | {}", metadata.original_name, file_id),
-            Err(e) => error!("❌ delete_failed | {} | {}", file_id, e),
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            upload_duration: Histogram::new(&DURATION_BUCKETS),
+            upload_bytes: Histogram::new(&SIZE_BUCKETS),
+            download_ttfb: Histogram::new(&DURATION_BUCKETS),
+            download_duration: Histogram::new(&DURATION_BUCKETS),
+            download_bytes: Histogram::new(&SIZE_BUCKETS),
         }
     }
 }
 
-async fn cleanup_expired_files(state: AppState) {
-    let mut interval = tokio::time::interval(Duration::from_secs(60));
-    loop {
-        interval.tick().await;
-        let now = Utc::now().timestamp();
-        let to_delete: Vec<String> = {
-            let registry = state.file_registry.read().await;
-            registry.iter()
-                .filter(|(_, m)| now - m.uploaded_at > state.config.file_lifetime as i64)
-                .map(|(id, _)| id.clone())
-                .collect()
+impl Metrics {
+    fn observe_upload(&self, started: Instant, size: u64) {
+        self.upload_duration.observe(started.elapsed().as_secs_f64());
+        self.upload_bytes.observe(size as f64);
+    }
+
+    fn render(&self) -> String {
+        let mut out = String::new();
+        self.upload_duration.render(&mut out, "sptzx_upload_duration_seconds", "Time to receive and store an upload");
+        self.upload_bytes.render(&mut out, "sptzx_upload_size_bytes", "Size of stored uploads");
+        self.download_ttfb.render(&mut out, "sptzx_download_ttfb_seconds", "Time from request to first body byte of a download");
+        self.download_duration.render(&mut out, "sptzx_download_duration_seconds", "Time from request to the end of a download");
+        self.download_bytes.render(&mut out, "sptzx_download_size_bytes", "Body bytes sent per download");
+        out
+    }
+}
+
+struct DownloadTiming {
+    metrics: Arc<Metrics>,
+    started: Instant,
+    first_byte: bool,
+    bytes: u64,
+}
+
+impl Drop for DownloadTiming {
+    fn drop(&mut self) {
+        if !self.first_byte {
+            self.metrics.download_ttfb.observe(self.started.elapsed().as_secs_f64());
+        }
+        self.metrics.download_duration.observe(self.started.elapsed().as_secs_f64());
+        self.metrics.download_bytes.observe(self.bytes as f64);
+    }
+}
+
+fn time_download(metrics: &Arc<Metrics>, started: Instant, response: Response) -> Response {
+    if !response.status().is_success() {
+        return response;
+    }
+    let mut timing = DownloadTiming { metrics: metrics.clone(), started, first_byte: false, bytes: 0 };
+    let (parts, body) = response.into_parts();
+    let body = Body::from_stream(body.into_data_stream().map(move |chunk| {
+        let timing = &mut timing;
+        if let Ok(chunk) = &chunk {
+            if !timing.first_byte {
+                timing.first_byte = true;
+                timing.metrics.download_ttfb.observe(timing.started.elapsed().as_secs_f64());
+            }
+            timing.bytes += chunk.len() as u64;
+        }
+        chunk
+    }));
+    Response::from_parts(parts, body)
+}
+
+#[derive(Debug, Default)]
+struct ReaderTracker {
+    entries: std::sync::Mutex<HashMap<String, ReaderEntry>>,
+}
+
+#[derive(Debug, Default)]
+struct ReaderEntry {
+    readers: usize,
+    downloads: usize,
+    pending_delete: Option<FileMetadata>,
+}
+
+struct ReadGuard {
+    tracker: Arc<ReaderTracker>,
+    file_id: String,
+    download: bool,
+}
+
+impl ReaderTracker {
+    fn acquire(self: &Arc<Self>, file_id: &str) -> ReadGuard {
+        self.entries.lock().unwrap().entry(file_id.to_string()).or_default().readers += 1;
+        ReadGuard { tracker: self.clone(), file_id: file_id.to_string(), download: false }
+    }
+
+    fn delete_pending(&self, file_id: &str) -> bool {
+        self.entries.lock().unwrap().get(file_id).is_some_and(|e| e.pending_delete.is_some())
+    }
+
+    fn defer_delete(&self, metadata: FileMetadata) -> Option<FileMetadata> {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get_mut(&metadata.file_id) {
+            Some(entry) if entry.readers > 0 => {
+                info!("⏳ delete_deferred | {} | readers={}", metadata.file_id, entry.readers);
+                entry.pending_delete = Some(metadata);
+                None
+            }
+            _ => Some(metadata),
+        }
+    }
+}
+
+impl ReadGuard {
+    fn begin_download(&mut self, limit: Option<usize>) -> Result<(), usize> {
+        let mut entries = self.tracker.entries.lock().unwrap();
+        let Some(entry) = entries.get_mut(&self.file_id) else {
+            return Ok(());
+        };
+        if let Some(limit) = limit.filter(|limit| entry.downloads >= *limit) {
+            return Err(limit);
+        }
+        entry.downloads += 1;
+        self.download = true;
+        Ok(())
+    }
+}
+
+impl Drop for ReadGuard {
+    fn drop(&mut self) {
+        let mut entries = self.tracker.entries.lock().unwrap();
+        let Some(entry) = entries.get_mut(&self.file_id) else {
+            return;
         };
-        for file_id in to_delete {
-            delete_file(&state, &file_id).await;
+        entry.readers -= 1;
+        if self.download {
+            entry.downloads -= 1;
+        }
+        if entry.readers > 0 {
+            return;
+        }
+        if let Some(metadata) = entries.remove(&self.file_id).and_then(|e| e.pending_delete) {
+            tokio::spawn(async move { unlink_files(&metadata).await });
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct FileMetadata {
+    file_id: String,
+    original_name: String,
+    disk_path: String,
+    mime_type: String,
+    guessed_mime: String,
+    size: u64,
+    uploaded_at: i64,
+    expires_at: i64,
+    owner: String,
+    request_id: String,
+    width: Option<u32>,
+    height: Option<u32>,
+    thumb_path: Option<String>,
+    checksum: String,
+    exif_stripped: bool,
+    #[serde(default)]
+    encodings: Vec<String>,
+    #[serde(default)]
+    password_hash: Option<String>,
+    #[serde(default)]
+    public: bool,
+    #[serde(default)]
+    download_name: Option<String>,
+    #[serde(default)]
+    worm: bool,
+    #[serde(default)]
+    download_count: u64,
+    #[serde(default)]
+    last_accessed: Option<i64>,
+    #[serde(default)]
+    description: Option<String>,
+    #[serde(default)]
+    bind_ip: bool,
+    #[serde(default)]
+    max_concurrent: Option<usize>,
+    #[serde(default)]
+    transcodes: Vec<String>,
+    #[serde(default)]
+    client_name: Option<String>,
+    #[serde(default)]
+    charset: Option<String>,
+    #[serde(default)]
+    group_id: Option<String>,
+    #[serde(default)]
+    group_position: usize,
+}
+
+#[derive(Debug)]
+enum Registry {
+    Memory(RwLock<HashMap<String, FileMetadata>>),
+    Sqlite(Arc<std::sync::Mutex<rusqlite::Connection>>),
+}
+
+impl Registry {
+    fn open(config: &Config) -> Result<Self, Box<dyn std::error::Error>> {
+        match config.metadata_backend {
+            MetadataBackend::Memory => Ok(Registry::Memory(RwLock::new(HashMap::new()))),
+            MetadataBackend::Sqlite => {
+                let conn = rusqlite::Connection::open(&config.metadata_path)?;
+                conn.execute_batch(
+                    "PRAGMA journal_mode = WAL;
+                     PRAGMA synchronous = NORMAL;
+                     CREATE TABLE IF NOT EXISTS files (
+                         file_id TEXT PRIMARY KEY,
+                         owner TEXT NOT NULL,
+                         uploaded_at INTEGER NOT NULL,
+                         expires_at INTEGER NOT NULL,
+                         metadata TEXT NOT NULL
+                     );
+                     CREATE INDEX IF NOT EXISTS idx_files_owner ON files (owner, uploaded_at);
+                     CREATE INDEX IF NOT EXISTS idx_files_expires ON files (expires_at);
+                     CREATE INDEX IF NOT EXISTS idx_files_uploaded ON files (uploaded_at);
+                     CREATE INDEX IF NOT EXISTS idx_files_checksum ON files (json_extract(metadata, '$.checksum'), uploaded_at);
+                     CREATE INDEX IF NOT EXISTS idx_files_group ON files (json_extract(metadata, '$.group_id'), uploaded_at);",
+                )?;
+                info!("🗄️ sqlite registry | {}", config.metadata_path);
+                Ok(Registry::Sqlite(Arc::new(std::sync::Mutex::new(conn))))
+            }
+        }
+    }
+
+    async fn insert(&self, metadata: FileMetadata) -> Result<(), String> {
+        match self {
+            Registry::Memory(map) => {
+                map.write().await.insert(metadata.file_id.clone(), metadata);
+                Ok(())
+            }
+            Registry::Sqlite(conn) => {
+                let json = serde_json::to_string(&metadata).map_err(|e| e.to_string())?;
+                with_db(conn, move |db| {
+                    db.execute(
+                        "INSERT OR REPLACE INTO files (file_id, owner, uploaded_at, expires_at, metadata)
+                         VALUES (?1, ?2, ?3, ?4, ?5)",
+                        rusqlite::params![metadata.file_id, metadata.owner, metadata.uploaded_at, metadata.expires_at, json],
+                    )
+                    .map(|_| ())
+                })
+                .await
+            }
+        }
+    }
+
+    async fn get(&self, file_id: &str) -> Option<FileMetadata> {
+        match self {
+            Registry::Memory(map) => map.read().await.get(file_id).cloned(),
+            Registry::Sqlite(conn) => {
+                let id = file_id.to_string();
+                let rows = with_db(conn, move |db| {
+                    query_metadata(db, "SELECT metadata FROM files WHERE file_id = ?1", rusqlite::params![id])
+                })
+                .await;
+                log_registry_error(rows).into_iter().next()
+            }
+        }
+    }
+
+    async fn remove(&self, file_id: &str) -> Option<FileMetadata> {
+        match self {
+            Registry::Memory(map) => map.write().await.remove(file_id),
+            Registry::Sqlite(conn) => {
+                let id = file_id.to_string();
+                let rows = with_db(conn, move |db| {
+                    let rows = query_metadata(db, "SELECT metadata FROM files WHERE file_id = ?1", rusqlite::params![id])?;
+                    db.execute("DELETE FROM files WHERE file_id = ?1", rusqlite::params![id])?;
+                    Ok(rows)
+                })
+                .await;
+                log_registry_error(rows).into_iter().next()
+            }
+        }
+    }
+
+    async fn update<T, F>(&self, file_id: &str, apply: F) -> Option<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut FileMetadata) -> T + Send + 'static,
+    {
+        match self {
+            Registry::Memory(map) => map.write().await.get_mut(file_id).map(apply),
+            Registry::Sqlite(conn) => {
+                let id = file_id.to_string();
+                let result = with_db(conn, move |db| {
+                    let tx = db.unchecked_transaction()?;
+                    let Some(mut metadata) = query_metadata(&tx, "SELECT metadata FROM files WHERE file_id = ?1", rusqlite::params![id])?
+                        .into_iter()
+                        .next()
+                    else {
+                        return Ok(None);
+                    };
+                    let value = apply(&mut metadata);
+                    let json = serde_json::to_string(&metadata)
+                        .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+                    tx.execute(
+                        "UPDATE files SET owner = ?2, uploaded_at = ?3, expires_at = ?4, metadata = ?5 WHERE file_id = ?1",
+                        rusqlite::params![id, metadata.owner, metadata.uploaded_at, metadata.expires_at, json],
+                    )?;
+                    tx.commit()?;
+                    Ok(Some(value))
+                })
+                .await;
+                log_registry_error(result.map(|v| v.into_iter().collect())).into_iter().next()
+            }
+        }
+    }
+
+    async fn all(&self) -> Vec<FileMetadata> {
+        match self {
+            Registry::Memory(map) => map.read().await.values().cloned().collect(),
+            Registry::Sqlite(conn) => {
+                let rows = with_db(conn, |db| query_metadata(db, "SELECT metadata FROM files", [])).await;
+                log_registry_error(rows)
+            }
+        }
+    }
+
+    async fn len(&self) -> usize {
+        match self {
+            Registry::Memory(map) => map.read().await.len(),
+            Registry::Sqlite(conn) => {
+                let count = with_db(conn, |db| {
+                    db.query_row("SELECT COUNT(*) FROM files", [], |row| row.get::<_, i64>(0))
+                })
+                .await;
+                log_registry_error(count.map(|c| vec![c as usize])).into_iter().next().unwrap_or(0)
+            }
+        }
+    }
+
+    async fn oldest(&self, count: usize) -> Vec<String> {
+        match self {
+            Registry::Memory(map) => {
+                let registry = map.read().await;
+                let mut entries: Vec<&FileMetadata> = registry.values().filter(|m| !m.worm).collect();
+                entries.sort_by_key(|m| m.uploaded_at);
+                entries.into_iter().take(count).map(|m| m.file_id.clone()).collect()
+            }
+            Registry::Sqlite(conn) => {
+                let ids = with_db(conn, move |db| {
+                    let mut stmt = db.prepare_cached("SELECT file_id FROM files WHERE NOT COALESCE(json_extract(metadata, '$.worm'), 0) ORDER BY uploaded_at LIMIT ?1")?;
+                    let ids = stmt.query_map(rusqlite::params![count as i64], |row| row.get(0))?;
+                    ids.collect()
+                })
+                .await;
+                log_registry_error(ids)
+            }
+        }
+    }
+
+    async fn find_by_checksum(&self, checksum: &str, now: i64) -> Vec<FileMetadata> {
+        match self {
+            Registry::Memory(map) => {
+                let mut matches: Vec<FileMetadata> = map
+                    .read()
+                    .await
+                    .values()
+                    .filter(|m| m.checksum == checksum && m.expires_at > now)
+                    .cloned()
+                    .collect();
+                matches.sort_by(|a, b| (a.uploaded_at, &a.file_id).cmp(&(b.uploaded_at, &b.file_id)));
+                matches
+            }
+            Registry::Sqlite(conn) => {
+                let checksum = checksum.to_string();
+                let rows = with_db(conn, move |db| {
+                    query_metadata(
+                        db,
+                        "SELECT metadata FROM files WHERE json_extract(metadata, '$.checksum') = ?1 AND expires_at > ?2 ORDER BY uploaded_at, file_id",
+                        rusqlite::params![checksum, now],
+                    )
+                })
+                .await;
+                log_registry_error(rows)
+            }
+        }
+    }
+
+    async fn find_by_group(&self, group_id: &str, now: i64) -> Vec<FileMetadata> {
+        match self {
+            Registry::Memory(map) => {
+                let mut members: Vec<FileMetadata> = map
+                    .read()
+                    .await
+                    .values()
+                    .filter(|m| m.group_id.as_deref() == Some(group_id) && m.expires_at > now)
+                    .cloned()
+                    .collect();
+                members.sort_by_key(|m| m.group_position);
+                members
+            }
+            Registry::Sqlite(conn) => {
+                let group_id = group_id.to_string();
+                let rows = with_db(conn, move |db| {
+                    query_metadata(
+                        db,
+                        "SELECT metadata FROM files WHERE json_extract(metadata, '$.group_id') = ?1 AND expires_at > ?2 ORDER BY json_extract(metadata, '$.group_position')",
+                        rusqlite::params![group_id, now],
+                    )
+                })
+                .await;
+                log_registry_error(rows)
+            }
+        }
+    }
+
+    async fn expired(&self, now: i64) -> Vec<String> {
+        match self {
+            Registry::Memory(map) => map
+                .read()
+                .await
+                .values()
+                .filter(|m| m.expires_at <= now)
+                .map(|m| m.file_id.clone())
+                .collect(),
+            Registry::Sqlite(conn) => {
+                let ids = with_db(conn, move |db| {
+                    let mut stmt = db.prepare_cached("SELECT file_id FROM files WHERE expires_at <= ?1")?;
+                    let ids = stmt.query_map(rusqlite::params![now], |row| row.get(0))?;
+                    ids.collect()
+                })
+                .await;
+                log_registry_error(ids)
+            }
+        }
+    }
+}
+
+async fn with_db<T, F>(conn: &Arc<std::sync::Mutex<rusqlite::Connection>>, run: F) -> Result<T, String>
+where
+    T: Send + 'static,
+    F: FnOnce(&rusqlite::Connection) -> rusqlite::Result<T> + Send + 'static,
+{
+    let conn = conn.clone();
+    tokio::task::spawn_blocking(move || {
+        let db = conn.lock().map_err(|_| "registry mutex poisoned".to_string())?;
+        run(&db).map_err(|e| e.to_string())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+fn query_metadata(
+    db: &rusqlite::Connection,
+    sql: &str,
+    params: impl rusqlite::Params,
+) -> rusqlite::Result<Vec<FileMetadata>> {
+    let mut stmt = db.prepare_cached(sql)?;
+    let rows = stmt.query_map(params, |row| row.get::<_, String>(0))?;
+    let mut out = Vec::new();
+    for json in rows {
+        match serde_json::from_str(&json?) {
+            Ok(metadata) => out.push(metadata),
+            Err(e) => warn!("⚠️ registry_decode_failed | {}", e),
+        }
+    }
+    Ok(out)
+}
+
+fn log_registry_error<T>(result: Result<Vec<T>, String>) -> Vec<T> {
+    result.unwrap_or_else(|e| {
+        error!("❌ registry_failed | {}", e);
+        Vec::new()
+    })
+}
+
+#[derive(Debug, Clone, Copy)]
+struct LinkOptions<'a> {
+    mode: &'a str,
+    expires_at: i64,
+    bound_ip: Option<IpAddr>,
+    max_uses: Option<u64>,
+    nonce: Option<&'a str>,
+}
+
+impl<'a> LinkOptions<'a> {
+    fn new(mode: &'a str, expires_at: i64) -> Self {
+        Self { mode, expires_at, bound_ip: None, max_uses: None, nonce: None }
+    }
+
+    fn with_ip(mut self, bound_ip: Option<IpAddr>) -> Self {
+        self.bound_ip = bound_ip;
+        self
+    }
+
+    fn with_uses(mut self, max_uses: Option<u64>) -> Self {
+        self.max_uses = max_uses;
+        self
+    }
+
+    fn with_nonce(mut self, nonce: Option<&'a str>) -> Self {
+        self.nonce = nonce;
+        self
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SignedUrlParams {
+    #[serde(rename = "sz-version")]
+    version: String,
+    #[serde(rename = "sz-owner")]
+    owner: String,
+    #[serde(rename = "sz-date")]
+    date: String,
+    #[serde(rename = "sz-expires")]
+    expires: String,
+    #[serde(rename = "sz-region")]
+    region: String,
+    #[serde(rename = "sz-mode")]
+    mode: String,
+    #[serde(rename = "sz-type")]
+    file_type: String,
+    #[serde(rename = "sz-id")]
+    id: String,
+    #[serde(rename = "sz-nonce")]
+    nonce: String,
+    #[serde(rename = "sz-ip", default, skip_serializing_if = "Option::is_none")]
+    ip: Option<String>,
+    #[serde(rename = "sz-uses", default, skip_serializing_if = "Option::is_none")]
+    uses: Option<String>,
+    #[serde(rename = "sz-signature")]
+    signature: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct UploadResponse {
+    id: String,
+    name: String,
+    size: u64,
+    mime: String,
+    width: Option<u32>,
+    height: Option<u32>,
+    view: String,
+    download: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    share: Option<String>,
+    ttl: u64,
+    uploaded_at: i64,
+    expires_at: i64,
+    checksum: String,
+    owner: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    delete_token: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct PipeResponse {
+    id: String,
+    url: String,
+    expires_in: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct PipeResult {
+    id: String,
+    size: u64,
+    delivered: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file: Option<UploadResponse>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ExtendRequest {
+    seconds: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct ExistsResponse {
+    exists: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expires_at: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+struct ExtendResponse {
+    id: String,
+    expires_at: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct SignRequest {
+    file_id: String,
+    #[serde(default = "default_sign_mode")]
+    mode: String,
+    #[serde(default)]
+    ip: Option<IpAddr>,
+    #[serde(default)]
+    uses: Option<u64>,
+    #[serde(default)]
+    url: Option<String>,
+}
+
+fn default_sign_mode() -> String {
+    "inline".to_string()
+}
+
+#[derive(Debug, Serialize)]
+struct SignResult {
+    file_id: String,
+    status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<ErrorResponse>,
+}
+
+#[derive(Debug, Serialize)]
+struct SignBatchResponse {
+    results: Vec<SignResult>,
+}
+
+#[derive(Debug, Serialize)]
+struct FileMetaResponse {
+    id: String,
+    name: String,
+    size: u64,
+    mime: String,
+    width: Option<u32>,
+    height: Option<u32>,
+    checksum: String,
+    uploaded_at: i64,
+    expires_at: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    download_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    download_count: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct VerifyResponse {
+    valid: bool,
+    expected_signature: String,
+    provided_signature: String,
+    string_to_sign: String,
+}
+
+#[derive(Debug, Serialize)]
+struct StatsResponse {
+    total_files: usize,
+    total_bytes: u64,
+    bytes_per_owner: HashMap<String, u64>,
+    files_per_owner: HashMap<String, usize>,
+    expiring_next_minute: usize,
+    oldest_upload: Option<i64>,
+    newest_upload: Option<i64>,
+    free_disk_bytes: Option<u64>,
+    pool_free_bytes: HashMap<String, Option<u64>>,
+    stored_bytes: u64,
+    max_total_bytes: Option<u64>,
+    max_files_per_owner: Option<usize>,
+    signature_blocked_ips: usize,
+    open_connections: Option<usize>,
+    refused_connections: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ErrorCode {
+    ShuttingDown,
+    FileTooLarge,
+    FileCreateFailed,
+    WriteFailed,
+    FlushFailed,
+    InvalidMultipart,
+    ChunkReadFailed,
+    InvalidContentType,
+    MimeNotAllowed,
+    ExtensionNotAllowed,
+    NoFiles,
+    ExportTooLarge,
+    BodyTooLarge,
+    Infected,
+    ChecksumMismatch,
+    ScanFailed,
+    RegistryFailed,
+    FileNotFound,
+    ReadFailed,
+    QrFailed,
+    ThumbnailUnavailable,
+    InvalidBody,
+    Unauthorized,
+    InvalidApiKey,
+    InvalidToken,
+    TokenExpired,
+    NotOwner,
+    InvalidMode,
+    InvalidSignature,
+    InvalidExpires,
+    UnsupportedVersion,
+    LinkExpired,
+    LinkExhausted,
+    FileExpired,
+    InvalidUses,
+    InvalidConcurrency,
+    DownloadBusy,
+    InvalidFormat,
+    IdMismatch,
+    OwnerMismatch,
+    IpMismatch,
+    TypeMismatch,
+    RefererNotAllowed,
+    InvalidTtl,
+    InvalidExpiresAt,
+    InvalidSize,
+    DuplicateField,
+    ConflictingField,
+    PasswordRequired,
+    InvalidPassword,
+    RateLimited,
+    SignatureBlocked,
+    PasswordBlocked,
+    OwnerFileLimit,
+    InvalidChecksum,
+    InvalidIdempotencyKey,
+    IdempotencyInFlight,
+    StorageUnavailable,
+    InsufficientStorage,
+    PublicDisabled,
+    MultipleFiles,
+    MissingFile,
+    FileTooSmall,
+    TooManyParts,
+    FieldTooLarge,
+    FieldsTooLarge,
+    InvalidArchive,
+    TooManyEntries,
+    ArchiveTooLarge,
+    StorageCapReached,
+    BatchTooLarge,
+    ExpectationFailed,
+    UpgradeRequired,
+    PipeBusy,
+    PipeClosed,
+    PipeTimeout,
+    UploadTimeout,
+    NotFound,
+    MethodNotAllowed,
+    WormDisabled,
+    WormProtected,
+    InvalidClientId,
+    DescriptionTooLong,
+    RangeNotSatisfiable,
+    MissingParameter(&'static str),
+    InvalidParameter(&'static str),
+    UnexpectedParameter,
+    ParameterTooLong(&'static str),
+    QueryTooLong,
+    ClassTooLarge(&'static str),
+}
+
+impl ErrorCode {
+    fn as_str(&self) -> std::borrow::Cow<'static, str> {
+        match self {
+            ErrorCode::ShuttingDown => "shutting_down".into(),
+            ErrorCode::FileTooLarge => "file_too_large".into(),
+            ErrorCode::FileCreateFailed => "file_create_failed".into(),
+            ErrorCode::WriteFailed => "write_failed".into(),
+            ErrorCode::FlushFailed => "flush_failed".into(),
+            ErrorCode::InvalidMultipart => "invalid_multipart".into(),
+            ErrorCode::ChunkReadFailed => "chunk_read_failed".into(),
+            ErrorCode::InvalidContentType => "invalid_content_type".into(),
+            ErrorCode::MimeNotAllowed => "mime_not_allowed".into(),
+            ErrorCode::ExtensionNotAllowed => "extension_not_allowed".into(),
+            ErrorCode::NoFiles => "no_files".into(),
+            ErrorCode::ExportTooLarge => "export_too_large".into(),
+            ErrorCode::BodyTooLarge => "body_too_large".into(),
+            ErrorCode::Infected => "infected".into(),
+            ErrorCode::ChecksumMismatch => "checksum_mismatch".into(),
+            ErrorCode::ScanFailed => "scan_failed".into(),
+            ErrorCode::RegistryFailed => "registry_failed".into(),
+            ErrorCode::FileNotFound => "file_not_found".into(),
+            ErrorCode::ReadFailed => "read_failed".into(),
+            ErrorCode::QrFailed => "qr_failed".into(),
+            ErrorCode::ThumbnailUnavailable => "thumbnail_unavailable".into(),
+            ErrorCode::InvalidBody => "invalid_body".into(),
+            ErrorCode::Unauthorized => "unauthorized".into(),
+            ErrorCode::InvalidApiKey => "invalid_api_key".into(),
+            ErrorCode::InvalidToken => "invalid_token".into(),
+            ErrorCode::TokenExpired => "token_expired".into(),
+            ErrorCode::NotOwner => "not_owner".into(),
+            ErrorCode::InvalidMode => "invalid_mode".into(),
+            ErrorCode::InvalidSignature => "invalid_signature".into(),
+            ErrorCode::InvalidExpires => "invalid_expires".into(),
+            ErrorCode::UnsupportedVersion => "unsupported_version".into(),
+            ErrorCode::LinkExpired => "link_expired".into(),
+            ErrorCode::LinkExhausted => "link_exhausted".into(),
+            ErrorCode::FileExpired => "file_expired".into(),
+            ErrorCode::InvalidUses => "invalid_uses".into(),
+            ErrorCode::InvalidConcurrency => "invalid_max_concurrent".into(),
+            ErrorCode::DownloadBusy => "download_busy".into(),
+            ErrorCode::InvalidFormat => "invalid_format".into(),
+            ErrorCode::IdMismatch => "id_mismatch".into(),
+            ErrorCode::OwnerMismatch => "owner_mismatch".into(),
+            ErrorCode::IpMismatch => "ip_mismatch".into(),
+            ErrorCode::TypeMismatch => "type_mismatch".into(),
+            ErrorCode::RefererNotAllowed => "referer_not_allowed".into(),
+            ErrorCode::InvalidTtl => "invalid_ttl".into(),
+            ErrorCode::InvalidExpiresAt => "invalid_expires_at".into(),
+            ErrorCode::InvalidSize => "invalid_size".into(),
+            ErrorCode::DuplicateField => "duplicate_field".into(),
+            ErrorCode::ConflictingField => "conflicting_field".into(),
+            ErrorCode::PasswordRequired => "password_required".into(),
+            ErrorCode::InvalidPassword => "invalid_password".into(),
+            ErrorCode::RateLimited => "rate_limited".into(),
+            ErrorCode::SignatureBlocked => "signature_blocked".into(),
+            ErrorCode::PasswordBlocked => "password_blocked".into(),
+            ErrorCode::OwnerFileLimit => "owner_file_limit".into(),
+            ErrorCode::InvalidChecksum => "invalid_checksum".into(),
+            ErrorCode::InvalidIdempotencyKey => "invalid_idempotency_key".into(),
+            ErrorCode::IdempotencyInFlight => "idempotency_in_flight".into(),
+            ErrorCode::StorageUnavailable => "storage_unavailable".into(),
+            ErrorCode::InsufficientStorage => "insufficient_storage".into(),
+            ErrorCode::PublicDisabled => "public_disabled".into(),
+            ErrorCode::MultipleFiles => "multiple_files".into(),
+            ErrorCode::MissingFile => "missing_file".into(),
+            ErrorCode::FileTooSmall => "file_too_small".into(),
+            ErrorCode::TooManyParts => "too_many_parts".into(),
+            ErrorCode::FieldTooLarge => "field_too_large".into(),
+            ErrorCode::FieldsTooLarge => "fields_too_large".into(),
+            ErrorCode::InvalidArchive => "invalid_archive".into(),
+            ErrorCode::TooManyEntries => "too_many_entries".into(),
+            ErrorCode::ArchiveTooLarge => "archive_too_large".into(),
+            ErrorCode::StorageCapReached => "storage_cap_reached".into(),
+            ErrorCode::BatchTooLarge => "batch_too_large".into(),
+            ErrorCode::ExpectationFailed => "expectation_failed".into(),
+            ErrorCode::UpgradeRequired => "upgrade_required".into(),
+            ErrorCode::PipeBusy => "pipe_busy".into(),
+            ErrorCode::PipeClosed => "pipe_closed".into(),
+            ErrorCode::PipeTimeout => "pipe_timeout".into(),
+            ErrorCode::UploadTimeout => "upload_timeout".into(),
+            ErrorCode::NotFound => "not_found".into(),
+            ErrorCode::MethodNotAllowed => "method_not_allowed".into(),
+            ErrorCode::WormDisabled => "worm_disabled".into(),
+            ErrorCode::WormProtected => "worm_protected".into(),
+            ErrorCode::InvalidClientId => "invalid_client_id".into(),
+            ErrorCode::DescriptionTooLong => "description_too_long".into(),
+            ErrorCode::RangeNotSatisfiable => "range_not_satisfiable".into(),
+            ErrorCode::MissingParameter(key) => format!("missing_{}", key).into(),
+            ErrorCode::InvalidParameter(key) => format!("invalid_{}", key).into(),
+            ErrorCode::UnexpectedParameter => "unexpected_parameter".into(),
+            ErrorCode::ParameterTooLong(_) => "parameter_too_long".into(),
+            ErrorCode::QueryTooLong => "query_too_long".into(),
+            ErrorCode::ClassTooLarge(class) => format!("{}_too_large", class).into(),
+        }
+    }
+
+    fn status(&self) -> StatusCode {
+        match self {
+            ErrorCode::InvalidMultipart
+            | ErrorCode::ChunkReadFailed
+            | ErrorCode::InvalidContentType
+            | ErrorCode::InvalidBody
+            | ErrorCode::InvalidMode
+            | ErrorCode::TooManyParts
+            | ErrorCode::BatchTooLarge
+            | ErrorCode::FieldTooLarge
+            | ErrorCode::FieldsTooLarge
+            | ErrorCode::InvalidArchive
+            | ErrorCode::TooManyEntries
+            | ErrorCode::MultipleFiles
+            | ErrorCode::MissingFile
+            | ErrorCode::FileTooSmall
+            | ErrorCode::InvalidChecksum
+            | ErrorCode::InvalidIdempotencyKey
+            | ErrorCode::InvalidTtl
+            | ErrorCode::InvalidExpiresAt
+            | ErrorCode::InvalidSize
+            | ErrorCode::DuplicateField
+            | ErrorCode::ConflictingField
+            | ErrorCode::InvalidExpires
+            | ErrorCode::UnsupportedVersion
+            | ErrorCode::InvalidClientId
+            | ErrorCode::DescriptionTooLong
+            | ErrorCode::InvalidUses
+            | ErrorCode::InvalidConcurrency
+            | ErrorCode::InvalidFormat
+            | ErrorCode::MissingParameter(_)
+            | ErrorCode::InvalidParameter(_)
+            | ErrorCode::UnexpectedParameter
+            | ErrorCode::ParameterTooLong(_)
+            | ErrorCode::QueryTooLong => StatusCode::BAD_REQUEST,
+            ErrorCode::Unauthorized
+            | ErrorCode::InvalidApiKey
+            | ErrorCode::InvalidToken
+            | ErrorCode::TokenExpired
+            | ErrorCode::PasswordRequired
+            | ErrorCode::InvalidPassword => StatusCode::UNAUTHORIZED,
+            ErrorCode::PublicDisabled
+            | ErrorCode::WormDisabled
+            | ErrorCode::WormProtected
+            | ErrorCode::NotOwner
+            | ErrorCode::InvalidSignature
+            | ErrorCode::LinkExpired
+            | ErrorCode::IdMismatch
+            | ErrorCode::OwnerMismatch
+            | ErrorCode::IpMismatch
+            | ErrorCode::TypeMismatch
+            | ErrorCode::RefererNotAllowed => StatusCode::FORBIDDEN,
+            ErrorCode::FileNotFound
+            | ErrorCode::NotFound
+            | ErrorCode::ThumbnailUnavailable
+            | ErrorCode::NoFiles => StatusCode::NOT_FOUND,
+            ErrorCode::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
+            ErrorCode::ExpectationFailed => StatusCode::EXPECTATION_FAILED,
+            ErrorCode::UpgradeRequired => StatusCode::UPGRADE_REQUIRED,
+            ErrorCode::PipeBusy | ErrorCode::IdempotencyInFlight => StatusCode::CONFLICT,
+            ErrorCode::PipeClosed
+            | ErrorCode::LinkExhausted
+            | ErrorCode::FileExpired => StatusCode::GONE,
+            ErrorCode::PipeTimeout | ErrorCode::UploadTimeout => StatusCode::REQUEST_TIMEOUT,
+            ErrorCode::RangeNotSatisfiable => StatusCode::RANGE_NOT_SATISFIABLE,
+            ErrorCode::FileTooLarge
+            | ErrorCode::ClassTooLarge(_)
+            | ErrorCode::ExportTooLarge
+            | ErrorCode::ArchiveTooLarge
+            | ErrorCode::BodyTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            ErrorCode::MimeNotAllowed | ErrorCode::ExtensionNotAllowed => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ErrorCode::Infected
+            | ErrorCode::ChecksumMismatch => StatusCode::UNPROCESSABLE_ENTITY,
+            ErrorCode::FileCreateFailed
+            | ErrorCode::WriteFailed
+            | ErrorCode::FlushFailed
+            | ErrorCode::RegistryFailed
+            | ErrorCode::ReadFailed
+            | ErrorCode::QrFailed => StatusCode::INTERNAL_SERVER_ERROR,
+            ErrorCode::ShuttingDown
+            | ErrorCode::ScanFailed
+            | ErrorCode::StorageUnavailable
+            | ErrorCode::DownloadBusy => StatusCode::SERVICE_UNAVAILABLE,
+            ErrorCode::InsufficientStorage
+            | ErrorCode::StorageCapReached => StatusCode::INSUFFICIENT_STORAGE,
+            ErrorCode::RateLimited
+            | ErrorCode::SignatureBlocked
+            | ErrorCode::PasswordBlocked
+            | ErrorCode::OwnerFileLimit => StatusCode::TOO_MANY_REQUESTS,
+        }
+    }
+
+    fn message(&self) -> std::borrow::Cow<'static, str> {
+        match self {
+            ErrorCode::ShuttingDown => "Server is shutting down, retry later".into(),
+            ErrorCode::FileTooLarge => "File exceeds the maximum upload size".into(),
+            ErrorCode::FileCreateFailed => "Could not create the file on disk".into(),
+            ErrorCode::WriteFailed => "Could not write the upload to disk".into(),
+            ErrorCode::FlushFailed => "Could not flush the upload to disk".into(),
+            ErrorCode::InvalidMultipart => "Malformed multipart body".into(),
+            ErrorCode::ChunkReadFailed => "Upload stream was interrupted".into(),
+            ErrorCode::InvalidContentType => "Declared content type is not a valid MIME type".into(),
+            ErrorCode::MimeNotAllowed => "File type is not allowed".into(),
+            ErrorCode::ExtensionNotAllowed => "File extension is not allowed".into(),
+            ErrorCode::NoFiles => "Owner has no live files".into(),
+            ErrorCode::ExportTooLarge => "Files exceed the maximum export size".into(),
+            ErrorCode::BodyTooLarge => "Request body exceeds the maximum JSON size".into(),
+            ErrorCode::Infected => "File was rejected by the virus scanner".into(),
+            ErrorCode::ChecksumMismatch => "Uploaded content does not match X-Expected-Sha256".into(),
+            ErrorCode::ScanFailed => "Virus scan could not be completed".into(),
+            ErrorCode::RegistryFailed => "Could not record the upload".into(),
+            ErrorCode::FileNotFound => "File does not exist or has expired".into(),
+            ErrorCode::ReadFailed => "Could not read the file".into(),
+            ErrorCode::QrFailed => "Could not generate the QR code".into(),
+            ErrorCode::ThumbnailUnavailable => "No thumbnail is available for this file".into(),
+            ErrorCode::InvalidBody => "Request body is not valid JSON for this endpoint".into(),
+            ErrorCode::Unauthorized => "Missing or invalid credentials".into(),
+            ErrorCode::InvalidApiKey => "API key is not recognised".into(),
+            ErrorCode::InvalidToken => "Bearer token is invalid".into(),
+            ErrorCode::TokenExpired => "Bearer token has expired".into(),
+            ErrorCode::NotOwner => "File belongs to a different owner".into(),
+            ErrorCode::InvalidMode => "Link mode must be inline or attachment".into(),
+            ErrorCode::InvalidSignature => "Link signature is invalid".into(),
+            ErrorCode::InvalidExpires => "Link expiry is not a valid timestamp".into(),
+            ErrorCode::UnsupportedVersion => "Unknown sz-version".into(),
+            ErrorCode::LinkExpired => "Link has expired".into(),
+            ErrorCode::LinkExhausted => "Link has been used the maximum number of times".into(),
+            ErrorCode::FileExpired => "File has expired and will be removed shortly".into(),
+            ErrorCode::InvalidUses => "Link uses must be a positive whole number".into(),
+            ErrorCode::InvalidConcurrency => "max_concurrent must be a positive whole number".into(),
+            ErrorCode::DownloadBusy => "Too many downloads of this file are in progress".into(),
+            ErrorCode::InvalidFormat => "Requested format is not supported".into(),
+            ErrorCode::IdMismatch => "Link was issued for a different file".into(),
+            ErrorCode::OwnerMismatch => "Link was issued for a different owner".into(),
+            ErrorCode::IpMismatch => "Link is bound to a different client IP".into(),
+            ErrorCode::TypeMismatch => "Link was issued for a different content type".into(),
+            ErrorCode::RefererNotAllowed => "Embedding this file from that site is not allowed".into(),
+            ErrorCode::InvalidTtl => "TTL must be a positive number of seconds within the maximum lifetime".into(),
+            ErrorCode::InvalidExpiresAt => "Expiry must be a unix timestamp in the future".into(),
+            ErrorCode::InvalidSize => "Declared size must be a whole number of bytes".into(),
+            ErrorCode::DuplicateField => "A metadata field was sent more than once".into(),
+            ErrorCode::ConflictingField => "A metadata field conflicts with the request headers".into(),
+            ErrorCode::PasswordRequired => "This file is password protected".into(),
+            ErrorCode::InvalidPassword => "Password is incorrect".into(),
+            ErrorCode::RateLimited => "Too many requests, retry later".into(),
+            ErrorCode::SignatureBlocked => "Too many invalid signatures from this address, retry later".into(),
+            ErrorCode::PasswordBlocked => "Too many wrong passwords for this file from this address, retry later".into(),
+            ErrorCode::OwnerFileLimit => "Owner has too many live files".into(),
+            ErrorCode::InvalidChecksum => "Checksum must be 64 hex characters".into(),
+            ErrorCode::InvalidIdempotencyKey => "Idempotency-Key must be 1 to 255 visible ASCII characters".into(),
+            ErrorCode::IdempotencyInFlight => "An upload with this Idempotency-Key is still in progress".into(),
+            ErrorCode::StorageUnavailable => "Upload storage is not writable".into(),
+            ErrorCode::InsufficientStorage => "All storage pools are full".into(),
+            ErrorCode::PublicDisabled => "Public uploads are not enabled on this server".into(),
+            ErrorCode::MultipleFiles => "Only one file part is accepted per upload".into(),
+            ErrorCode::MissingFile => "The upload contained no file part".into(),
+            ErrorCode::FileTooSmall => "File is smaller than the minimum allowed size".into(),
+            ErrorCode::TooManyParts => "The multipart body has too many parts".into(),
+            ErrorCode::FieldTooLarge => "A form field exceeds the maximum field size".into(),
+            ErrorCode::FieldsTooLarge => "The form fields together exceed the maximum size".into(),
+            ErrorCode::InvalidArchive => "The archive is malformed or contains an unsafe path".into(),
+            ErrorCode::TooManyEntries => "The archive contains too many files".into(),
+            ErrorCode::ArchiveTooLarge => "The archive expands beyond the maximum size".into(),
+            ErrorCode::StorageCapReached => "Server storage limit reached".into(),
+            ErrorCode::BatchTooLarge => "Too many entries in one batch".into(),
+            ErrorCode::ExpectationFailed => "Only Expect: 100-continue is supported".into(),
+            ErrorCode::UpgradeRequired => "This endpoint only speaks WebSocket".into(),
+            ErrorCode::PipeBusy => "The other side of this pipe is already connected".into(),
+            ErrorCode::PipeClosed => "The reader disconnected before the upload finished".into(),
+            ErrorCode::PipeTimeout => "The reader stopped consuming the pipe in time".into(),
+            ErrorCode::UploadTimeout => "The upload stopped sending data".into(),
+            ErrorCode::NotFound => "No route matches this path".into(),
+            ErrorCode::MethodNotAllowed => "This method is not allowed on this path".into(),
+            ErrorCode::WormDisabled => "WORM uploads are not enabled on this server".into(),
+            ErrorCode::WormProtected => "File is WORM protected until it expires".into(),
+            ErrorCode::InvalidClientId => "Client ID must be 1 to 128 letters, digits, '.', '_' or '-'".into(),
+            ErrorCode::DescriptionTooLong => "Description exceeds the maximum length".into(),
+            ErrorCode::RangeNotSatisfiable => "Requested range lies outside the file".into(),
+            ErrorCode::MissingParameter(key) => format!("Missing query parameter {}", key).into(),
+            ErrorCode::InvalidParameter(key) => format!("Query parameter {} is malformed", key).into(),
+            ErrorCode::UnexpectedParameter => "The link carries a sz- parameter its version does not sign".into(),
+            ErrorCode::ParameterTooLong(key) => format!("Query parameter {} is too long", key).into(),
+            ErrorCode::QueryTooLong => "Query string is too long".into(),
+            ErrorCode::ClassTooLarge(class) => format!("File exceeds the maximum size for {} uploads", class).into(),
+        }
+    }
+}
+
+impl Serialize for ErrorCode {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct ErrorResponse {
+    code: ErrorCode,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    details: Option<serde_json::Value>,
+}
+
+#[derive(Debug)]
+struct ApiError {
+    body: ErrorResponse,
+    retry_after: Option<u64>,
+}
+
+impl ApiError {
+    fn new(code: ErrorCode) -> Self {
+        Self {
+            body: ErrorResponse { code, message: code.message().into_owned(), details: None },
+            retry_after: None,
+        }
+    }
+
+    fn with_details(mut self, details: serde_json::Value) -> Self {
+        self.body.details = Some(details);
+        self
+    }
+
+    fn with_retry_after(mut self, seconds: u64) -> Self {
+        self.retry_after = Some(seconds.max(1));
+        self
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let mut response = (self.body.code.status(), Json(&self.body)).into_response();
+        if let Some(seconds) = self.retry_after {
+            response.headers_mut().insert(header::RETRY_AFTER, seconds.into());
+        }
+        response.extensions_mut().insert(self.body);
+        response
+    }
+}
+
+#[derive(Debug, clap::Parser)]
+#[command(name = "sptzx", about = "Temporary file CDN with signed links", version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, clap::Subcommand)]
+enum Command {
+    #[command(about = "Run the HTTP server (default)")]
+    Serve,
+    #[command(about = "Run one cleanup pass (expiry, storage cap, free-space floor) and exit")]
+    Gc,
+    #[command(about = "Drop registry entries missing on disk and delete files the registry does not know")]
+    Reconcile,
+    #[command(about = "Print storage stats as JSON")]
+    Stats,
+    #[command(about = "Re-hash stored files and report (or quarantine) checksum mismatches")]
+    Verify {
+        #[arg(long, help = "Only check this owner's files")]
+        owner: Option<String>,
+    },
+    #[command(about = "Move content-addressed files into the layout set by SPTZX_CONTENT_LAYOUT")]
+    MigrateLayout,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let command = <Cli as clap::Parser>::parse().command.unwrap_or(Command::Serve);
+    let fmt_layer = tracing_subscriber::fmt::layer()
+        .with_target(false)
+        .with_level(true);
+    let fmt_layer = if env::var("SPTZX_LOG_FORMAT").is_ok_and(|v| v == "json") {
+        fmt_layer.json().boxed()
+    } else {
+        fmt_layer.compact().boxed()
+    };
+    let otlp_endpoint = env::var("SPTZX_OTLP_ENDPOINT").ok().filter(|v| !v.is_empty());
+    #[cfg(feature = "otel")]
+    let (tracer_provider, otlp_error) = match otlp_endpoint.as_deref().map(otlp_tracer_provider) {
+        Some(Ok(provider)) => (Some(provider), None),
+        Some(Err(e)) => (None, Some(e.to_string())),
+        None => (None, None),
+    };
+    #[cfg(feature = "otel")]
+    let otel_layer = tracer_provider.as_ref().map(|provider| {
+        use opentelemetry::trace::TracerProvider as _;
+        tracing_opentelemetry::layer()
+            .with_tracer(provider.tracer("sptzx"))
+            .with_filter(filter_fn(|m| m.target().starts_with("sptzx") || m.target().starts_with("tower_http")))
+    });
+    #[cfg(not(feature = "otel"))]
+    let otel_layer = None::<tracing_subscriber::layer::Identity>;
+    tracing_subscriber::registry()
+        .with(fmt_layer.with_filter(filter_fn(|m| m.is_event() && *m.level() <= tracing::Level::INFO)))
+        .with(otel_layer)
+        .init();
+    #[cfg(feature = "otel")]
+    match (&otlp_endpoint, otlp_error) {
+        (_, Some(e)) => warn!("⚠️ otlp_init_failed | {}", e),
+        (Some(endpoint), None) => info!("🛰️ otlp_export | {}", endpoint),
+        (None, None) => {}
+    }
+    #[cfg(not(feature = "otel"))]
+    if otlp_endpoint.is_some() {
+        warn!("⚠️ otlp_unavailable | SPTZX_OTLP_ENDPOINT is set but this build lacks the otel feature");
+    }
+
+    let config = Config::from_env();
+    if let Err(e) = config.validate() {
+        error!("❌ invalid_config | {}", e);
+        return Err(e.into());
+    }
+    if command != Command::Serve && config.metadata_backend == MetadataBackend::Memory {
+        error!("❌ invalid_config | {:?} needs SPTZX_METADATA_BACKEND=sqlite, the memory registry only lives inside a running server", command);
+        return Err("maintenance commands need SPTZX_METADATA_BACKEND=sqlite".into());
+    }
+    for pattern in &config.inline_mime_types {
+        if FORCE_ATTACHMENT_MIME.contains(&pattern.as_str()) {
+            warn!("⚠️ inline_mime_ignored | {} is always served as an attachment", pattern);
+        }
+    }
+    if config.secret_key == DEFAULT_SECRET_KEY {
+        warn!("🚨 DEFAULT SECRET KEY IN USE | SPTZX_SECRET_KEY is unset, so anyone can forge signed URLs | set it, or SPTZX_ENV=production to refuse starting like this");
+    }
+    let config = Arc::new(config);
+
+    fs::create_dir_all(&config.upload_dir).await?;
+    for pool in &config.storage_pools {
+        fs::create_dir_all(pool).await?;
+    }
+
+    let (expiry_tx, expiry_rx) = mpsc::unbounded_channel();
+    let audit_tx = match &config.audit_log {
+        Some(path) => {
+            let file = fs::OpenOptions::new().create(true).append(true).open(path).await?;
+            let (tx, rx) = mpsc::unbounded_channel();
+            tokio::spawn(run_audit_log(file, rx));
+            info!("📒 audit_log | {}", path);
+            Some(tx)
+        }
+        None => None,
+    };
+    let fallback_image = match &config.fallback_image {
+        Some(path) => {
+            let mime_type = mime_guess::from_path(path).first_or_octet_stream();
+            if mime_type.type_() != mime_guess::mime::IMAGE {
+                error!("❌ invalid_config | SPTZX_FALLBACK_IMAGE {} is not an image ({})", path, mime_type);
+                return Err(format!("SPTZX_FALLBACK_IMAGE must be an image, got {}", mime_type).into());
+            }
+            let body = fs::read(path).await.map_err(|e| {
+                error!("❌ fallback_image_unreadable | {} | {}", path, e);
+                format!("failed to read SPTZX_FALLBACK_IMAGE: {}", e)
+            })?;
+            info!("🖼️ fallback_image | {} | {} bytes", path, body.len());
+            Some(Arc::new(FallbackImage {
+                mime_type: HeaderValue::from_str(mime_type.as_ref())?,
+                body: body.into(),
+            }))
+        }
+        None => None,
+    };
+    let state = AppState::new(config.clone(), expiry_tx, audit_tx, fallback_image)?;
+
+    match command {
+        Command::Serve => {}
+        Command::Gc => return run_gc(&state).await,
+        Command::Reconcile => return run_reconcile(&state).await,
+        Command::Verify { owner } => {
+            account_registry(&state).await;
+            let summary = run_verify_pass(&state, owner.as_deref()).await;
+            if summary.mismatched + summary.unreadable > 0 {
+                return Err(format!(
+                    "{} files failed verification ({} mismatched, {} unreadable)",
+                    summary.mismatched + summary.unreadable,
+                    summary.mismatched,
+                    summary.unreadable
+                )
+                .into());
+            }
+            return Ok(());
+        }
+        Command::MigrateLayout => return run_migrate_layout(&state).await,
+        Command::Stats => {
+            account_registry(&state).await;
+            println!("{}", serde_json::to_string_pretty(&collect_stats(&state).await)?);
+            return Ok(());
+        }
+    }
+
+    let app = build_router(&state);
+
+    let existing = state.file_registry.all().await;
+    tokio::spawn(reconcile_registry(state.clone(), existing));
+    tokio::spawn(run_expiry_timer(state.clone(), expiry_rx));
+    tokio::spawn(cleanup_expired_files(state.clone()));
+    tokio::spawn(evict_idle_buckets(state.clone()));
+    if config.verify_interval > 0 {
+        tokio::spawn(verify_files_periodically(state.clone()));
+    }
+    if let Some(verifier) = state.jwt.clone().filter(|v| v.jwks_url.is_some()) {
+        tokio::spawn(refresh_jwks(verifier));
+    }
+
+    let tls = match (&config.tls_cert, &config.tls_key) {
+        (Some(cert), Some(key)) => {
+            let _ = rustls::crypto::ring::default_provider().install_default();
+            let tls = RustlsConfig::from_pem_file(cert, key).await.map_err(|e| {
+                error!("❌ tls_load_failed | {} | {} | {}", cert, key, e);
+                format!("failed to load TLS certificate/key: {}", e)
+            })?;
+            Some(tls)
+        }
+        _ => None,
+    };
+
+    let (listener, bound) = bind_listener(config.bind_addr.parse()?, config.dual_stack, config.listen_backlog)?;
+    info!("🚀 Sptzx listening on {}{} | Workers: {} | Buffer: write {}KB / read {}KB | Max: {}MB | TTL: {}s | Cleanup: {}s", 
+        bound.join(" + "), 
+        if tls.is_some() { " (https)" } else { "" },
+        config.workers,
+        config.buffer_size / 1024,
+        config.read_buffer / 1024,
+        config.max_file_size / 1024 / 1024,
+        config.file_lifetime,
+        config.cleanup_interval
+    );
+    if !config.signing {
+        warn!("🚨 SIGNING DISABLED (SPTZX_SIGNING=off) | links are plain /file/:id URLs with no signature or expiry check | anyone who can reach this server can read any file");
+    }
+    
+    let handle = axum_server::Handle::new();
+    tokio::spawn({
+        let handle = handle.clone();
+        let state = state.clone();
+        async move {
+            shutdown_signal(state).await;
+            handle.graceful_shutdown(None);
+        }
+    });
+
+    let server = axum_server::from_tcp(listener.into_std()?).handle(handle);
+    let acceptor = ConnectionAcceptor {
+        limit: state.connections.clone(),
+        plaintext: tls.is_none(),
+        write_timeout: (config.download_timeout > 0).then(|| Duration::from_secs(config.download_timeout)),
+    };
+    match tls {
+        Some(tls) => {
+            tokio::spawn(reload_tls_on_sighup(tls.clone(), config.clone()));
+            server
+                .acceptor(RustlsAcceptor::new(tls).acceptor(acceptor))
+                .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+                .await?;
+        }
+        None => {
+            server
+                .acceptor(acceptor)
+                .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+                .await?;
+        }
+    }
+
+    info!("👋 Sptzx stopped");
+
+    #[cfg(feature = "otel")]
+    if let Some(provider) = tracer_provider {
+        if let Err(e) = provider.shutdown() {
+            warn!("⚠️ otlp_flush_failed | {}", e);
+        }
+    }
+
+    Ok(())
+}
+
+fn build_router(state: &AppState) -> Router {
+    let config = &state.config;
+    let app = Router::new()
+        .route("/", get(health_check).options(capabilities_handler))
+        .route("/live", get(liveness_check))
+        .route(
+            "/upload",
+            post(upload_handler)
+                .route_layer(middleware::from_fn_with_state(state.clone(), decode_upload_encoding))
+                .route_layer(middleware::from_fn(capture_trailers))
+                .route_layer(middleware::from_fn_with_state(state.clone(), advertise_upload_limit))
+                .route_layer(middleware::from_fn_with_state(state.clone(), reject_when_draining))
+                .options(capabilities_handler),
+        )
+        .route(
+            "/pipe",
+            post(create_pipe_handler).route_layer(middleware::from_fn_with_state(state.clone(), reject_when_draining)),
+        )
+        .route(
+            "/upload/archive",
+            post(archive_upload_handler).route_layer(middleware::from_fn_with_state(state.clone(), reject_when_draining)),
+        )
+        .route(
+            "/pipe/:id",
+            get(pipe_read_handler)
+                .merge(put(pipe_write_handler).route_layer(middleware::from_fn_with_state(state.clone(), reject_when_draining))),
+        )
+        .route(
+            "/group/:id",
+            get(group_handler).route_layer(middleware::from_fn_with_state(state.clone(), limit_signed_query)),
+        )
+        .route(
+            "/file/:id",
+            get(serve_file)
+                .route_layer(middleware::from_fn_with_state(state.clone(), limit_signed_query))
+                .merge(put(put_file_handler).route_layer(middleware::from_fn_with_state(state.clone(), reject_when_draining)))
+                .merge(delete(delete_file_handler)),
+        )
+        .route("/p/:id", get(serve_public))
+        .route(
+            "/view/:id",
+            get(viewer_handler).route_layer(middleware::from_fn_with_state(state.clone(), limit_signed_query)),
+        )
+        .route(
+            "/file/:id/thumb",
+            get(serve_thumbnail).route_layer(middleware::from_fn_with_state(state.clone(), limit_signed_query)),
+        )
+        .route(
+            "/file/:id/qr",
+            get(qr_handler).route_layer(middleware::from_fn_with_state(state.clone(), limit_signed_query)),
+        )
+        .route(
+            "/file/:id/meta",
+            get(file_meta_handler).route_layer(middleware::from_fn_with_state(state.clone(), limit_signed_query)),
+        )
+        .route("/file/:id/exists", get(exists_handler))
+        .route("/file/:id/extend", post(extend_handler).layer(DefaultBodyLimit::max(config.max_json_body)))
+        .route("/sign/batch", post(sign_batch_handler).layer(DefaultBodyLimit::max(config.max_json_body)))
+        .route("/export", get(export_handler))
+        .route("/stats", get(stats_handler))
+        .route("/metrics", get(metrics_handler))
+        .route("/admin/files", delete(purge_handler))
+        .route("/admin/config", get(config_handler))
+        .route("/events", get(events_handler))
+        .route("/verify", post(verify_handler).layer(DefaultBodyLimit::max(config.max_json_body)))
+        .route("/sha256/:hex", get(checksum_handler))
+        .fallback(not_found_handler)
+        .method_not_allowed_fallback(method_not_allowed_handler);
+    let app = if config.route_prefix.is_empty() {
+        app
+    } else {
+        Router::new().nest(&config.route_prefix, app).fallback(not_found_handler)
+    };
+    app
+        .layer(middleware::from_fn_with_state(state.clone(), obscure_errors))
+        .layer(middleware::from_fn(negotiate_error_format))
+        .layer(middleware::from_fn_with_state(state.clone(), strip_untrusted_forwarding))
+        .layer(DefaultBodyLimit::max(config.max_file_size))
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(TraceLayer::new_for_http().make_span_with(|request: &axum::http::Request<axum::body::Body>| {
+            let request_id = request
+                .extensions()
+                .get::<RequestId>()
+                .and_then(|id| id.header_value().to_str().ok())
+                .unwrap_or_default();
+            tracing::info_span!("request", id = %request_id, method = %request.method(), path = %request.uri().path())
+        }))
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+        .with_state(state.clone())
+}
+
+async fn account_registry(state: &AppState) {
+    for metadata in state.file_registry.all().await {
+        state.stored_bytes.fetch_add(metadata.size, Ordering::Relaxed);
+        *state.owner_files.lock().unwrap().entry(metadata.owner).or_insert(0) += 1;
+    }
+}
+
+async fn run_migrate_layout(state: &AppState) -> Result<(), Box<dyn std::error::Error>> {
+    let files: Vec<FileMetadata> = state
+        .file_registry
+        .all()
+        .await
+        .into_iter()
+        .filter(|m| m.file_id == content_file_id(&m.owner, &m.checksum))
+        .collect();
+
+    let mut moved = 0;
+    for metadata in files {
+        let source = PathBuf::from(&metadata.disk_path);
+        let target = content_disk_path(&pool_root(&state.config, &source), &metadata.file_id, &state.config);
+        if target == source {
+            continue;
+        }
+        if let Some(dir) = target.parent() {
+            fs::create_dir_all(dir).await?;
+        }
+        fs::rename(&source, &target).await?;
+        let disk_path = target.to_string_lossy().into_owned();
+        for encoding in &metadata.encodings {
+            let _ = fs::rename(variant_path(&metadata.disk_path, encoding), variant_path(&disk_path, encoding)).await;
+        }
+        for format in &metadata.transcodes {
+            let _ = fs::rename(transcode_path(&metadata.disk_path, format), transcode_path(&disk_path, format)).await;
+        }
+        let thumb_path = match &metadata.thumb_path {
+            Some(thumb_path) => {
+                let moved_thumb = format!("{}.thumb.png", disk_path.trim_end_matches(".bin"));
+                fs::rename(thumb_path, &moved_thumb).await.ok().map(|_| moved_thumb)
+            }
+            None => None,
+        };
+        state
+            .file_registry
+            .update(&metadata.file_id, move |entry| {
+                entry.disk_path = disk_path;
+                entry.thumb_path = thumb_path;
+            })
+            .await;
+        info!("📦 migrated | {} | {}", metadata.file_id, target.display());
+        moved += 1;
+    }
+    info!("✅ migrate_layout | {} files moved | {}", moved, if state.config.sharded_layout { "sharded" } else { "flat" });
+    Ok(())
+}
+
+fn pool_root(config: &Config, path: &std::path::Path) -> PathBuf {
+    config
+        .storage_pools
+        .iter()
+        .map(PathBuf::from)
+        .filter(|pool| path.starts_with(pool))
+        .max_by_key(|pool| pool.components().count())
+        .or_else(|| path.parent().map(PathBuf::from))
+        .unwrap_or_default()
+}
+
+fn content_disk_path(pool: &std::path::Path, content_id: &str, config: &Config) -> PathBuf {
+    let name = format!("{}.bin", content_id);
+    if config.sharded_layout {
+        pool.join(&content_id[..2]).join(&content_id[2..4]).join(name)
+    } else {
+        pool.join(name)
+    }
+}
+
+async fn run_gc(state: &AppState) -> Result<(), Box<dyn std::error::Error>> {
+    account_registry(state).await;
+    let before = state.file_registry.len().await;
+    let reclaimed = state.stored_bytes.load(Ordering::Relaxed);
+    let expired = run_cleanup_pass(state).await;
+    let reclaimed = reclaimed.saturating_sub(state.stored_bytes.load(Ordering::Relaxed));
+    info!(
+        "🧹 gc | {} expired | {} removed | {} bytes reclaimed",
+        expired,
+        before.saturating_sub(state.file_registry.len().await),
+        reclaimed
+    );
+    Ok(())
+}
+
+async fn run_reconcile(state: &AppState) -> Result<(), Box<dyn std::error::Error>> {
+    let existing = state.file_registry.all().await;
+    let known: HashSet<String> = existing.iter().map(|m| m.file_id.clone()).collect();
+    reconcile_registry(state.clone(), existing).await;
+
+    let mut orphans = 0;
+    let mut orphan_bytes = 0;
+    let mut dirs: Vec<(PathBuf, usize)> = state.config.storage_pools.iter().map(|pool| (PathBuf::from(pool), 0)).collect();
+    while let Some((dir, depth)) = dirs.pop() {
+        let mut entries = fs::read_dir(&dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let file_type = entry.file_type().await.ok();
+            if file_type.is_some_and(|t| t.is_dir()) && depth < 2 && name.len() == 2 && name.bytes().all(|b| b.is_ascii_hexdigit()) {
+                dirs.push((entry.path(), depth + 1));
+                continue;
+            }
+            let Some(stem) = name.split('.').next().filter(|stem| Uuid::parse_str(stem).is_ok()) else {
+                continue;
+            };
+            if known.contains(stem) || !file_type.is_some_and(|t| t.is_file()) {
+                continue;
+            }
+            let size = entry.metadata().await.map(|m| m.len()).unwrap_or(0);
+            match fs::remove_file(entry.path()).await {
+                Ok(()) => {
+                    warn!("🗑️ orphan_removed | {} | {} bytes", entry.path().display(), size);
+                    orphans += 1;
+                    orphan_bytes += size;
+                }
+                Err(e) => warn!("⚠️ orphan_remove_failed | {} | {}", entry.path().display(), e),
+            }
+        }
+    }
+    info!("✅ reconcile | {} orphan files removed | {} bytes", orphans, orphan_bytes);
+    Ok(())
+}
+
+#[derive(Debug, Default)]
+struct VerifySummary {
+    checked: usize,
+    mismatched: usize,
+    missing: usize,
+    unreadable: usize,
+}
+
+enum Verified {
+    Intact,
+    Mismatch(String),
+    Missing,
+    Unreadable,
+}
+
+async fn verify_file(metadata: &FileMetadata, read_buffer: usize) -> std::io::Result<Verified> {
+    let path = metadata.disk_path.clone();
+    let digest = tokio::task::spawn_blocking(move || -> std::io::Result<Option<String>> {
+        let mut file = match std::fs::File::open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let mut hasher = Sha256::new();
+        let mut buf = vec![0u8; read_buffer.max(64 * 1024)];
+        loop {
+            let n = std::io::Read::read(&mut file, &mut buf)?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+        }
+        Ok(Some(hex::encode(hasher.finalize())))
+    })
+    .await
+    .map_err(std::io::Error::other)??;
+    Ok(match digest {
+        None => Verified::Missing,
+        Some(actual) if actual == metadata.checksum => Verified::Intact,
+        Some(actual) => Verified::Mismatch(actual),
+    })
+}
+
+async fn move_to_quarantine(config: &Config, source: &std::path::Path, record: serde_json::Value) -> std::io::Result<PathBuf> {
+    let dir = match &config.quarantine_dir {
+        Some(dir) => PathBuf::from(dir),
+        None => pool_root(config, source).join("quarantine"),
+    };
+    let name = source.file_name().unwrap_or_default().to_string_lossy().into_owned();
+    let target = dir.join(&name);
+    fs::create_dir_all(&dir).await?;
+    if fs::rename(source, &target).await.is_err() {
+        fs::copy(source, &target).await?;
+        fs::remove_file(source).await?;
+    }
+    fs::OpenOptions::new()
+        .write(true)
+        .open(&target)
+        .await?
+        .into_std()
+        .await
+        .set_modified(SystemTime::now())?;
+    fs::write(dir.join(format!("{}.meta.json", name)), serde_json::to_vec_pretty(&record).unwrap_or_default()).await?;
+    Ok(target)
+}
+
+async fn quarantine_rejected(
+    state: &AppState,
+    disk_path: &std::path::Path,
+    file_id: &str,
+    original_name: &str,
+    reason: &str,
+    details: serde_json::Value,
+) {
+    if state.config.quarantine_dir.is_none() {
+        return;
+    }
+    let record = serde_json::json!({
+        "reason": reason,
+        "file_id": file_id,
+        "original_name": original_name,
+        "quarantined_at": Utc::now().timestamp(),
+        "details": details,
+    });
+    match move_to_quarantine(&state.config, disk_path, record).await {
+        Ok(target) => warn!("🧪 quarantined | {} | {} | {}", file_id, reason, target.display()),
+        Err(e) => error!("❌ quarantine_failed | {} | {}", file_id, e),
+    }
+}
+
+async fn prune_quarantine(state: &AppState) {
+    let retention = state.config.quarantine_retention;
+    if retention == 0 {
+        return;
+    }
+    let cutoff = SystemTime::now() - Duration::from_secs(retention);
+    let mut dirs: Vec<PathBuf> = state.config.storage_pools.iter().map(|pool| PathBuf::from(pool).join("quarantine")).collect();
+    dirs.extend(state.config.quarantine_dir.as_ref().map(PathBuf::from));
+
+    let mut removed = 0;
+    for dir in dirs {
+        let Ok(mut entries) = fs::read_dir(&dir).await else {
+            continue;
+        };
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let stale = entry
+                .metadata()
+                .await
+                .ok()
+                .filter(|m| m.is_file())
+                .and_then(|m| m.modified().ok())
+                .is_some_and(|modified| modified < cutoff);
+            if stale && fs::remove_file(entry.path()).await.is_ok() {
+                removed += 1;
+            }
+        }
+    }
+    if removed > 0 {
+        info!("🧹 quarantine_pruned | {} files | older than {}s", removed, retention);
+    }
+}
+
+async fn quarantine_file(state: &AppState, metadata: &FileMetadata) {
+    let record = serde_json::json!({
+        "reason": "checksum_drift",
+        "quarantined_at": Utc::now().timestamp(),
+        "metadata": metadata,
+    });
+    let target = match move_to_quarantine(&state.config, std::path::Path::new(&metadata.disk_path), record).await {
+        Ok(target) => target,
+        Err(e) => {
+            error!("❌ quarantine_failed | {} | {}", metadata.file_id, e);
+            return;
+        }
+    };
+    if let Some(removed) = state.file_registry.remove(&metadata.file_id).await {
+        release_storage(state, removed.size);
+        release_owner_file(state, &removed.owner);
+        remove_derived_files(&removed).await;
+    }
+    warn!("🧪 quarantined | {} | {}", metadata.file_id, target.display());
+}
+
+async fn run_verify_pass(state: &AppState, owner: Option<&str>) -> VerifySummary {
+    let started = Instant::now();
+    let files: Vec<FileMetadata> = state
+        .file_registry
+        .all()
+        .await
+        .into_iter()
+        .filter(|m| !m.checksum.is_empty() && owner.is_none_or(|o| m.owner == o))
+        .collect();
+    let outcomes: Vec<Verified> = futures_util::stream::iter(files)
+        .map(|metadata| async move {
+            let outcome = match verify_file(&metadata, state.config.read_buffer).await {
+                Ok(outcome) => outcome,
+                Err(e) => {
+                    error!("❌ verify_read_failed | {} | {}", metadata.file_id, e);
+                    return Verified::Unreadable;
+                }
+            };
+            match &outcome {
+                Verified::Mismatch(actual) => {
+                    error!("❌ checksum_drift | {} | expected {} | got {}", metadata.file_id, metadata.checksum, actual);
+                    if state.config.verify_quarantine {
+                        quarantine_file(state, &metadata).await;
+                    }
+                }
+                Verified::Missing => warn!("⚠️ verify_missing | {} | {}", metadata.file_id, metadata.disk_path),
+                Verified::Unreadable | Verified::Intact => {}
+            }
+            outcome
+        })
+        .buffer_unordered(state.config.verify_concurrency)
+        .collect()
+        .await;
+    let mut summary = VerifySummary { checked: outcomes.len(), ..VerifySummary::default() };
+    for outcome in &outcomes {
+        match outcome {
+            Verified::Mismatch(_) => summary.mismatched += 1,
+            Verified::Missing => summary.missing += 1,
+            Verified::Unreadable => summary.unreadable += 1,
+            Verified::Intact => {}
+        }
+    }
+    info!(
+        "🔍 verify | {} | {} checked | {} mismatched | {} missing | {} unreadable | {}ms",
+        owner.unwrap_or("all"),
+        summary.checked,
+        summary.mismatched,
+        summary.missing,
+        summary.unreadable,
+        started.elapsed().as_millis()
+    );
+    summary
+}
+
+async fn verify_files_periodically(state: AppState) {
+    let mut interval = tokio::time::interval(Duration::from_secs(state.config.verify_interval));
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        run_verify_pass(&state, None).await;
+    }
+}
+
+#[cfg(feature = "otel")]
+fn otlp_tracer_provider(endpoint: &str) -> Result<opentelemetry_sdk::trace::TracerProvider, opentelemetry::trace::TraceError> {
+    use opentelemetry_otlp::WithExportConfig;
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_tonic()
+        .with_endpoint(endpoint)
+        .build()?;
+    Ok(opentelemetry_sdk::trace::TracerProvider::builder()
+        .with_batch_exporter(exporter, opentelemetry_sdk::runtime::Tokio)
+        .with_resource(opentelemetry_sdk::Resource::new([opentelemetry::KeyValue::new("service.name", "sptzx")]))
+        .build())
+}
+
+#[derive(Debug)]
+struct ConnectionLimit {
+    max: usize,
+    permits: Arc<tokio::sync::Semaphore>,
+    refused: AtomicU64,
+}
+
+impl ConnectionLimit {
+    fn new(max: usize) -> Option<Arc<Self>> {
+        (max > 0).then(|| {
+            Arc::new(Self { max, permits: Arc::new(tokio::sync::Semaphore::new(max)), refused: AtomicU64::new(0) })
+        })
+    }
+
+    fn open(&self) -> usize {
+        self.max - self.permits.available_permits()
+    }
+}
+
+#[derive(Debug, Clone)]
+struct ConnectionAcceptor {
+    limit: Option<Arc<ConnectionLimit>>,
+    plaintext: bool,
+    write_timeout: Option<Duration>,
+}
+
+impl<S: Send + 'static> Accept<tokio::net::TcpStream, S> for ConnectionAcceptor {
+    type Stream = ConnectionStream;
+    type Service = S;
+    type Future = futures_util::future::BoxFuture<'static, std::io::Result<(Self::Stream, Self::Service)>>;
+
+    fn accept(&self, mut stream: tokio::net::TcpStream, service: S) -> Self::Future {
+        let _ = stream.set_nodelay(true);
+        let permit = match &self.limit {
+            None => Ok(None),
+            Some(limit) => limit.permits.clone().try_acquire_owned().map(Some).map_err(|_| limit.clone()),
+        };
+        let plaintext = self.plaintext;
+        let write_timeout = self.write_timeout;
+        Box::pin(async move {
+            match permit {
+                Ok(permit) => {
+                    let peer = stream.peer_addr().map(|addr| addr.ip()).unwrap_or(Ipv4Addr::UNSPECIFIED.into());
+                    Ok((ConnectionStream { inner: stream, peer, write_timeout, stall: None, _permit: permit }, service))
+                }
+                Err(limit) => {
+                    if limit.refused.fetch_add(1, Ordering::Relaxed) % 1000 == 0 {
+                        warn!("🚧 connection_refused | limit {} | {} refused", limit.max, limit.refused.load(Ordering::Relaxed));
+                    }
+                    if plaintext {
+                        let _ = tokio::time::timeout(Duration::from_secs(1), stream.write_all(CONNECTION_LIMIT_RESPONSE)).await;
+                    }
+                    Err(std::io::Error::new(std::io::ErrorKind::ConnectionRefused, "connection limit reached"))
+                }
+            }
+        })
+    }
+}
+
+struct ConnectionStream {
+    inner: tokio::net::TcpStream,
+    peer: IpAddr,
+    write_timeout: Option<Duration>,
+    stall: Option<std::pin::Pin<Box<tokio::time::Sleep>>>,
+    _permit: Option<tokio::sync::OwnedSemaphorePermit>,
+}
+
+impl ConnectionStream {
+    fn watch_stall<T>(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+        poll: std::task::Poll<std::io::Result<T>>,
+    ) -> std::task::Poll<std::io::Result<T>> {
+        let Some(timeout) = self.write_timeout else { return poll };
+        if poll.is_ready() {
+            self.stall = None;
+            return poll;
+        }
+        let stall = self.stall.get_or_insert_with(|| Box::pin(tokio::time::sleep(timeout)));
+        match std::future::Future::poll(stall.as_mut(), cx) {
+            std::task::Poll::Ready(()) => {
+                warn!("⏱️ download_stalled | {} | no progress for {}s", self.peer, timeout.as_secs());
+                std::task::Poll::Ready(Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "client stopped reading")))
+            }
+            std::task::Poll::Pending => std::task::Poll::Pending,
+        }
+    }
+}
+
+impl AsyncRead for ConnectionStream {
+    fn poll_read(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        std::pin::Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for ConnectionStream {
+    fn poll_write(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> std::task::Poll<std::io::Result<usize>> {
+        let poll = std::pin::Pin::new(&mut self.inner).poll_write(cx, buf);
+        self.watch_stall(cx, poll)
+    }
+
+    fn poll_write_vectored(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        bufs: &[std::io::IoSlice<'_>],
+    ) -> std::task::Poll<std::io::Result<usize>> {
+        let poll = std::pin::Pin::new(&mut self.inner).poll_write_vectored(cx, bufs);
+        self.watch_stall(cx, poll)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<std::io::Result<()>> {
+        let poll = std::pin::Pin::new(&mut self.inner).poll_flush(cx);
+        self.watch_stall(cx, poll)
+    }
+
+    fn poll_shutdown(mut self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<std::io::Result<()>> {
+        std::pin::Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+async fn reload_tls_on_sighup(tls: RustlsConfig, config: Arc<Config>) {
+    #[cfg(unix)]
+    {
+        let (Some(cert), Some(key)) = (&config.tls_cert, &config.tls_key) else { return };
+        let mut hangup = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+            Ok(signal) => signal,
+            Err(e) => {
+                error!("❌ sighup_handler_failed | {}", e);
+                return;
+            }
+        };
+        while hangup.recv().await.is_some() {
+            match tls.reload_from_pem_file(cert, key).await {
+                Ok(_) => info!("🔐 tls_reloaded | {}", cert),
+                Err(e) => error!("❌ tls_reload_failed | {} | {} | keeping previous certificate", cert, e),
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = (tls, config);
+}
+
+fn bind_listener(addr: SocketAddr, dual_stack: bool, backlog: i32) -> std::io::Result<(tokio::net::TcpListener, Vec<String>)> {
+    let addr = match addr {
+        SocketAddr::V4(v4) if dual_stack && v4.ip().is_unspecified() => {
+            SocketAddr::new(std::net::Ipv6Addr::UNSPECIFIED.into(), v4.port())
+        }
+        other => other,
+    };
+
+    let socket = socket2::Socket::new(socket2::Domain::for_address(addr), socket2::Type::STREAM, Some(socket2::Protocol::TCP))?;
+    socket.set_reuse_address(true)?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(!dual_stack)?;
+    }
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(backlog)?;
+
+    let listener = tokio::net::TcpListener::from_std(socket.into())?;
+    let local = listener.local_addr()?;
+    let mut bound = vec![local.to_string()];
+    if dual_stack && local.is_ipv6() {
+        let v4 = match local.ip() {
+            ip if ip.is_unspecified() => std::net::Ipv4Addr::UNSPECIFIED.to_string(),
+            ip => ip.to_string(),
+        };
+        bound.push(format!("{}:{} (dual-stack)", v4, local.port()));
+    }
+    Ok((listener, bound))
+}
+
+async fn shutdown_signal(state: AppState) {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                error!("❌ sigterm_handler_failed | {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+
+    state.draining.store(true, Ordering::SeqCst);
+    warn!("🛑 draining | uploads rejected for {}s before shutdown", state.config.shutdown_drain);
+    tokio::time::sleep(Duration::from_secs(state.config.shutdown_drain)).await;
+}
+
+async fn negotiate_error_format(request: axum::extract::Request, next: middleware::Next) -> Response {
+    let html = accepts_first(request.headers(), "text/html");
+    let mut response = next.run(request).await;
+    let Some(error) = response.extensions_mut().remove::<ErrorResponse>() else {
+        return response;
+    };
+    response.headers_mut().append(header::VARY, HeaderValue::from_static("accept"));
+    if !html {
+        return response;
+    }
+
+    let status = response.status();
+    let title = format!("{} {}", status.as_u16(), status.canonical_reason().unwrap_or("Error"));
+    let page = format!(
+        "<!doctype html><html><head><meta charset=\"utf-8\"><meta name=\"viewport\" content=\"width=device-width, initial-scale=1\"><title>{}</title><style>html,body{{margin:0;height:100%;background:#111;color:#ccc;font:16px/1.5 sans-serif;display:flex;align-items:center;justify-content:center}}main{{text-align:center;padding:16px}}h1{{color:#eee;font-size:20px;margin:0 0 8px}}code{{color:#888;font-size:13px}}</style></head><body><main><h1>{}</h1><p>{}</p><code>{}</code></main></body></html>",
+        html_escape(&title),
+        html_escape(&error.message),
+        html_escape(&title),
+        html_escape(&error.code.as_str()),
+    );
+    let (mut parts, _) = response.into_parts();
+    parts.headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("text/html; charset=utf-8"));
+    parts.headers.remove(header::CONTENT_LENGTH);
+    Response::from_parts(parts, Body::from(page))
+}
+
+fn accepts_first(headers: &HeaderMap, mime_type: &str) -> bool {
+    headers
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(',').next())
+        .is_some_and(|first| first.split(';').next().unwrap_or_default().trim().eq_ignore_ascii_case(mime_type))
+}
+
+async fn reject_when_draining(
+    State(state): State<AppState>,
+    request: axum::extract::Request,
+    next: middleware::Next,
+) -> Response {
+    if state.draining.load(Ordering::SeqCst) {
+        return ApiError::new(ErrorCode::ShuttingDown)
+            .with_retry_after(state.config.shutdown_drain)
+            .into_response();
+    }
+    next.run(request).await
+}
+
+async fn strip_untrusted_forwarding(
+    State(state): State<AppState>,
+    mut request: axum::extract::Request,
+    next: middleware::Next,
+) -> Response {
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip().to_canonical())
+        .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+    if !is_trusted_proxy(&state.config, peer) {
+        let headers = request.headers_mut();
+        headers.remove(header::FORWARDED);
+        headers.remove("x-forwarded-proto");
+    }
+    next.run(request).await
+}
+
+async fn obscure_errors(
+    State(state): State<AppState>,
+    request: axum::extract::Request,
+    next: middleware::Next,
+) -> Response {
+    if !state.config.obscure_errors || !matches!(*request.method(), Method::GET | Method::HEAD) {
+        return next.run(request).await;
+    }
+    let path = request.uri().path().to_string();
+    let response = next.run(request).await;
+    let Some(error) = response.extensions().get::<ErrorResponse>() else {
+        return response;
+    };
+    let obscured = matches!(
+        error.code,
+        ErrorCode::FileNotFound
+            | ErrorCode::FileExpired
+            | ErrorCode::InvalidSignature
+            | ErrorCode::InvalidExpires
+            | ErrorCode::UnsupportedVersion
+            | ErrorCode::LinkExpired
+            | ErrorCode::LinkExhausted
+            | ErrorCode::InvalidMode
+            | ErrorCode::IdMismatch
+            | ErrorCode::OwnerMismatch
+            | ErrorCode::IpMismatch
+            | ErrorCode::TypeMismatch
+            | ErrorCode::RefererNotAllowed
+            | ErrorCode::PublicDisabled
+            | ErrorCode::MissingParameter(_)
+            | ErrorCode::InvalidParameter(_)
+            | ErrorCode::UnexpectedParameter
+            | ErrorCode::ParameterTooLong(_)
+    );
+    if !obscured {
+        return response;
+    }
+
+    info!("🕶️ obscured | {} | {} | {}", path, error.code.as_str(), error.message);
+    let jitter = (Uuid::new_v4().as_u128() % (state.config.obscure_delay as u128 + 1)) as u64;
+    tokio::time::sleep(Duration::from_millis(jitter)).await;
+    ApiError::new(ErrorCode::FileNotFound).into_response()
+}
+
+async fn limit_signed_query(
+    State(state): State<AppState>,
+    request: axum::extract::Request,
+    next: middleware::Next,
+) -> Response {
+    let length = request.uri().query().map_or(0, str::len);
+    if length > state.config.max_signed_query {
+        warn!("⚠️ query_too_long | {} | {} bytes", request.uri().path(), length);
+        return ApiError::new(ErrorCode::QueryTooLong)
+            .with_details(serde_json::json!({ "limit": state.config.max_signed_query }))
+            .into_response();
+    }
+    next.run(request).await
+}
+
+async fn advertise_upload_limit(
+    State(state): State<AppState>,
+    request: axum::extract::Request,
+    next: middleware::Next,
+) -> Response {
+    let expectation = request
+        .headers()
+        .get(header::EXPECT)
+        .map(|v| v.to_str().map(str::trim).unwrap_or_default().to_ascii_lowercase());
+    let mut response = match expectation {
+        Some(expectation) if expectation != "100-continue" => ApiError::new(ErrorCode::ExpectationFailed).into_response(),
+        _ => next.run(request).await,
+    };
+    if response.status().is_client_error() || response.status().is_server_error() {
+        response.headers_mut().insert("x-max-file-size", state.config.max_file_size.into());
+    }
+    response
+}
+
+#[derive(Clone)]
+struct RequestTrailers {
+    body: Arc<std::sync::Mutex<Body>>,
+    trailers: Arc<std::sync::Mutex<Option<HeaderMap>>>,
+}
+
+type BodyFrame = Option<Result<http_body::Frame<bytes::Bytes>, axum::Error>>;
+
+impl RequestTrailers {
+    fn poll_frame(&self, cx: &mut std::task::Context<'_>) -> std::task::Poll<BodyFrame> {
+        let frame = http_body::Body::poll_frame(std::pin::Pin::new(&mut *self.body.lock().unwrap()), cx);
+        if let std::task::Poll::Ready(Some(Ok(frame))) = &frame {
+            if let Some(trailers) = frame.trailers_ref() {
+                *self.trailers.lock().unwrap() = Some(trailers.clone());
+            }
+        }
+        frame
+    }
+
+    async fn finish(&self) -> Option<HeaderMap> {
+        let mut drained = 0;
+        while drained <= TRAILER_DRAIN_LIMIT {
+            match futures_util::future::poll_fn(|cx| self.poll_frame(cx)).await {
+                Some(Ok(frame)) => drained += frame.data_ref().map_or(0, |data| data.len()),
+                _ => break,
+            }
+        }
+        self.trailers.lock().unwrap().take()
+    }
+}
+
+struct TrailerTap(RequestTrailers);
+
+impl http_body::Body for TrailerTap {
+    type Data = bytes::Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<BodyFrame> {
+        self.0.poll_frame(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        http_body::Body::is_end_stream(&*self.0.body.lock().unwrap())
+    }
+
+    fn size_hint(&self) -> http_body::SizeHint {
+        http_body::Body::size_hint(&*self.0.body.lock().unwrap())
+    }
+}
+
+async fn capture_trailers(request: axum::extract::Request, next: middleware::Next) -> Response {
+    let (mut parts, body) = request.into_parts();
+    let trailers = RequestTrailers {
+        body: Arc::new(std::sync::Mutex::new(body)),
+        trailers: Arc::new(std::sync::Mutex::new(None)),
+    };
+    parts.extensions.insert(trailers.clone());
+    next.run(axum::extract::Request::from_parts(parts, Body::new(TrailerTap(trailers)))).await
+}
+
+fn parse_checksum(value: &HeaderValue) -> Result<String, ApiError> {
+    match value.to_str().map(|v| v.trim().to_ascii_lowercase()) {
+        Ok(hex) if hex.len() == 64 && hex.chars().all(|c| c.is_ascii_hexdigit()) => Ok(hex),
+        _ => Err(ApiError::new(ErrorCode::InvalidChecksum)),
+    }
+}
+
+async fn decode_upload_encoding(
+    State(state): State<AppState>,
+    request: axum::extract::Request,
+    next: middleware::Next,
+) -> Response {
+    let gzipped = request
+        .headers()
+        .get(header::CONTENT_ENCODING)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.trim().eq_ignore_ascii_case("gzip"));
+    if !state.config.decode_upload_encoding || !gzipped {
+        return next.run(request).await;
+    }
+
+    let (mut parts, body) = request.into_parts();
+    parts.headers.remove(header::CONTENT_ENCODING);
+    parts.headers.remove(header::CONTENT_LENGTH);
+
+    let cap = state.config.max_file_size as u64 + HEADER_SNIFF_LEN as u64;
+    let decoder = flate2::write::GzDecoder::new(Vec::new());
+    let decoded = futures_util::stream::unfold(
+        (body.into_data_stream(), Some(decoder), 0u64),
+        move |(mut body, decoder, total)| async move {
+            let mut decoder = decoder?;
+            match body.next().await {
+                Some(Ok(chunk)) => match gunzip_step(&mut decoder, &chunk, total, cap) {
+                    Ok((bytes, total)) if total > cap => {
+                        warn!("🧨 gzip_bomb | decoded upload passed {} bytes", cap);
+                        Some((Ok(bytes), (body, None, total)))
+                    }
+                    Ok((bytes, total)) => Some((Ok(bytes), (body, Some(decoder), total))),
+                    Err(e) => Some((Err(e), (body, None, total))),
+                },
+                Some(Err(e)) => Some((Err(std::io::Error::other(e)), (body, None, total))),
+                None => {
+                    let tail = decoder
+                        .try_finish()
+                        .and_then(|_| gunzip_step(&mut decoder, &[], total, cap))
+                        .map(|(bytes, _)| bytes);
+                    Some((tail, (body, None, total)))
+                }
+            }
+        },
+    );
+
+    next.run(axum::extract::Request::from_parts(parts, Body::from_stream(decoded))).await
+}
+
+fn gunzip_step(
+    decoder: &mut flate2::write::GzDecoder<Vec<u8>>,
+    input: &[u8],
+    total: u64,
+    cap: u64,
+) -> std::io::Result<(bytes::Bytes, u64)> {
+    use std::io::Write;
+
+    let mut output = Vec::new();
+    let mut total = total;
+    let mut slices = input.chunks(GUNZIP_SLICE_LEN);
+    loop {
+        total += decoder.get_ref().len() as u64;
+        output.append(decoder.get_mut());
+        match slices.next() {
+            Some(slice) if total <= cap => decoder.write_all(slice)?,
+            _ => return Ok((bytes::Bytes::from(output), total)),
+        }
+    }
+}
+
+async fn not_found_handler() -> ApiError {
+    ApiError::new(ErrorCode::NotFound)
+}
+
+async fn method_not_allowed_handler() -> ApiError {
+    ApiError::new(ErrorCode::MethodNotAllowed)
+}
+
+async fn liveness_check() -> Response {
+    Json(serde_json::json!({"status":"ok"})).into_response()
+}
+
+async fn health_check(State(state): State<AppState>) -> Response {
+    let unwritable: Vec<String> = state.unwritable_pools.lock().unwrap().iter().cloned().collect();
+    let (status, body) = if !state.ready.load(Ordering::Acquire) {
+        (StatusCode::SERVICE_UNAVAILABLE, serde_json::json!({"status":"starting"}))
+    } else if unwritable.is_empty() {
+        (StatusCode::OK, serde_json::json!({"status":"ok"}))
+    } else if unwritable.len() < state.config.storage_pools.len() {
+        (StatusCode::OK, serde_json::json!({"status":"ok","unwritable_pools":unwritable}))
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, serde_json::json!({"status":"degraded","storage":"unwritable"}))
+    };
+    let body = if state.config.health_build_info { state.build.annotate(body) } else { body };
+    (status, Json(body)).into_response()
+}
+
+async fn capabilities_handler(State(state): State<AppState>) -> Response {
+    let config = &state.config;
+    let mut auth_schemes = Vec::new();
+    if !config.api_keys.is_empty() {
+        auth_schemes.extend(["bearer", "x-api-key"]);
+    }
+    if state.jwt.is_some() {
+        auth_schemes.push("jwt");
+    }
+    if config.admin_token.is_some() {
+        auth_schemes.push("x-admin-token");
+    }
+
+    let mut headers = HeaderMap::new();
+    headers.insert(header::ALLOW, HeaderValue::from_static("GET, POST, OPTIONS"));
+    headers.insert("sptzx-max-file-size", config.max_file_size.into());
+    headers.insert("sptzx-max-files", MAX_FILES_PER_UPLOAD.into());
+    headers.insert("sptzx-archive-max-entries", config.archive_max_entries.into());
+    headers.insert("sptzx-auth-schemes", auth_schemes.join(", ").parse().unwrap());
+    headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, HeaderValue::from_static("*"));
+    headers.insert(header::ACCESS_CONTROL_ALLOW_METHODS, HeaderValue::from_static("GET, POST, OPTIONS"));
+    headers.insert(
+        header::ACCESS_CONTROL_ALLOW_HEADERS,
+        HeaderValue::from_static("authorization, content-type, x-api-key, x-content-type, x-request-id"),
+    );
+    headers.insert(
+        header::ACCESS_CONTROL_EXPOSE_HEADERS,
+        HeaderValue::from_static(
+            "sptzx-max-file-size, sptzx-max-files, sptzx-archive-max-entries, sptzx-auth-schemes, x-request-id",
+        ),
+    );
+    headers.insert(header::ACCESS_CONTROL_MAX_AGE, HeaderValue::from_static("86400"));
+
+    let body = serde_json::json!({
+        "max_file_size": config.max_file_size,
+        "min_file_size": config.min_file_size,
+        "max_files": MAX_FILES_PER_UPLOAD,
+        "archive_max_entries": config.archive_max_entries,
+        "multi_upload_mode": config.multi_upload_mode,
+        "file_lifetime": config.file_lifetime,
+        "max_file_lifetime": config.max_file_lifetime,
+        "methods": ["GET", "POST", "OPTIONS"],
+        "auth_schemes": auth_schemes,
+        "allowed_mime_types": config.allowed_mime_types,
+        "thumbnails": config.thumbnails != ThumbnailMode::Off,
+        "precompressed_encodings": if config.precompress { PRECOMPRESSED_ENCODINGS.to_vec() } else { Vec::new() },
+    });
+
+    (StatusCode::OK, headers, Json(body)).into_response()
+}
+
+#[tracing::instrument(name = "upload", skip_all, fields(file_id = Empty, bytes = Empty, mime = Empty, duration_ms = Empty))]
+async fn upload_handler(
+    State(state): State<AppState>,
+    ClientIp(client): ClientIp,
+    Query(query): Query<HashMap<String, String>>,
+    Extension(trailers): Extension<RequestTrailers>,
+    headers: HeaderMap,
+    mut multipart: Multipart,
+) -> Result<Response, ApiError> {
+    let started = Instant::now();
+    check_rate_limit(&state.ip_limiter, "ip", &client.to_string())?;
+    let authenticated_owner = authenticate_owner(&headers, &state)?;
+    let limits = UploadLimits::resolve(&state.config, authenticated_owner.is_some())?;
+
+    let idempotency_key = match headers.get("idempotency-key") {
+        Some(value) => match value.to_str().map(str::trim) {
+            Ok(key) if !key.is_empty() && key.len() <= 255 => Some(key.to_string()),
+            _ => return Err(ApiError::new(ErrorCode::InvalidIdempotencyKey)),
+        },
+        None => None,
+    };
+    let idempotency_scope = match &authenticated_owner {
+        Some(owner) => owner.clone(),
+        None => format!("{}|{}", state.config.anon_owner, client),
+    };
+    let mut idempotency = None;
+    if let Some(key) = &idempotency_key {
+        if let Some(previous) = state.idempotency.get(&idempotency_scope, key) {
+            let now = Utc::now().timestamp();
+            if state.file_registry.get(&previous.id).await.is_some_and(|m| m.expires_at > now) {
+                info!("🔁 idempotent_replay | {} | {}", idempotency_scope, previous.id);
+                return Ok(([("idempotent-replayed", "true")], upload_reply(&query, &headers, previous)).into_response());
+            }
+        }
+        idempotency = state.idempotency.reserve(&idempotency_scope, key)?;
+    }
+    if let Some(owner) = &authenticated_owner {
+        check_rate_limit(&state.owner_limiter, "owner", owner)?;
+    }
+    let file_id = Uuid::new_v4().to_string();
+    let mut original_filename = String::from("unknown");
+    let mut client_name = None;
+    let mut total_size: u64 = 0;
+    let mut header_bytes: Vec<u8> = Vec::with_capacity(HEADER_SNIFF_LEN);
+    let mut hasher = Sha256::new();
+    let mut declared_size = None;
+    let expected_checksum = headers.get("x-expected-sha256").map(parse_checksum).transpose()?;
+    let header_content_type = headers
+        .get("x-content-type")
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string());
+    let header_expires_at = headers
+        .get("x-expires-at")
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string());
+    let header_download_name = headers
+        .get("x-download-name")
+        .and_then(|v| std::str::from_utf8(v.as_bytes()).ok())
+        .map(|v| v.to_string());
+    let mut form_fields: HashMap<String, String> = HashMap::new();
+    let mut file_seen = false;
+
+    if let Some(declared) = headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok())
+    {
+        if declared > limits.max_file_size {
+            return Err(file_too_large(limits.max_file_size));
+        }
+        if let Some(max_total) = state.config.max_total_bytes {
+            let evicts = state.config.evict_on_full || state.config.cleanup_strategy != CleanupStrategy::Ttl;
+            if !evicts && state.stored_bytes.load(Ordering::Relaxed).saturating_add(declared) > max_total + HEADER_SNIFF_LEN as u64 {
+                return Err(ApiError::new(ErrorCode::StorageCapReached)
+                    .with_details(serde_json::json!({ "max_total_bytes": max_total })));
+            }
+        }
+    }
+
+    let pool = select_pool(&state).ok_or_else(|| {
+        if state.unwritable_pools.lock().unwrap().len() >= state.config.storage_pools.len() {
+            ApiError::new(ErrorCode::StorageUnavailable)
+        } else {
+            error!("❌ storage_full | all pools below reserve of {} bytes", state.config.pool_reserve);
+            ApiError::new(ErrorCode::InsufficientStorage)
+        }
+    })?;
+    let disk_path = PathBuf::from(&pool).join(format!("{}.bin", file_id));
+
+    let file = File::create(&disk_path).await.map_err(|e| match e.kind() {
+        std::io::ErrorKind::PermissionDenied | std::io::ErrorKind::ReadOnlyFilesystem => {
+            mark_storage_writable(&state, &pool, false, &e.to_string());
+            ApiError::new(ErrorCode::StorageUnavailable)
+        }
+        _ => ApiError::new(ErrorCode::FileCreateFailed),
+    })?;
+    let mut partial = PartialUpload::new(&disk_path);
+
+    let mut writer = BufWriter::with_capacity(state.config.buffer_size, file);
+
+    let streamed = async {
+        let mut part_count = 0;
+        let mut fields_total = 0;
+        while let Some(mut field) = within_idle(state.config.upload_timeout, &file_id, multipart.next_field()).await?.map_err(|e| multipart_error(e, ErrorCode::InvalidMultipart))? {
+            part_count += 1;
+            if part_count > state.config.max_parts {
+                warn!("⚠️ too_many_parts | {} | limit {}", file_id, state.config.max_parts);
+                return Err(ApiError::new(ErrorCode::TooManyParts)
+                    .with_details(serde_json::json!({ "limit": state.config.max_parts })));
+            }
+
+            let text_name = field.name().filter(|n| field.file_name().is_none() && *n != "file").map(str::to_string);
+            if let Some(name) = text_name {
+                let mut raw = Vec::new();
+                while let Some(chunk) = within_idle(state.config.upload_timeout, &file_id, field.chunk()).await?.map_err(|e| multipart_error(e, ErrorCode::InvalidMultipart))? {
+                    if raw.len() + chunk.len() > state.config.max_field_size {
+                        return Err(ApiError::new(ErrorCode::FieldTooLarge)
+                            .with_details(serde_json::json!({ "field": name, "limit": state.config.max_field_size })));
+                    }
+                    fields_total += chunk.len();
+                    if fields_total > state.config.max_fields_total {
+                        warn!("⚠️ fields_too_large | {} | limit {}", file_id, state.config.max_fields_total);
+                        return Err(ApiError::new(ErrorCode::FieldsTooLarge)
+                            .with_details(serde_json::json!({ "limit": state.config.max_fields_total })));
+                    }
+                    raw.extend_from_slice(&chunk);
+                }
+                if !METADATA_FIELDS.contains(&name.as_str()) {
+                    warn!("⚠️ unknown_field_ignored | {} | {} | {} bytes", file_id, name, raw.len());
+                    continue;
+                }
+                let value = String::from_utf8(raw).map_err(|_| ApiError::new(ErrorCode::InvalidMultipart))?;
+                if form_fields.insert(name.clone(), value).is_some() {
+                    warn!("⚠️ duplicate_field | {} | {}", file_id, name);
+                    return Err(ApiError::new(ErrorCode::DuplicateField).with_details(serde_json::json!({ "field": name })));
+                }
+                continue;
+            }
+
+            if file_seen {
+                warn!("⚠️ multiple_files | {} | {:?}", file_id, field.name());
+                return Err(ApiError::new(ErrorCode::MultipleFiles));
+            }
+            file_seen = true;
+
+            if let Some(name) = field.file_name() {
+                original_filename = sanitize_filename(name, state.config.max_filename_len);
+                client_name = Some(sanitize_download_name(name, state.config.max_filename_len)).filter(|n| !n.is_empty());
+            }
+            check_extension(&original_filename, &state.config)?;
+
+            let provisional_mime = form_fields
+                .get("content_type")
+                .or(header_content_type.as_ref())
+                .and_then(|v| v.trim().parse::<mime_guess::mime::Mime>().ok())
+                .map(|v| v.to_string())
+                .unwrap_or_else(|| mime_guess::from_path(&original_filename).first_or_octet_stream().to_string());
+            let class_limit = class_size_limit(&provisional_mime, &state.config);
+            if let Some(value) = form_fields.get("size") {
+                let size = value.trim().parse::<u64>().map_err(|_| ApiError::new(ErrorCode::InvalidSize))?;
+                check_declared_size(&state, &pool, size, limits.max_file_size, class_limit)?;
+                declared_size = Some(size);
+            }
+
+            let mut stream = field;
+            while let Some(chunk) = within_idle(state.config.upload_timeout, &file_id, stream.next()).await? {
+                let data = chunk.map_err(|e| multipart_error(e, ErrorCode::ChunkReadFailed))?;
+
+                total_size += data.len() as u64;
+
+                if total_size > limits.max_file_size {
+                    return Err(file_too_large(limits.max_file_size));
+                }
+                if let Some((class, limit)) = class_limit {
+                    if total_size > limit {
+                        return Err(class_too_large(class, limit));
+                    }
+                }
+
+                if header_bytes.len() < HEADER_SNIFF_LEN {
+                    let take = (HEADER_SNIFF_LEN - header_bytes.len()).min(data.len());
+                    header_bytes.extend_from_slice(&data[..take]);
+                }
+
+                hasher.update(&data);
+                writer.write_all(&data).await.map_err(|_| ApiError::new(ErrorCode::WriteFailed))?;
+            }
+        }
+
+        writer.flush().await.map_err(|_| ApiError::new(ErrorCode::FlushFailed))
+    }
+    .await;
+
+    streamed?;
+    if !file_seen {
+        return Err(ApiError::new(ErrorCode::MissingFile));
+    }
+    let trailer_checksum = trailers
+        .finish()
+        .await
+        .and_then(|trailers| trailers.get("x-checksum-sha256").map(parse_checksum))
+        .transpose()?;
+    form_fields.remove("size");
+    if let Some(declared) = declared_size.filter(|declared| *declared != total_size) {
+        warn!("⚠️ size_mismatch | {} | declared {} | received {}", file_id, declared, total_size);
+    }
+    if total_size < state.config.min_file_size {
+        return Err(ApiError::new(ErrorCode::FileTooSmall)
+            .with_details(serde_json::json!({ "size": total_size, "min_file_size": state.config.min_file_size })));
+    }
+
+    let content_type_override = match (header_content_type, form_fields.remove("content_type")) {
+        (Some(header_value), Some(field_value)) if header_value.trim() != field_value.trim() => {
+            return Err(conflicting_field("content_type"));
+        }
+        (header_value, field_value) => field_value.or(header_value),
+    };
+
+    let requested_expiry = match (header_expires_at, form_fields.remove("expires_at")) {
+        (Some(header_value), Some(field_value)) if header_value.trim() != field_value.trim() => {
+            return Err(conflicting_field("expires_at"));
+        }
+        (header_value, field_value) => field_value.or(header_value),
+    }
+    .map(|value| parse_expires_at(&value, limits.max_lifetime))
+    .transpose()?;
+
+    let owner = match (authenticated_owner, form_fields.remove("owner").map(|v| v.trim().to_string())) {
+        (Some(authenticated), Some(requested)) if authenticated != requested => {
+            return Err(conflicting_field("owner"));
+        }
+        (Some(authenticated), _) => authenticated,
+        (None, Some(_)) if !state.config.api_keys.is_empty() || state.jwt.is_some() => {
+            return Err(ApiError::new(ErrorCode::Unauthorized));
+        }
+        (None, Some(requested)) if !requested.is_empty() => requested,
+        (None, _) => state.config.anon_owner.clone(),
+    };
+
+    let limits = limits.for_owner(&owner, &state.config);
+    let requested_ttl = match form_fields.remove("ttl") {
+        Some(_) if requested_expiry.is_some() => {
+            return Err(conflicting_field("ttl"));
+        }
+        Some(value) => match value.trim().parse::<u64>() {
+            Ok(ttl) if ttl > 0 && ttl <= limits.max_lifetime => Some(ttl),
+            _ => {
+                return Err(ApiError::new(ErrorCode::InvalidTtl)
+                    .with_details(serde_json::json!({ "max": limits.max_lifetime })));
+            }
+        },
+        None => None,
+    };
+
+    let public = match (query.get("public"), form_fields.remove("public")) {
+        (Some(query_value), Some(field_value)) if query_value.trim() != field_value.trim() => {
+            return Err(conflicting_field("public"));
+        }
+        (query_value, field_value) => field_value.as_deref().or(query_value.map(String::as_str)).map(str::trim) == Some("true"),
+    };
+    if public && !state.config.allow_public {
+        return Err(ApiError::new(ErrorCode::PublicDisabled));
+    }
+
+    let worm = match (query.get("worm"), form_fields.remove("worm")) {
+        (Some(query_value), Some(field_value)) if query_value.trim() != field_value.trim() => {
+            return Err(conflicting_field("worm"));
+        }
+        (query_value, field_value) => field_value.as_deref().or(query_value.map(String::as_str)).map(str::trim) == Some("true"),
+    };
+    if worm && !state.config.allow_worm {
+        return Err(ApiError::new(ErrorCode::WormDisabled));
+    }
+
+    let bind_ip = match (query.get("bind_ip"), form_fields.remove("bind_ip")) {
+        (Some(query_value), Some(field_value)) if query_value.trim() != field_value.trim() => {
+            return Err(conflicting_field("bind_ip"));
+        }
+        (query_value, field_value) => field_value.as_deref().or(query_value.map(String::as_str)).map(str::trim) == Some("true"),
+    };
+
+    let max_concurrent = match (query.get("max_concurrent"), form_fields.remove("max_concurrent")) {
+        (Some(query_value), Some(field_value)) if query_value.trim() != field_value.trim() => {
+            return Err(conflicting_field("max_concurrent"));
+        }
+        (query_value, field_value) => match field_value.as_deref().or(query_value.map(String::as_str)).map(str::trim) {
+            Some(value) => match value.parse::<usize>() {
+                Ok(limit) if limit > 0 => Some(match state.config.max_concurrent_downloads {
+                    0 => limit,
+                    global => limit.min(global),
+                }),
+                _ => return Err(ApiError::new(ErrorCode::InvalidConcurrency)),
+            },
+            None => None,
+        },
+    };
+
+    let download_name = match (header_download_name, form_fields.remove("download_name")) {
+        (Some(header_value), Some(field_value)) if header_value.trim() != field_value.trim() => {
+            return Err(conflicting_field("download_name"));
+        }
+        (header_value, field_value) => field_value
+            .or(header_value)
+            .map(|name| sanitize_download_name(&name, state.config.max_filename_len))
+            .filter(|name| !name.is_empty()),
+    };
+
+    let description = match form_fields.remove("description").map(|v| sanitize_description(&v)) {
+        Some(text) if text.chars().count() > state.config.max_description_len => {
+            return Err(ApiError::new(ErrorCode::DescriptionTooLong)
+                .with_details(serde_json::json!({ "limit": state.config.max_description_len })));
+        }
+        Some(text) => Some(text).filter(|text| !text.is_empty()),
+        None => None,
+    };
+
+    let password_hash = form_fields
+        .remove("password")
+        .filter(|v| !v.is_empty())
+        .map(|password| compute_hmac(&password, &state.config.secret_key));
+
+    let guessed_mime = mime_guess::from_path(&original_filename).first_or_octet_stream().to_string();
+    let mime_type = match content_type_override.as_deref().map(str::trim).filter(|v| !v.is_empty()) {
+        Some(declared) => match declared.parse::<mime_guess::mime::Mime>() {
+            Ok(parsed) => parsed.to_string(),
+            Err(_) => {
+                return Err(ApiError::new(ErrorCode::InvalidContentType));
+            }
+        },
+        None => guessed_mime.clone(),
+    };
+    let mime_type = correct_mime(&state.config, &file_id, mime_type, &header_bytes);
+
+    if !is_allowed_mime(&mime_type, &state.config) {
+        warn!("⚠️ mime_not_allowed | {} | {}", original_filename, mime_type);
+        return Err(ApiError::new(ErrorCode::MimeNotAllowed).with_details(serde_json::json!({ "mime": mime_type })));
+    }
+
+    if let Some((class, limit)) = class_size_limit(&mime_type, &state.config) {
+        if total_size > limit {
+            return Err(class_too_large(class, limit));
+        }
+    }
+    let ttl = requested_ttl
+        .unwrap_or_else(|| expiry_ttl(requested_expiry, limits.for_type(&mime_type, &state.config).default_ttl));
+
+    let (width, height) = image_dimensions(&mime_type, &header_bytes);
+    let mut checksum = hex::encode(hasher.finalize());
+    if let Some(expected) = expected_checksum.into_iter().chain(trailer_checksum).find(|expected| *expected != checksum) {
+        warn!("⚠️ checksum_mismatch | {} | expected {} | got {}", file_id, expected, checksum);
+        let details = serde_json::json!({ "owner": owner, "expected": expected, "actual": checksum });
+        quarantine_rejected(&state, &disk_path, &file_id, &original_filename, "checksum_mismatch", details).await;
+        return Err(ApiError::new(ErrorCode::ChecksumMismatch)
+            .with_details(serde_json::json!({ "expected": expected, "actual": checksum })));
+    }
+
+    let mut exif_stripped = false;
+    if state.config.strip_exif && is_strippable_mime(&mime_type) {
+        if let Some((stripped_size, stripped_checksum)) = strip_image_metadata(&disk_path, &mime_type).await {
+            total_size = stripped_size;
+            checksum = stripped_checksum;
+            exif_stripped = true;
+        }
+    }
+
+    let (file_id, disk_path) = if state.config.id_from_content {
+        let content_id = content_file_id(&owner, &checksum);
+        let now = Utc::now().timestamp();
+        let existing = state.file_registry.get(&content_id).await;
+        let reusable = existing.as_ref().is_some_and(|existing| {
+            existing.expires_at > now
+                && existing.password_hash == password_hash
+                && existing.public == public
+                && existing.worm == worm
+                && existing.download_name == download_name
+                && existing.description == description
+                && existing.bind_ip == bind_ip
+                && existing.max_concurrent == max_concurrent
+        });
+        let occupied = existing.is_some();
+        if let Some(existing) = existing.filter(|_| reusable) {
+            drop(partial);
+            let expires_at = requested_expiry.unwrap_or(Utc::now().timestamp() + ttl as i64);
+            let existing = if existing.expires_at < expires_at {
+                let refreshed = state
+                    .file_registry
+                    .update(&content_id, move |entry| {
+                        entry.expires_at = entry.expires_at.max(expires_at);
+                        entry.clone()
+                    })
+                    .await
+                    .unwrap_or(existing);
+                schedule_expiry(&state, &content_id, refreshed.expires_at);
+                refreshed
+            } else {
+                existing
+            };
+            info!("🧬 content_id_hit | {} | {}", content_id, existing.owner);
+            let ttl = existing.expires_at.saturating_sub(Utc::now().timestamp()).max(0) as u64;
+            let response = upload_response(&state, &headers, client, &existing, ttl);
+            if let Some(reservation) = idempotency {
+                reservation.complete(response.clone());
+            }
+            return Ok(upload_reply(&query, &headers, response));
+        }
+        let content_path = content_disk_path(&pool_root(&state.config, &disk_path), &content_id, &state.config);
+        if occupied {
+            info!("🧬 content_id_mismatch | {} | settings differ or entry expired, keeping {}", content_id, file_id);
+            (file_id, disk_path)
+        } else if state.readers.delete_pending(&content_id) {
+            warn!("⚠️ content_id_busy | {} | previous copy still being read, keeping {}", content_id, file_id);
+            (file_id, disk_path)
+        } else {
+            if let Some(dir) = content_path.parent().filter(|_| state.config.sharded_layout) {
+                fs::create_dir_all(dir).await.map_err(|_| ApiError::new(ErrorCode::WriteFailed))?;
+            }
+            match fs::hard_link(&disk_path, &content_path).await {
+                Ok(()) => {
+                    partial.commit();
+                    partial = PartialUpload::new(&content_path);
+                    if let Err(e) = fs::remove_file(&disk_path).await {
+                        warn!("⚠️ partial_cleanup_failed | {} | {}", disk_path.display(), e);
+                    }
+                    (content_id, content_path)
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                    warn!("⚠️ content_id_busy | {} | same content still being stored, keeping {}", content_id, file_id);
+                    (file_id, disk_path)
+                }
+                Err(_) => return Err(ApiError::new(ErrorCode::WriteFailed)),
+            }
+        }
+    } else {
+        (file_id, disk_path)
+    };
+
+    scan_upload(&state, &file_id, &original_filename, &disk_path).await?;
+
+    let thumb_path = if state.config.thumbnails == ThumbnailMode::Eager && is_thumbnailable_mime(&mime_type) {
+        create_thumbnail(&disk_path.to_string_lossy(), &mime_type, state.config.thumbnail_size).await
+    } else {
+        None
+    };
+    if let Some(thumb_path) = &thumb_path {
+        partial.track(thumb_path);
+    }
+
+    let encodings = if state.config.precompress && is_compressible_mime(&mime_type) {
+        for encoding in PRECOMPRESSED_ENCODINGS {
+            partial.track(variant_path(&disk_path.to_string_lossy(), encoding));
+        }
+        precompress_variants(&disk_path.to_string_lossy(), total_size).await
+    } else {
+        Vec::new()
+    };
+
+    let uploaded_at = Utc::now().timestamp();
+    let metadata = FileMetadata {
+        file_id: file_id.clone(),
+        original_name: original_filename.clone(),
+        disk_path: disk_path.to_string_lossy().to_string(),
+        mime_type: mime_type.clone(),
+        guessed_mime,
+        size: total_size,
+        uploaded_at,
+        expires_at: requested_expiry.unwrap_or(uploaded_at + ttl as i64),
+        owner,
+        request_id: headers
+            .get("x-request-id")
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_string(),
+        width,
+        height,
+        thumb_path,
+        checksum,
+        exif_stripped,
+        encodings,
+        password_hash,
+        public,
+        download_name,
+        worm,
+        download_count: 0,
+        last_accessed: None,
+        description,
+        bind_ip,
+        max_concurrent,
+        transcodes: Vec::new(),
+        client_name,
+        group_id: None,
+        group_position: 0,
+        charset: text_charset(&mime_type, &header_bytes, total_size),
+    };
+
+    sync_upload(&state.config, &disk_path).await?;
+    register_upload(&state, &metadata, &mut partial).await?;
+    state.metrics.observe_upload(started, total_size);
+
+    info!("✅ {} | {} | {}", original_filename, total_size, mime_type);
+    tracing::Span::current()
+        .record("file_id", file_id.as_str())
+        .record("bytes", total_size)
+        .record("mime", mime_type.as_str())
+        .record("duration_ms", started.elapsed().as_millis() as u64);
+
+    let response = upload_response(&state, &headers, client, &metadata, ttl);
+    if let Some(reservation) = idempotency {
+        reservation.complete(response.clone());
+    }
+
+    Ok(upload_reply(&query, &headers, response))
+}
+
+fn upload_response(state: &AppState, headers: &HeaderMap, client: IpAddr, metadata: &FileMetadata, ttl: u64) -> UploadResponse {
+    let base_url = request_base_url(headers, &state.config);
+    let bound_ip = metadata.bind_ip.then_some(client);
+    let view_url = generate_signed_url(
+        &base_url,
+        metadata,
+        LinkOptions::new("inline", metadata.expires_at).with_ip(bound_ip),
+        &state.config,
+    );
+    let download_url = generate_signed_url(
+        &base_url,
+        metadata,
+        LinkOptions::new("attachment", metadata.expires_at).with_ip(bound_ip),
+        &state.config,
+    );
+    UploadResponse {
+        id: metadata.file_id.clone(),
+        name: metadata.original_name.clone(),
+        size: metadata.size,
+        mime: metadata.mime_type.clone(),
+        width: metadata.width,
+        height: metadata.height,
+        view: view_url,
+        download: download_url,
+        share: share_link(&base_url, metadata, bound_ip, &state.config),
+        ttl,
+        uploaded_at: metadata.uploaded_at,
+        expires_at: metadata.expires_at,
+        checksum: metadata.checksum.clone(),
+        owner: metadata.owner.clone(),
+        delete_token: delete_token(metadata, &state.config),
+    }
+}
+
+fn upload_reply(query: &HashMap<String, String>, headers: &HeaderMap, response: UploadResponse) -> Response {
+    let plain = match query.get("format").map(String::as_str) {
+        Some("text") => true,
+        Some(_) => false,
+        None => accepts_first(headers, "text/plain"),
+    };
+    if plain {
+        ([(header::CONTENT_TYPE, "text/plain; charset=utf-8")], format!("{}\n", response.download)).into_response()
+    } else {
+        Json(response).into_response()
+    }
+}
+
+async fn create_pipe_handler(
+    State(state): State<AppState>,
+    ClientIp(client): ClientIp,
+    Query(query): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    if state.config.pipe == PipeMode::Off {
+        return Err(ApiError::new(ErrorCode::NotFound));
+    }
+    let (owner, limits) = authorize_pipe(&state, client, &headers)?;
+
+    let content_type = match query.get("content_type").map(|v| v.trim()).filter(|v| !v.is_empty()) {
+        Some(declared) => declared
+            .parse::<mime_guess::mime::Mime>()
+            .map_err(|_| ApiError::new(ErrorCode::InvalidContentType))?
+            .to_string(),
+        None => "application/octet-stream".to_string(),
+    };
+    if !is_allowed_mime(&content_type, &state.config) {
+        return Err(ApiError::new(ErrorCode::MimeNotAllowed).with_details(serde_json::json!({ "mime": content_type })));
+    }
+
+    let pipe_id = Uuid::new_v4().to_string();
+    let (tx, rx) = mpsc::channel(PIPE_CHANNEL_DEPTH);
+    state.pipes.lock().unwrap().insert(
+        pipe_id.clone(),
+        PipeSlot {
+            created: Instant::now(),
+            owner: owner.clone(),
+            content_type: content_type.clone(),
+            limits,
+            tx: Some(tx),
+            rx: Some(rx),
+        },
+    );
+    info!("🚰 pipe_open | {} | {} | {}", pipe_id, owner, content_type);
+
+    Ok(Json(PipeResponse {
+        url: format!("{}/pipe/{}", request_base_url(&headers, &state.config), pipe_id),
+        id: pipe_id,
+        expires_in: state.config.pipe_timeout,
+    })
+    .into_response())
+}
+
+fn authorize_pipe(state: &AppState, client: IpAddr, headers: &HeaderMap) -> Result<(String, UploadLimits), ApiError> {
+    check_rate_limit(&state.ip_limiter, "ip", &client.to_string())?;
+    let authenticated_owner = authenticate_owner(headers, state)?;
+    let limits = UploadLimits::resolve(&state.config, authenticated_owner.is_some())?;
+    let owner = authenticated_owner.unwrap_or_else(|| state.config.anon_owner.clone());
+    let limits = limits.for_owner(&owner, &state.config);
+    check_rate_limit(&state.owner_limiter, "owner", &owner)?;
+    Ok((owner, limits))
+}
+
+fn take_pipe_half<T>(
+    state: &AppState,
+    pipe_id: &str,
+    owner: &str,
+    take: impl FnOnce(&mut PipeSlot) -> Option<T>,
+) -> Result<(T, String, String, UploadLimits), ApiError> {
+    let mut pipes = state.pipes.lock().unwrap();
+    let slot = pipes.get_mut(pipe_id).ok_or_else(|| ApiError::new(ErrorCode::FileNotFound))?;
+    if slot.owner != owner {
+        warn!("⚠️ pipe_not_owner | {} | {}", pipe_id, owner);
+        return Err(ApiError::new(ErrorCode::NotOwner));
+    }
+    let half = take(slot).ok_or_else(|| ApiError::new(ErrorCode::PipeBusy))?;
+    let taken = (half, slot.owner.clone(), slot.content_type.clone(), slot.limits);
+    if slot.tx.is_none() && slot.rx.is_none() {
+        pipes.remove(pipe_id);
+    }
+    Ok(taken)
+}
+
+async fn pipe_read_handler(
+    State(state): State<AppState>,
+    ClientIp(client): ClientIp,
+    AxumPath(pipe_id): AxumPath<String>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let (reader, _) = authorize_pipe(&state, client, &headers)?;
+    let (rx, _, content_type, _) = take_pipe_half(&state, &pipe_id, &reader, |slot| slot.rx.take())?;
+    let wait = Duration::from_secs(state.config.pipe_timeout);
+    let chunks = futures_util::stream::unfold(rx, move |mut rx| async move {
+        match tokio::time::timeout(wait, rx.recv()).await {
+            Ok(chunk) => chunk.map(|chunk| (chunk, rx)),
+            Err(_) => Some((Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "pipe writer stalled")), rx)),
+        }
+    });
+
+    let mut response = Body::from_stream(chunks).into_response();
+    let headers = response.headers_mut();
+    if let Ok(value) = HeaderValue::from_str(&content_type) {
+        headers.insert(header::CONTENT_TYPE, value);
+    }
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    if !is_viewable_mime(&content_type, &state.config) {
+        headers.insert(header::CONTENT_DISPOSITION, HeaderValue::from_static("attachment"));
+    }
+    apply_response_headers(headers, &state.config);
+    Ok(response)
+}
+
+async fn pipe_write_handler(
+    State(state): State<AppState>,
+    ClientIp(client): ClientIp,
+    AxumPath(pipe_id): AxumPath<String>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<Response, ApiError> {
+    let (writer, _) = authorize_pipe(&state, client, &headers)?;
+    let (tx, owner, content_type, limits) = take_pipe_half(&state, &pipe_id, &writer, |slot| slot.tx.take())?;
+    let started = Instant::now();
+    let wait = Duration::from_secs(state.config.pipe_timeout);
+    let mut spool = match state.config.pipe {
+        PipeMode::Spool => {
+            let pool = select_pool(&state).ok_or_else(|| ApiError::new(ErrorCode::InsufficientStorage))?;
+            let disk_path = PathBuf::from(&pool).join(format!("{}.bin", pipe_id));
+            let file = File::create(&disk_path).await.map_err(|_| ApiError::new(ErrorCode::FileCreateFailed))?;
+            Some((PartialUpload::new(&disk_path), disk_path, BufWriter::with_capacity(state.config.buffer_size, file)))
+        }
+        _ => None,
+    };
+
+    let mut consumer = Some(tx);
+    let mut total_size: u64 = 0;
+    let mut header_bytes: Vec<u8> = Vec::with_capacity(HEADER_SNIFF_LEN);
+    let mut hasher = Sha256::new();
+    let mut stream = body.into_data_stream();
+    loop {
+        let chunk = match within_idle(state.config.upload_timeout, &pipe_id, stream.next()).await {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => break,
+            Err(e) => {
+                if let Some(tx) = &consumer {
+                    let _ = tx.try_send(Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "writer stalled")));
+                }
+                return Err(e);
+            }
+        };
+        let data = chunk.map_err(|e| {
+            if let Some(tx) = &consumer {
+                let _ = tx.try_send(Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, e.to_string())));
+            }
+            ApiError::new(ErrorCode::ChunkReadFailed)
+        })?;
+        total_size += data.len() as u64;
+        if total_size > limits.max_file_size {
+            if let Some(tx) = &consumer {
+                let _ = tx.try_send(Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "pipe exceeded the maximum upload size")));
+            }
+            return Err(file_too_large(limits.max_file_size));
+        }
+
+        if let Some((_, _, writer)) = &mut spool {
+            if header_bytes.len() < HEADER_SNIFF_LEN {
+                let take = (HEADER_SNIFF_LEN - header_bytes.len()).min(data.len());
+                header_bytes.extend_from_slice(&data[..take]);
+            }
+            hasher.update(&data);
+            writer.write_all(&data).await.map_err(|_| ApiError::new(ErrorCode::WriteFailed))?;
+        }
+
+        if let Some(tx) = &consumer {
+            let outcome = tokio::time::timeout(wait, tx.send(Ok(data))).await;
+            if !matches!(outcome, Ok(Ok(()))) {
+                let timed_out = outcome.is_err();
+                warn!("⚠️ pipe_reader_lost | {} | {} bytes | {}", pipe_id, total_size, if timed_out { "timeout" } else { "closed" });
+                state.pipes.lock().unwrap().remove(&pipe_id);
+                if spool.is_none() {
+                    return Err(ApiError::new(if timed_out { ErrorCode::PipeTimeout } else { ErrorCode::PipeClosed }));
+                }
+                consumer = None;
+            }
+        }
+    }
+
+    let Some((mut partial, disk_path, mut writer)) = spool.filter(|_| consumer.is_none()) else {
+        info!("🚰 pipe_delivered | {} | {} bytes | {}ms", pipe_id, total_size, started.elapsed().as_millis());
+        return Ok(Json(PipeResult { id: pipe_id, size: total_size, delivered: true, file: None }).into_response());
+    };
+
+    writer.flush().await.map_err(|_| ApiError::new(ErrorCode::FlushFailed))?;
+    let original_filename = String::from("unknown");
+    check_extension(&original_filename, &state.config)?;
+    let mime_type = correct_mime(&state.config, &pipe_id, content_type.clone(), &header_bytes);
+    if !is_allowed_mime(&mime_type, &state.config) {
+        warn!("⚠️ mime_not_allowed | {} | {}", pipe_id, mime_type);
+        return Err(ApiError::new(ErrorCode::MimeNotAllowed).with_details(serde_json::json!({ "mime": mime_type })));
+    }
+    if total_size < state.config.min_file_size {
+        return Err(ApiError::new(ErrorCode::FileTooSmall)
+            .with_details(serde_json::json!({ "size": total_size, "min_file_size": state.config.min_file_size })));
+    }
+    if let Some((class, limit)) = class_size_limit(&mime_type, &state.config) {
+        if total_size > limit {
+            return Err(class_too_large(class, limit));
+        }
+    }
+    scan_upload(&state, &pipe_id, &original_filename, &disk_path).await?;
+
+    let (width, height) = image_dimensions(&mime_type, &header_bytes);
+    let uploaded_at = Utc::now().timestamp();
+    let ttl = limits.for_type(&mime_type, &state.config).default_ttl;
+    let metadata = FileMetadata {
+        file_id: pipe_id.clone(),
+        original_name: original_filename.clone(),
+        disk_path: disk_path.to_string_lossy().to_string(),
+        mime_type: mime_type.clone(),
+        guessed_mime: content_type,
+        size: total_size,
+        uploaded_at,
+        expires_at: uploaded_at + ttl as i64,
+        owner,
+        request_id: headers
+            .get("x-request-id")
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_string(),
+        width,
+        height,
+        thumb_path: None,
+        checksum: hex::encode(hasher.finalize()),
+        exif_stripped: false,
+        encodings: Vec::new(),
+        password_hash: None,
+        public: false,
+        download_name: None,
+        worm: false,
+        download_count: 0,
+        last_accessed: None,
+        description: None,
+        bind_ip: false,
+        max_concurrent: None,
+        transcodes: Vec::new(),
+        client_name: None,
+        group_id: None,
+        group_position: 0,
+        charset: text_charset(&mime_type, &header_bytes, total_size),
+    };
+    sync_upload(&state.config, &disk_path).await?;
+    register_upload(&state, &metadata, &mut partial).await?;
+    state.metrics.observe_upload(started, total_size);
+    info!("💾 pipe_spooled | {} | {} bytes | {}", pipe_id, total_size, mime_type);
+
+    let base_url = request_base_url(&headers, &state.config);
+    let file = UploadResponse {
+        id: pipe_id.clone(),
+        name: original_filename,
+        size: total_size,
+        mime: mime_type,
+        width,
+        height,
+        view: generate_signed_url(&base_url, &metadata, LinkOptions::new("inline", metadata.expires_at), &state.config),
+        download: generate_signed_url(&base_url, &metadata, LinkOptions::new("attachment", metadata.expires_at), &state.config),
+        share: share_link(&base_url, &metadata, None, &state.config),
+        ttl,
+        uploaded_at,
+        expires_at: metadata.expires_at,
+        checksum: metadata.checksum.clone(),
+        owner: metadata.owner.clone(),
+        delete_token: delete_token(&metadata, &state.config),
+    };
+    Ok(Json(PipeResult { id: pipe_id, size: total_size, delivered: false, file: Some(file) }).into_response())
+}
+
+fn prune_pipes(state: &AppState) {
+    let wait = Duration::from_secs(state.config.pipe_timeout);
+    let mut pipes = state.pipes.lock().unwrap();
+    let before = pipes.len();
+    pipes.retain(|_, slot| slot.created.elapsed() < wait);
+    if pipes.len() < before {
+        info!("🧹 pipes_pruned | {}", before - pipes.len());
+    }
+}
+
+fn client_file_id(owner: &str, client_id: &str) -> String {
+    Uuid::new_v5(&Uuid::NAMESPACE_OID, format!("{}\n{}", owner, client_id).as_bytes()).to_string()
+}
+
+fn content_file_id(owner: &str, checksum: &str) -> String {
+    Uuid::new_v5(&Uuid::NAMESPACE_URL, format!("sha256:{}\n{}", checksum, owner).as_bytes()).to_string()
+}
+
+async fn put_file_handler(
+    State(state): State<AppState>,
+    ClientIp(client): ClientIp,
+    AxumPath(client_id): AxumPath<String>,
+    Query(query): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<Response, ApiError> {
+    let started = Instant::now();
+    check_rate_limit(&state.ip_limiter, "ip", &client.to_string())?;
+    let owner = authenticate_owner(&headers, &state)?.ok_or_else(|| ApiError::new(ErrorCode::Unauthorized))?;
+    check_rate_limit(&state.owner_limiter, "owner", &owner)?;
+    let limits = UploadLimits::resolve(&state.config, true)?.for_owner(&owner, &state.config);
+    if client_id.is_empty()
+        || client_id.len() > 128
+        || !client_id.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+    {
+        return Err(ApiError::new(ErrorCode::InvalidClientId));
+    }
+
+    let file_id = client_file_id(&owner, &client_id);
+    let now = Utc::now().timestamp();
+    let previous = state.file_registry.get(&file_id).await;
+    if let Some(previous) = previous.as_ref().filter(|m| m.expires_at > now) {
+        if previous.owner != owner {
+            return Err(ApiError::new(ErrorCode::NotOwner));
+        }
+        if previous.worm {
+            return Err(ApiError::new(ErrorCode::WormProtected));
+        }
+    }
+
+    let requested_expiry = query
+        .get("expires_at")
+        .map(String::as_str)
+        .or(headers.get("x-expires-at").and_then(|v| v.to_str().ok()))
+        .map(|value| parse_expires_at(value, limits.max_lifetime))
+        .transpose()?;
+    let requested_ttl = match query.get("ttl") {
+        Some(_) if requested_expiry.is_some() => {
+            return Err(conflicting_field("ttl"));
+        }
+        Some(value) => match value.trim().parse::<u64>() {
+            Ok(ttl) if ttl > 0 && ttl <= limits.max_lifetime => Some(ttl),
+            _ => {
+                return Err(ApiError::new(ErrorCode::InvalidTtl).with_details(serde_json::json!({ "max": limits.max_lifetime })));
+            }
+        },
+        None => None,
+    };
+    let original_filename = sanitize_filename(&client_id, state.config.max_filename_len);
+    check_extension(&original_filename, &state.config)?;
+    let guessed_mime = mime_guess::from_path(&original_filename).first_or_octet_stream().to_string();
+    let mime_type = match headers.get(header::CONTENT_TYPE).and_then(|v| v.to_str().ok()).map(str::trim) {
+        Some(declared) if !declared.is_empty() => declared
+            .parse::<mime_guess::mime::Mime>()
+            .map_err(|_| ApiError::new(ErrorCode::InvalidContentType))?
+            .to_string(),
+        _ => guessed_mime.clone(),
+    };
+    if !is_allowed_mime(&mime_type, &state.config) {
+        warn!("⚠️ mime_not_allowed | {} | {}", original_filename, mime_type);
+        return Err(ApiError::new(ErrorCode::MimeNotAllowed).with_details(serde_json::json!({ "mime": mime_type })));
+    }
+    let class_limit = class_size_limit(&mime_type, &state.config);
+
+    let pool = select_pool(&state).ok_or_else(|| ApiError::new(ErrorCode::InsufficientStorage))?;
+    if let Some(declared) = headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok())
+    {
+        check_declared_size(&state, &pool, declared, limits.max_file_size, class_limit)?;
+    }
+    let revision = Uuid::new_v4().simple().to_string();
+    let disk_path = PathBuf::from(&pool).join(format!("{}.{}.bin", file_id, &revision[..12]));
+    let file = File::create(&disk_path).await.map_err(|_| ApiError::new(ErrorCode::FileCreateFailed))?;
+    let mut partial = PartialUpload::new(&disk_path);
+    let mut writer = BufWriter::with_capacity(state.config.buffer_size, file);
+
+    let mut total_size: u64 = 0;
+    let mut header_bytes: Vec<u8> = Vec::with_capacity(HEADER_SNIFF_LEN);
+    let mut hasher = Sha256::new();
+    let mut stream = body.into_data_stream();
+    while let Some(chunk) = within_idle(state.config.upload_timeout, &file_id, stream.next()).await? {
+        let data = chunk.map_err(|_| ApiError::new(ErrorCode::ChunkReadFailed))?;
+        total_size += data.len() as u64;
+        if total_size > limits.max_file_size {
+            return Err(file_too_large(limits.max_file_size));
+        }
+        if let Some((class, limit)) = class_limit {
+            if total_size > limit {
+                return Err(class_too_large(class, limit));
+            }
+        }
+        if header_bytes.len() < HEADER_SNIFF_LEN {
+            let take = (HEADER_SNIFF_LEN - header_bytes.len()).min(data.len());
+            header_bytes.extend_from_slice(&data[..take]);
+        }
+        hasher.update(&data);
+        writer.write_all(&data).await.map_err(|_| ApiError::new(ErrorCode::WriteFailed))?;
+    }
+    writer.flush().await.map_err(|_| ApiError::new(ErrorCode::FlushFailed))?;
+    if total_size < state.config.min_file_size {
+        return Err(ApiError::new(ErrorCode::FileTooSmall)
+            .with_details(serde_json::json!({ "size": total_size, "min_file_size": state.config.min_file_size })));
+    }
+    let mime_type = correct_mime(&state.config, &file_id, mime_type, &header_bytes);
+    if !is_allowed_mime(&mime_type, &state.config) {
+        warn!("⚠️ mime_not_allowed | {} | {}", original_filename, mime_type);
+        return Err(ApiError::new(ErrorCode::MimeNotAllowed).with_details(serde_json::json!({ "mime": mime_type })));
+    }
+    scan_upload(&state, &file_id, &original_filename, &disk_path).await?;
+    let ttl = requested_ttl
+        .unwrap_or_else(|| expiry_ttl(requested_expiry, limits.for_type(&mime_type, &state.config).default_ttl));
+
+    let (width, height) = image_dimensions(&mime_type, &header_bytes);
+    let uploaded_at = Utc::now().timestamp();
+    let metadata = FileMetadata {
+        file_id: file_id.clone(),
+        original_name: original_filename.clone(),
+        disk_path: disk_path.to_string_lossy().to_string(),
+        mime_type: mime_type.clone(),
+        guessed_mime,
+        size: total_size,
+        uploaded_at,
+        expires_at: requested_expiry.unwrap_or(uploaded_at + ttl as i64),
+        owner,
+        request_id: headers
+            .get("x-request-id")
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_string(),
+        width,
+        height,
+        thumb_path: None,
+        checksum: hex::encode(hasher.finalize()),
+        exif_stripped: false,
+        encodings: Vec::new(),
+        password_hash: None,
+        public: false,
+        download_name: None,
+        worm: false,
+        download_count: 0,
+        last_accessed: None,
+        description: None,
+        bind_ip: false,
+        max_concurrent: None,
+        transcodes: Vec::new(),
+        client_name: None,
+        group_id: None,
+        group_position: 0,
+        charset: text_charset(&mime_type, &header_bytes, total_size),
+    };
+
+    sync_upload(&state.config, &disk_path).await?;
+    match previous {
+        None => register_upload(&state, &metadata, &mut partial).await?,
+        Some(previous) => {
+            let grown = total_size.saturating_sub(previous.size);
+            if grown > 0 && !reserve_storage(&state, grown).await {
+                let max_total = state.config.max_total_bytes.unwrap_or_default();
+                return Err(ApiError::new(ErrorCode::StorageCapReached)
+                    .with_details(serde_json::json!({ "max_total_bytes": max_total })));
+            }
+            if let Err(e) = state.file_registry.insert(metadata.clone()).await {
+                error!("❌ registry_insert_failed | {} | {}", file_id, e);
+                release_storage(&state, grown);
+                return Err(ApiError::new(ErrorCode::RegistryFailed));
+            }
+            partial.commit();
+            release_storage(&state, previous.size.saturating_sub(total_size));
+            schedule_expiry(&state, &file_id, metadata.expires_at);
+            publish_event(&state, || FileEvent {
+                event: "upload",
+                file_id: file_id.clone(),
+                owner: metadata.owner.clone(),
+                size: Some(total_size),
+                mime: Some(mime_type.clone()),
+                status: None,
+                reason: Some("replaced"),
+                timestamp: uploaded_at,
+            });
+            if let Some(previous) = state.readers.defer_delete(previous) {
+                unlink_files(&previous).await;
+            }
+        }
+    }
+    state.metrics.observe_upload(started, total_size);
+    info!("♻️ put | {} | {} | {} | {}", client_id, file_id, total_size, mime_type);
+
+    let base_url = request_base_url(&headers, &state.config);
+    Ok(Json(UploadResponse {
+        id: file_id.clone(),
+        name: original_filename,
+        size: total_size,
+        mime: mime_type,
+        width,
+        height,
+        view: generate_signed_url(&base_url, &metadata, LinkOptions::new("inline", metadata.expires_at), &state.config),
+        download: generate_signed_url(&base_url, &metadata, LinkOptions::new("attachment", metadata.expires_at), &state.config),
+        share: share_link(&base_url, &metadata, None, &state.config),
+        ttl,
+        uploaded_at,
+        expires_at: metadata.expires_at,
+        checksum: metadata.checksum.clone(),
+        owner: metadata.owner.clone(),
+        delete_token: delete_token(&metadata, &state.config),
+    })
+    .into_response())
+}
+
+struct ArchiveEntry {
+    file_id: String,
+    name: String,
+    disk_path: PathBuf,
+    size: u64,
+    checksum: String,
+    header_bytes: Vec<u8>,
+}
+
+fn extract_archive(
+    archive_path: &std::path::Path,
+    pool: &str,
+    max_entries: usize,
+    max_entry_size: u64,
+    max_total: u64,
+    max_name_len: usize,
+) -> Result<Vec<(ArchiveEntry, PartialUpload)>, ApiError> {
+    use std::io::{Read, Write};
+
+    let file = std::fs::File::open(archive_path).map_err(|_| ApiError::new(ErrorCode::InvalidArchive))?;
+    let mut archive = zip::ZipArchive::new(file).map_err(|_| ApiError::new(ErrorCode::InvalidArchive))?;
+    let mut extracted = Vec::new();
+    let mut total_size: u64 = 0;
+    let mut buf = vec![0u8; 64 * 1024];
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index).map_err(|_| ApiError::new(ErrorCode::InvalidArchive))?;
+        if entry.is_dir() {
+            continue;
+        }
+        let Some(path) = entry.enclosed_name() else {
+            warn!("⚠️ archive_unsafe_path | {}", entry.name());
+            return Err(ApiError::new(ErrorCode::InvalidArchive).with_details(serde_json::json!({ "entry": entry.name() })));
+        };
+        if extracted.len() >= max_entries {
+            return Err(ApiError::new(ErrorCode::TooManyEntries).with_details(serde_json::json!({ "limit": max_entries })));
+        }
+        let name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        let name = sanitize_filename(&name, max_name_len);
+        let file_id = Uuid::new_v4().to_string();
+        let disk_path = PathBuf::from(pool).join(format!("{}.bin", file_id));
+        let output = std::fs::File::create(&disk_path).map_err(|_| ApiError::new(ErrorCode::FileCreateFailed))?;
+        let partial = PartialUpload::new(&disk_path);
+        let mut writer = std::io::BufWriter::new(output);
+        let mut size: u64 = 0;
+        let mut header_bytes: Vec<u8> = Vec::with_capacity(HEADER_SNIFF_LEN);
+        let mut hasher = Sha256::new();
+        loop {
+            let read = entry.read(&mut buf).map_err(|_| ApiError::new(ErrorCode::InvalidArchive))?;
+            if read == 0 {
+                break;
+            }
+            size += read as u64;
+            total_size += read as u64;
+            if size > max_entry_size {
+                return Err(file_too_large(max_entry_size));
+            }
+            if total_size > max_total {
+                warn!("⚠️ archive_too_large | {} | limit {}", archive_path.display(), max_total);
+                return Err(ApiError::new(ErrorCode::ArchiveTooLarge).with_details(serde_json::json!({ "limit": max_total })));
+            }
+            if header_bytes.len() < HEADER_SNIFF_LEN {
+                let take = (HEADER_SNIFF_LEN - header_bytes.len()).min(read);
+                header_bytes.extend_from_slice(&buf[..take]);
+            }
+            hasher.update(&buf[..read]);
+            writer.write_all(&buf[..read]).map_err(|_| ApiError::new(ErrorCode::WriteFailed))?;
+        }
+        writer.flush().map_err(|_| ApiError::new(ErrorCode::FlushFailed))?;
+        let entry = ArchiveEntry { file_id, name, disk_path, size, checksum: hex::encode(hasher.finalize()), header_bytes };
+        extracted.push((entry, partial));
+    }
+    Ok(extracted)
+}
+
+async fn archive_upload_handler(
+    State(state): State<AppState>,
+    ClientIp(client): ClientIp,
+    Query(query): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<Response, ApiError> {
+    if state.config.archive_max_entries == 0 {
+        return Err(ApiError::new(ErrorCode::NotFound));
+    }
+    let started = Instant::now();
+    check_rate_limit(&state.ip_limiter, "ip", &client.to_string())?;
+    let authenticated_owner = authenticate_owner(&headers, &state)?;
+    let limits = UploadLimits::resolve(&state.config, authenticated_owner.is_some())?;
+    let owner = authenticated_owner.unwrap_or_else(|| state.config.anon_owner.clone());
+    let limits = limits.for_owner(&owner, &state.config);
+    check_rate_limit(&state.owner_limiter, "owner", &owner)?;
+
+    let requested_expiry = query
+        .get("expires_at")
+        .map(String::as_str)
+        .or(headers.get("x-expires-at").and_then(|v| v.to_str().ok()))
+        .map(|value| parse_expires_at(value, limits.max_lifetime))
+        .transpose()?;
+    let requested_ttl = match query.get("ttl") {
+        Some(_) if requested_expiry.is_some() => {
+            return Err(conflicting_field("ttl"));
+        }
+        Some(value) => match value.trim().parse::<u64>() {
+            Ok(ttl) if ttl > 0 && ttl <= limits.max_lifetime => Some(ttl),
+            _ => {
+                return Err(ApiError::new(ErrorCode::InvalidTtl).with_details(serde_json::json!({ "max": limits.max_lifetime })));
+            }
+        },
+        None => None,
+    };
+
+    let pool = select_pool(&state).ok_or_else(|| ApiError::new(ErrorCode::InsufficientStorage))?;
+    if let Some(declared) = headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok())
+    {
+        check_declared_size(&state, &pool, declared, limits.max_file_size, None)?;
+    }
+    let spool_id = Uuid::new_v4().to_string();
+    let spool_path = PathBuf::from(&pool).join(format!("{}.zip.tmp", spool_id));
+    let file = File::create(&spool_path).await.map_err(|_| ApiError::new(ErrorCode::FileCreateFailed))?;
+    let mut spool = PartialUpload::new(&spool_path);
+    let mut writer = BufWriter::with_capacity(state.config.buffer_size, file);
+    let mut archive_size: u64 = 0;
+    let mut stream = body.into_data_stream();
+    while let Some(chunk) = within_idle(state.config.upload_timeout, &spool_id, stream.next()).await? {
+        let data = chunk.map_err(|_| ApiError::new(ErrorCode::ChunkReadFailed))?;
+        archive_size += data.len() as u64;
+        if archive_size > limits.max_file_size {
+            return Err(file_too_large(limits.max_file_size));
+        }
+        writer.write_all(&data).await.map_err(|_| ApiError::new(ErrorCode::WriteFailed))?;
+    }
+    writer.flush().await.map_err(|_| ApiError::new(ErrorCode::FlushFailed))?;
+    drop(writer);
+
+    let extracted = {
+        let (archive_path, pool) = (spool_path.clone(), pool.clone());
+        let (max_entries, max_total, max_name_len) =
+            (state.config.archive_max_entries, state.config.archive_max_bytes, state.config.max_filename_len);
+        let max_entry_size = limits.max_file_size;
+        tokio::task::spawn_blocking(move || {
+            extract_archive(&archive_path, &pool, max_entries, max_entry_size, max_total, max_name_len)
+        })
+        .await
+        .map_err(|_| ApiError::new(ErrorCode::InvalidArchive))
+    };
+    spool.commit();
+    if let Err(e) = fs::remove_file(&spool_path).await {
+        warn!("⚠️ archive_spool_cleanup_failed | {} | {}", spool_path.display(), e);
+    }
+    let extracted = extracted??;
+    if extracted.is_empty() {
+        return Err(ApiError::new(ErrorCode::MissingFile));
+    }
+
+    let request_id = headers
+        .get("x-request-id")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    let group_id = (query.get("group").map(|v| v.trim()) == Some("true")).then(|| Uuid::new_v4().to_string());
+    let best_effort = state.config.multi_upload_mode == MultiUploadMode::BestEffort;
+    let mut prepared = Vec::with_capacity(extracted.len());
+    let mut results: Vec<(String, Result<(), ApiError>)> = Vec::with_capacity(extracted.len());
+    for (position, (entry, partial)) in extracted.into_iter().enumerate() {
+        let entry_name = entry.name.clone();
+        let outcome: Result<_, ApiError> = async {
+            check_extension(&entry.name, &state.config)?;
+            let guessed_mime = mime_guess::from_path(&entry.name).first_or_octet_stream().to_string();
+            let mime_type = correct_mime(&state.config, &entry.file_id, guessed_mime.clone(), &entry.header_bytes);
+            if !is_allowed_mime(&mime_type, &state.config) {
+                warn!("⚠️ mime_not_allowed | {} | {}", entry.name, mime_type);
+                return Err(ApiError::new(ErrorCode::MimeNotAllowed)
+                    .with_details(serde_json::json!({ "mime": mime_type, "entry": entry.name })));
+            }
+            if let Some((class, limit)) =
+                class_size_limit(&mime_type, &state.config).filter(|(_, limit)| entry.size > *limit)
+            {
+                return Err(class_too_large(class, limit));
+            }
+            if entry.size < state.config.min_file_size {
+                return Err(ApiError::new(ErrorCode::FileTooSmall).with_details(serde_json::json!({
+                    "size": entry.size,
+                    "min_file_size": state.config.min_file_size,
+                    "entry": entry.name,
+                })));
+            }
+            scan_upload(&state, &entry.file_id, &entry.name, &entry.disk_path).await?;
+            sync_upload(&state.config, &entry.disk_path).await?;
+            let ttl = requested_ttl
+                .unwrap_or_else(|| expiry_ttl(requested_expiry, limits.for_type(&mime_type, &state.config).default_ttl));
+
+            let (width, height) = image_dimensions(&mime_type, &entry.header_bytes);
+            let uploaded_at = Utc::now().timestamp();
+            let metadata = FileMetadata {
+                file_id: entry.file_id,
+                original_name: entry.name,
+                disk_path: entry.disk_path.to_string_lossy().to_string(),
+                charset: text_charset(&mime_type, &entry.header_bytes, entry.size),
+                mime_type,
+                guessed_mime,
+                size: entry.size,
+                uploaded_at,
+                expires_at: requested_expiry.unwrap_or(uploaded_at + ttl as i64),
+                owner: owner.clone(),
+                request_id: request_id.clone(),
+                width,
+                height,
+                thumb_path: None,
+                checksum: entry.checksum,
+                exif_stripped: false,
+                encodings: Vec::new(),
+                password_hash: None,
+                public: false,
+                download_name: None,
+                worm: false,
+                download_count: 0,
+                last_accessed: None,
+                description: None,
+                bind_ip: false,
+                max_concurrent: None,
+                transcodes: Vec::new(),
+                client_name: None,
+                group_id: group_id.clone(),
+                group_position: position,
+            };
+            Ok((metadata, ttl, partial))
+        }
+        .await;
+        match outcome {
+            Ok(ready) => {
+                results.push((entry_name, Ok(())));
+                prepared.push(ready);
+            }
+            Err(e) if best_effort => {
+                warn!("⚠️ archive_entry_rejected | {} | {}", entry_name, e.body.code.as_str());
+                results.push((entry_name, Err(e)));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    let mut registered: Vec<(FileMetadata, u64)> = Vec::with_capacity(prepared.len());
+    let mut slots: Vec<Result<usize, ApiError>> = Vec::with_capacity(prepared.len());
+    for (metadata, ttl, mut partial) in prepared {
+        if let Err(e) = register_upload(&state, &metadata, &mut partial).await {
+            if best_effort {
+                warn!("⚠️ archive_entry_rejected | {} | {}", metadata.original_name, e.body.code.as_str());
+                slots.push(Err(e));
+                continue;
+            }
+            for (previous, _) in &registered {
+                delete_file(&state, &previous.file_id, "archive_rollback").await;
+            }
+            return Err(e);
+        }
+        state.metrics.observe_upload(started, metadata.size);
+        slots.push(Ok(registered.len()));
+        registered.push((metadata, ttl));
+    }
+    let total_size: u64 = registered.iter().map(|(m, _)| m.size).sum();
+    info!("📦 archive | {} | {} files | {} bytes", owner, registered.len(), total_size);
+
+    let responses: Vec<UploadResponse> =
+        registered.iter().map(|(metadata, ttl)| upload_response(&state, &headers, client, metadata, *ttl)).collect();
+    let group = group_id.filter(|_| !registered.is_empty()).map(|id| {
+        let expires_at = registered.iter().map(|(m, _)| m.expires_at).max().unwrap_or_default();
+        let base_url = request_base_url(&headers, &state.config);
+        let url = group_link(&base_url, &id, &owner, expires_at, &state.config);
+        info!("🗂️ group_created | {} | {} files", id, responses.len());
+        GroupLink { id, url, expires_at }
+    });
+    if best_effort {
+        let mut slots = slots.into_iter();
+        let files = results
+            .into_iter()
+            .map(|(name, outcome)| match outcome.and_then(|_| slots.next().expect("one slot per prepared entry")) {
+                Ok(index) => {
+                    ArchiveEntryResult { name, status: StatusCode::OK.as_u16(), file: Some(responses[index].clone()), error: None }
+                }
+                Err(e) => ArchiveEntryResult { name, status: e.body.code.status().as_u16(), file: None, error: Some(e.body) },
+            })
+            .collect();
+        return Ok((StatusCode::MULTI_STATUS, Json(ArchiveMultiStatus { group, files })).into_response());
+    }
+    match group {
+        Some(group) => Ok(Json(ArchiveGroupResponse { group, files: responses }).into_response()),
+        None => Ok(Json(responses).into_response()),
+    }
+}
+
+#[derive(Serialize)]
+struct ArchiveEntryResult {
+    name: String,
+    status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file: Option<UploadResponse>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<ErrorResponse>,
+}
+
+#[derive(Serialize)]
+struct ArchiveMultiStatus {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    group: Option<GroupLink>,
+    files: Vec<ArchiveEntryResult>,
+}
+
+#[derive(Serialize)]
+struct GroupLink {
+    id: String,
+    url: String,
+    expires_at: i64,
+}
+
+#[derive(Serialize)]
+struct ArchiveGroupResponse {
+    group: GroupLink,
+    files: Vec<UploadResponse>,
+}
+
+#[derive(Serialize)]
+struct GroupMember {
+    id: String,
+    name: String,
+    size: u64,
+    mime: String,
+    checksum: String,
+    expires_at: i64,
+    view: String,
+    download: String,
+}
+
+#[derive(Serialize)]
+struct GroupResponse {
+    id: String,
+    expires_at: i64,
+    files: Vec<GroupMember>,
+}
+
+async fn group_handler(
+    State(state): State<AppState>,
+    ClientIp(client): ClientIp,
+    AxumPath(group_id): AxumPath<String>,
+    Query(params): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let signed_params = verify_signed_request(&state, client, &group_id, &params)?;
+    let signed = !signed_params.signature.is_empty();
+    if signed && signed_params.file_type != GROUP_LINK_TYPE {
+        warn!("⚠️ type_mismatch | {} | signed={} | stored=group", group_id, signed_params.file_type);
+        return Err(ApiError::new(ErrorCode::TypeMismatch));
+    }
+
+    let members: Vec<FileMetadata> = state
+        .file_registry
+        .find_by_group(&group_id, Utc::now().timestamp())
+        .await
+        .into_iter()
+        .filter(|m| !signed || m.owner == signed_params.owner)
+        .collect();
+    if members.is_empty() {
+        return Err(ApiError::new(ErrorCode::FileNotFound));
+    }
+
+    let base_url = request_base_url(&headers, &state.config);
+    let files: Vec<GroupMember> = members
+        .iter()
+        .map(|metadata| {
+            let expires_at = effective_expiry(&signed_params, metadata.expires_at);
+            let bound_ip = metadata.bind_ip.then_some(client);
+            GroupMember {
+                id: metadata.file_id.clone(),
+                name: metadata.original_name.clone(),
+                size: metadata.size,
+                mime: metadata.mime_type.clone(),
+                checksum: metadata.checksum.clone(),
+                expires_at: metadata.expires_at,
+                view: generate_signed_url(&base_url, metadata, LinkOptions::new("inline", expires_at).with_ip(bound_ip), &state.config),
+                download: generate_signed_url(
+                    &base_url,
+                    metadata,
+                    LinkOptions::new("attachment", expires_at).with_ip(bound_ip),
+                    &state.config,
+                ),
+            }
+        })
+        .collect();
+    let expires_at = members.iter().map(|m| m.expires_at).max().unwrap_or_default();
+    info!("🗂️ group | {} | {} files", group_id, files.len());
+
+    let mut response = if accepts_first(&headers, "text/html") {
+        let rows: String = files
+            .iter()
+            .map(|file| {
+                format!(
+                    "<li><a href=\"{}\">{}</a> <small>{} bytes</small> <a href=\"{}\">download</a></li>",
+                    html_escape(&file.view),
+                    html_escape(&file.name),
+                    file.size,
+                    html_escape(&file.download)
+                )
+            })
+            .collect();
+        Html(format!(
+            "<!doctype html><html><head><meta charset=\"utf-8\"><meta name=\"viewport\" content=\"width=device-width, initial-scale=1\"><title>{} files</title><style>body{{font:15px/1.6 sans-serif;margin:24px}}small{{color:#666}}</style></head><body><ul>{}</ul></body></html>",
+            files.len(),
+            rows
+        ))
+        .into_response()
+    } else {
+        Json(GroupResponse { id: group_id, expires_at, files }).into_response()
+    };
+    response.headers_mut().insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    response
+        .headers_mut()
+        .insert(header::REFERRER_POLICY, HeaderValue::from_static("no-referrer"));
+    Ok(response)
+}
+
+async fn serve_file(
+    State(state): State<AppState>,
+    ClientIp(client): ClientIp,
+    AxumPath(file_id): AxumPath<String>,
+    Query(params): Query<HashMap<String, String>>,
+    request_headers: HeaderMap,
+) -> Response {
+    serve_and_audit(state, client, file_id, params, request_headers, false).await
+}
+
+async fn serve_public(
+    State(state): State<AppState>,
+    ClientIp(client): ClientIp,
+    AxumPath(file_id): AxumPath<String>,
+    Query(params): Query<HashMap<String, String>>,
+    request_headers: HeaderMap,
+) -> Response {
+    serve_and_audit(state, client, file_id, params, request_headers, true).await
+}
+
+async fn serve_and_audit(
+    state: AppState,
+    client: IpAddr,
+    file_id: String,
+    params: HashMap<String, String>,
+    request_headers: HeaderMap,
+    public: bool,
+) -> Response {
+    let started = Instant::now();
+    let span = tracing::info_span!("serve", file_id = %file_id, status = Empty, bytes = Empty, range = Empty, cache = Empty);
+    let result = serve_file_inner(&state, client, &file_id, &params, &request_headers, public)
+        .instrument(span.clone())
+        .await;
+    record_serve_span(&span, &result);
+    if let Some(response) = result.as_ref().ok().filter(|r| r.status().is_success()) {
+        publish_event(&state, || FileEvent {
+            event: "download",
+            file_id: file_id.clone(),
+            owner: response.extensions().get::<ServedOwner>().map(|o| o.0.clone()).unwrap_or_default(),
+            size: response
+                .headers()
+                .get(header::CONTENT_LENGTH)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.parse().ok()),
+            mime: None,
+            status: Some(response.status().as_u16()),
+            reason: None,
+            timestamp: Utc::now().timestamp(),
+        });
+    }
+    let (response, result) = match result {
+        Ok(response) => (time_download(&state.metrics, started, response), "ok".to_string()),
+        Err(e) => {
+            let code = e.body.code.as_str().into_owned();
+            (error_response(&state, &params, e), code)
+        }
+    };
+    if state.audit_tx.is_none() {
+        return log_access(&state.config, client, &file_id, started, response);
+    }
+
+    let response = log_access(&state.config, client, &file_id, started, response);
+    let Some(audit_tx) = &state.audit_tx else {
+        return response;
+    };
+    let owner = response
+        .extensions()
+        .get::<ServedOwner>()
+        .map(|o| o.0.clone())
+        .or_else(|| params.get("sz-owner").cloned())
+        .unwrap_or_default();
+    let bytes = response
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+        .filter(|_| response.status().is_success())
+        .unwrap_or(0);
+    let record = AuditRecord {
+        timestamp: Utc::now().timestamp(),
+        action: "download",
+        file_id,
+        owner,
+        client_ip: client.to_string(),
+        status: response.status().as_u16(),
+        result,
+        bytes,
+        files: None,
+        request_id: request_headers
+            .get("x-request-id")
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_string(),
+    };
+    if audit_tx.send(record).is_err() {
+        warn!("⚠️ audit_log_closed");
+    }
+    response
+}
+
+fn error_response(state: &AppState, params: &HashMap<String, String>, error: ApiError) -> Response {
+    let Some(fallback) = &state.fallback_image else {
+        return error.into_response();
+    };
+    let wants_image = params
+        .get("sz-type")
+        .is_some_and(|t| mime_essence(t).starts_with("image/"));
+    if !wants_image || !matches!(error.body.code, ErrorCode::FileNotFound | ErrorCode::FileExpired | ErrorCode::LinkExpired) {
+        return error.into_response();
+    }
+
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CONTENT_TYPE, fallback.mime_type.clone());
+    headers.insert(header::CONTENT_LENGTH, HeaderValue::from(fallback.body.len()));
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    apply_response_headers(&mut headers, &state.config);
+    (error.body.code.status(), headers, fallback.body.clone()).into_response()
+}
+
+fn record_serve_span(span: &tracing::Span, result: &Result<Response, ApiError>) {
+    let response = match result {
+        Ok(response) => response,
+        Err(e) => {
+            span.record("status", e.body.code.status().as_u16());
+            return;
+        }
+    };
+    let headers = response.headers();
+    span.record("status", response.status().as_u16());
+    if let Some(bytes) = headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok())
+    {
+        span.record("bytes", bytes);
+    }
+    if let Some(range) = headers.get(header::CONTENT_RANGE).and_then(|v| v.to_str().ok()) {
+        span.record("range", range);
+    }
+}
+
+struct AccessEntry {
+    level: tracing::Level,
+    client_ip: IpAddr,
+    file_id: String,
+    status: u16,
+    range: Option<String>,
+    bytes: u64,
+    started: Instant,
+}
+
+impl Drop for AccessEntry {
+    fn drop(&mut self) {
+        let duration_ms = self.started.elapsed().as_millis() as u64;
+        let range = self.range.as_deref().unwrap_or("-");
+        if self.level == tracing::Level::DEBUG {
+            tracing::debug!(client_ip = %self.client_ip, file_id = %self.file_id, status = self.status, bytes = self.bytes, range, duration_ms, "📥 access");
+        } else {
+            info!(client_ip = %self.client_ip, file_id = %self.file_id, status = self.status, bytes = self.bytes, range, duration_ms, "📥 access");
+        }
+    }
+}
+
+fn log_access(config: &Config, client: IpAddr, file_id: &str, started: Instant, response: Response) -> Response {
+    let Some(level) = config.access_log else {
+        return response;
+    };
+
+    let mut entry = AccessEntry {
+        level,
+        client_ip: client,
+        file_id: file_id.to_string(),
+        status: response.status().as_u16(),
+        range: response
+            .headers()
+            .get(header::CONTENT_RANGE)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_string()),
+        bytes: 0,
+        started,
+    };
+    let (parts, body) = response.into_parts();
+    let body = Body::from_stream(body.into_data_stream().map(move |chunk| {
+        let entry = &mut entry;
+        if let Ok(chunk) = &chunk {
+            entry.bytes += chunk.len() as u64;
+        }
+        chunk
+    }));
+    Response::from_parts(parts, body)
+}
+
+async fn serve_file_inner(
+    state: &AppState,
+    client: IpAddr,
+    file_id: &str,
+    params: &HashMap<String, String>,
+    request_headers: &HeaderMap,
+    public: bool,
+) -> Result<Response, ApiError> {
+    let signed_params = if public {
+        unsigned_params(file_id, params)?
+    } else {
+        verify_signed_request(state, client, file_id, params)?
+    };
+    check_referer(request_headers, &signed_params.mode, &state.config)?;
+
+    let mut reader = state.readers.acquire(file_id);
+    let metadata = state
+        .file_registry
+        .get(file_id)
+        .await
+        .filter(|m| !public || m.public)
+        .ok_or_else(|| ApiError::new(ErrorCode::FileNotFound))?;
+    check_not_expired(&metadata, &state.config)?;
+
+    check_signed_type(&signed_params, &metadata)?;
+    check_signed_owner(&signed_params, &metadata)?;
+    check_password(state, client, &metadata, request_headers)?;
+
+    let transcoded = match requested_transcode(params, &metadata, &state.config)? {
+        Some(format) => transcoded_variant(state, &metadata, format).await,
+        None => None,
+    };
+    let metadata = match &transcoded {
+        Some((variant, _)) => variant.clone(),
+        None => metadata,
+    };
+
+    let encoding = if request_headers.contains_key(header::RANGE) || transcoded.is_some() {
+        None
+    } else {
+        negotiate_encoding(request_headers, &metadata.encodings)
+    };
+    if is_not_modified(request_headers, &metadata, encoding) {
+        let mut headers = HeaderMap::new();
+        insert_validators(&mut headers, &metadata, encoding);
+        headers.insert(header::CACHE_CONTROL, cache_control_for(&state.config, &signed_params, &metadata.mime_type, metadata.expires_at));
+        insert_expiry_headers(&mut headers, &signed_params, metadata.expires_at);
+        insert_vary(&mut headers, &metadata, &signed_params.mode, &state.config);
+        apply_response_headers(&mut headers, &state.config);
+        let mut response = (StatusCode::NOT_MODIFIED, headers).into_response();
+        response.extensions_mut().insert(ServedOwner(metadata.owner.clone()));
+        tracing::Span::current().record("cache", "not_modified");
+        return Ok(response);
+    }
+
+    let limit = metadata
+        .max_concurrent
+        .or((state.config.max_concurrent_downloads > 0).then_some(state.config.max_concurrent_downloads));
+    if let Err(limit) = reader.begin_download(limit) {
+        warn!("🚥 download_busy | {} | limit {}", file_id, limit);
+        return Err(ApiError::new(ErrorCode::DownloadBusy)
+            .with_details(serde_json::json!({ "limit": limit }))
+            .with_retry_after(1));
+    }
+
+    if let Some((_, path)) = &transcoded {
+        match fs::read(path).await {
+            Ok(content) => {
+                consume_link_use(state, &signed_params)?;
+                record_download(state, file_id, true).await;
+                tracing::Span::current().record("cache", "transcoded");
+                let length = content.len() as u64;
+                let mut response = file_response(state, &metadata, &signed_params, Body::from(content), length, None, None);
+                response.headers_mut().remove(header::ACCEPT_RANGES);
+                return Ok(response);
+            }
+            Err(e) => {
+                error!("❌ read_failed | {} | {}", path, e);
+                return Err(ApiError::new(ErrorCode::ReadFailed));
+            }
+        }
+    }
+
+    if let Some(encoding) = encoding {
+        match fs::read(variant_path(&metadata.disk_path, encoding)).await {
+            Ok(content) => {
+                consume_link_use(state, &signed_params)?;
+                record_download(state, file_id, true).await;
+                tracing::Span::current().record("cache", "precompressed");
+                let length = content.len() as u64;
+                return Ok(file_response(state, &metadata, &signed_params, Body::from(content), length, None, Some(encoding)));
+            }
+            Err(e) => warn!("⚠️ variant_read_failed | {} | {} | {}", file_id, encoding, e),
+        }
+    }
+
+    let mut file = match File::open(&metadata.disk_path).await {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            warn!("⚠️ desync | {} | {} missing on disk, dropping entry", file_id, metadata.disk_path);
+            if let Some(stale) = state.file_registry.remove(file_id).await {
+                release_storage(state, stale.size);
+                release_owner_file(state, &stale.owner);
+                remove_derived_files(&stale).await;
+            }
+            return Err(ApiError::new(ErrorCode::FileNotFound));
+        }
+        Err(e) => {
+            error!("❌ read_failed | {} | {}", file_id, e);
+            return Err(ApiError::new(ErrorCode::ReadFailed));
+        }
+    };
+
+    let size = match file.metadata().await {
+        Ok(stat) => stat.len(),
+        Err(e) => {
+            error!("❌ read_failed | {} | {}", file_id, e);
+            return Err(ApiError::new(ErrorCode::ReadFailed));
+        }
+    };
+    tracing::Span::current().record("cache", "miss");
+
+    let (start, length, range) = match requested_range(request_headers, &metadata, size) {
+        ByteRange::Full => (0, size, None),
+        ByteRange::Partial(start, end) => (start, end - start + 1, Some((start, end, size))),
+        ByteRange::Unsatisfiable => {
+            let mut response = ApiError::new(ErrorCode::RangeNotSatisfiable).into_response();
+            if let Ok(content_range) = format!("bytes */{}", size).parse() {
+                response.headers_mut().insert(header::CONTENT_RANGE, content_range);
+            }
+            response.headers_mut().insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+            response.extensions_mut().insert(ServedOwner(metadata.owner.clone()));
+            return Ok(response);
+        }
+    };
+
+    let counted = start == 0 && length > 1;
+    if counted {
+        consume_link_use(state, &signed_params)?;
+    }
+    record_download(state, file_id, counted).await;
+
+    if start > 0 {
+        if let Err(e) = file.seek(std::io::SeekFrom::Start(start)).await {
+            error!("❌ read_failed | {} | {}", file_id, e);
+            return Err(ApiError::new(ErrorCode::ReadFailed));
+        }
+    }
+
+    let stream = ReaderStream::with_capacity(file.take(length), state.config.read_buffer).map(move |chunk| {
+        let _ = &reader;
+        chunk
+    });
+
+    Ok(file_response(state, &metadata, &signed_params, Body::from_stream(stream), length, range, None))
+}
+
+async fn export_handler(
+    State(state): State<AppState>,
+    ClientIp(client): ClientIp,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    check_rate_limit(&state.ip_limiter, "ip", &client.to_string())?;
+    let owner = authenticate_owner(&headers, &state)?.ok_or_else(|| ApiError::new(ErrorCode::Unauthorized))?;
+    check_rate_limit(&state.owner_limiter, "owner", &owner)?;
+
+    let now = Utc::now().timestamp();
+    let mut files: Vec<FileMetadata> = state
+        .file_registry
+        .all()
+        .await
+        .into_iter()
+        .filter(|m| m.owner == owner && m.expires_at > now)
+        .collect();
+    if files.is_empty() {
+        return Err(ApiError::new(ErrorCode::NoFiles));
+    }
+    files.sort_by_key(|m| m.uploaded_at);
+
+    let total: u64 = files.iter().map(|m| m.size).sum();
+    if state.config.export_max_bytes > 0 && total > state.config.export_max_bytes {
+        warn!("⚠️ export_too_large | {} | {} bytes | limit {}", owner, total, state.config.export_max_bytes);
+        return Err(ApiError::new(ErrorCode::ExportTooLarge)
+            .with_details(serde_json::json!({ "size": total, "limit": state.config.export_max_bytes })));
+    }
+
+    let guards: Vec<ReadGuard> = files.iter().map(|m| state.readers.acquire(&m.file_id)).collect();
+    let mut names = HashSet::new();
+    let entries: Vec<(bytes::Bytes, FileMetadata)> = files
+        .into_iter()
+        .map(|metadata| {
+            let mut name = metadata.original_name.clone();
+            if !names.insert(name.clone()) {
+                name = format!("{}-{}", metadata.file_id, name);
+                names.insert(name.clone());
+            }
+            (tar_header(&name, metadata.size, metadata.uploaded_at).into(), metadata)
+        })
+        .collect();
+    let length = entries.iter().map(|(header, m)| header.len() as u64 + m.size.div_ceil(512) * 512).sum::<u64>() + 1024;
+    let count = entries.len();
+
+    let read_buffer = state.config.read_buffer;
+    let body = futures_util::stream::iter(entries)
+        .then(move |(header, metadata)| async move {
+            let file = File::open(&metadata.disk_path).await?;
+            let padding = bytes::Bytes::from(vec![0u8; (metadata.size.div_ceil(512) * 512 - metadata.size) as usize]);
+            Ok::<_, std::io::Error>(
+                futures_util::stream::once(std::future::ready(Ok(header)))
+                    .chain(ReaderStream::with_capacity(file.take(metadata.size), read_buffer))
+                    .chain(futures_util::stream::once(std::future::ready(Ok(padding)))),
+            )
+        })
+        .try_flatten()
+        .chain(futures_util::stream::once(async move {
+            drop(guards);
+            Ok(bytes::Bytes::from_static(&[0u8; 1024]))
+        }));
+
+    info!("📦 export | {} | {} files | {} bytes", owner, count, length);
+
+    let mut response_headers = HeaderMap::new();
+    response_headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("application/x-tar"));
+    response_headers.insert(
+        header::CONTENT_DISPOSITION,
+        content_disposition("attachment", &format!("{}-{}.tar", owner, Utc::now().format("%Y%m%d")))
+            .parse()
+            .unwrap_or(HeaderValue::from_static("attachment")),
+    );
+    response_headers.insert(header::CONTENT_LENGTH, length.into());
+    response_headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    apply_response_headers(&mut response_headers, &state.config);
+    Ok((StatusCode::OK, response_headers, Body::from_stream(body)).into_response())
+}
+
+fn tar_header(name: &str, size: u64, mtime: i64) -> Vec<u8> {
+    let mut out = Vec::with_capacity(512);
+    let mut records = String::new();
+    if name.len() > 100 {
+        records.push_str(&pax_record("path", name));
+    }
+    if size > TAR_MAX_OCTAL {
+        records.push_str(&pax_record("size", &size.to_string()));
+    }
+    if !records.is_empty() {
+        out.extend(tar_block("PaxHeader", records.len() as u64, mtime, b'x'));
+        out.extend_from_slice(records.as_bytes());
+        out.resize(out.len().div_ceil(512) * 512, 0);
+    }
+    let mut cut = name.len().min(100);
+    while !name.is_char_boundary(cut) {
+        cut -= 1;
+    }
+    out.extend(tar_block(&name[..cut], size, mtime, b'0'));
+    out
+}
+
+fn pax_record(key: &str, value: &str) -> String {
+    let body = format!(" {}={}\n", key, value);
+    let mut len = body.len();
+    while len.to_string().len() + body.len() != len {
+        len = len.to_string().len() + body.len();
+    }
+    format!("{}{}", len, body)
+}
+
+fn tar_block(name: &str, size: u64, mtime: i64, kind: u8) -> [u8; 512] {
+    let mut block = [0u8; 512];
+    block[..name.len()].copy_from_slice(name.as_bytes());
+    block[100..108].copy_from_slice(b"0000644\0");
+    block[108..116].copy_from_slice(b"0000000\0");
+    block[116..124].copy_from_slice(b"0000000\0");
+    block[124..136].copy_from_slice(format!("{:011o}\0", size.min(TAR_MAX_OCTAL)).as_bytes());
+    block[136..148].copy_from_slice(format!("{:011o}\0", (mtime.max(0) as u64).min(TAR_MAX_OCTAL)).as_bytes());
+    block[148..156].copy_from_slice(b"        ");
+    block[156] = kind;
+    block[257..263].copy_from_slice(b"ustar\0");
+    block[263..265].copy_from_slice(b"00");
+    let checksum: u32 = block.iter().map(|b| *b as u32).sum();
+    block[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+    block
+}
+
+fn consume_link_use(state: &AppState, signed_params: &SignedUrlParams) -> Result<(), ApiError> {
+    let limit = match signed_params.uses.as_deref() {
+        Some(uses) => uses.parse::<u64>().map_err(|_| ApiError::new(ErrorCode::InvalidUses))?,
+        None => state.config.max_link_uses,
+    };
+    if limit == 0 || signed_params.nonce.is_empty() {
+        return Ok(());
+    }
+    let expires_at = signed_params.expires.parse::<i64>().unwrap_or_default();
+    let mut link_uses = state.link_uses.lock().unwrap();
+    let entry = link_uses
+        .entry(signed_params.nonce.clone())
+        .or_insert(LinkUses { count: 0, expires_at });
+    if entry.count >= limit {
+        warn!("⚠️ link_exhausted | {} | {} | {} uses", signed_params.id, signed_params.nonce, limit);
+        return Err(ApiError::new(ErrorCode::LinkExhausted).with_details(serde_json::json!({ "limit": limit })));
+    }
+    entry.count += 1;
+    Ok(())
+}
+
+async fn record_download(state: &AppState, file_id: &str, counted: bool) {
+    let now = Utc::now().timestamp();
+    state
+        .file_registry
+        .update(file_id, move |entry| {
+            entry.last_accessed = Some(now);
+            if counted {
+                entry.download_count += 1;
+            }
+        })
+        .await;
+}
+
+enum ByteRange {
+    Full,
+    Partial(u64, u64),
+    Unsatisfiable,
+}
+
+fn requested_range(headers: &HeaderMap, metadata: &FileMetadata, size: u64) -> ByteRange {
+    let Some(spec) = headers
+        .get(header::RANGE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().strip_prefix("bytes="))
+    else {
+        return ByteRange::Full;
+    };
+
+    if let Some(if_range) = headers.get(header::IF_RANGE).and_then(|v| v.to_str().ok()) {
+        let if_range = if_range.trim();
+        if if_range != entity_tag(metadata, None) && if_range != http_date(metadata.uploaded_at) {
+            return ByteRange::Full;
+        }
+    }
+
+    if spec.contains(',') {
+        return ByteRange::Full;
+    }
+    let Some((first, last)) = spec.trim().split_once('-') else {
+        return ByteRange::Full;
+    };
+
+    if first.is_empty() {
+        return match last.parse::<u64>() {
+            Ok(0) => ByteRange::Unsatisfiable,
+            Ok(_) if size == 0 => ByteRange::Unsatisfiable,
+            Ok(suffix) => ByteRange::Partial(size.saturating_sub(suffix), size - 1),
+            Err(_) => ByteRange::Full,
+        };
+    }
+
+    let Ok(start) = first.parse::<u64>() else {
+        return ByteRange::Full;
+    };
+    let end = match last {
+        "" => u64::MAX,
+        last => match last.parse::<u64>() {
+            Ok(end) if end >= start => end,
+            _ => return ByteRange::Full,
+        },
+    };
+    if start >= size {
+        return ByteRange::Unsatisfiable;
+    }
+    ByteRange::Partial(start, end.min(size - 1))
+}
+
+fn entity_tag(metadata: &FileMetadata, encoding: Option<&str>) -> String {
+    match encoding {
+        Some(encoding) => format!("\"{}-{}\"", metadata.checksum, encoding),
+        None => format!("\"{}\"", metadata.checksum),
+    }
+}
+
+fn http_date(timestamp: i64) -> String {
+    chrono::DateTime::from_timestamp(timestamp, 0)
+        .unwrap_or_default()
+        .format("%a, %d %b %Y %H:%M:%S GMT")
+        .to_string()
+}
+
+fn insert_validators(headers: &mut HeaderMap, metadata: &FileMetadata, encoding: Option<&str>) {
+    if let Ok(etag) = entity_tag(metadata, encoding).parse() {
+        headers.insert(header::ETAG, etag);
+    }
+    if let Ok(last_modified) = http_date(metadata.uploaded_at).parse() {
+        headers.insert(header::LAST_MODIFIED, last_modified);
+    }
+}
+
+fn is_not_modified(headers: &HeaderMap, metadata: &FileMetadata, encoding: Option<&str>) -> bool {
+    if let Some(if_none_match) = headers.get(header::IF_NONE_MATCH).and_then(|v| v.to_str().ok()) {
+        let etag = entity_tag(metadata, encoding);
+        return if_none_match
+            .split(',')
+            .map(|t| t.trim().trim_start_matches("W/"))
+            .any(|t| t == "*" || t == etag);
+    }
+
+    headers
+        .get(header::IF_MODIFIED_SINCE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| chrono::DateTime::parse_from_rfc2822(v).ok())
+        .is_some_and(|since| metadata.uploaded_at <= since.timestamp())
+}
+
+const PROTECTED_HEADERS: [header::HeaderName; 5] = [
+    header::CONTENT_TYPE,
+    header::CONTENT_DISPOSITION,
+    header::CONTENT_LENGTH,
+    header::CONTENT_ENCODING,
+    header::ETAG,
+];
+
+fn apply_response_headers(headers: &mut HeaderMap, config: &Config) {
+    if config.security_headers {
+        headers.entry(header::X_CONTENT_TYPE_OPTIONS).or_insert(HeaderValue::from_static("nosniff"));
+        headers
+            .entry(header::CONTENT_SECURITY_POLICY)
+            .or_insert(HeaderValue::from_static("default-src 'none'"));
+        headers.entry(header::X_FRAME_OPTIONS).or_insert(HeaderValue::from_static("DENY"));
+        headers.entry(header::REFERRER_POLICY).or_insert(HeaderValue::from_static("no-referrer"));
+    }
+    for (name, value) in &config.extra_headers {
+        if let (Ok(name), Ok(value)) = (header::HeaderName::from_bytes(name.as_bytes()), HeaderValue::from_str(value)) {
+            if !PROTECTED_HEADERS.contains(&name) {
+                headers.insert(name, value);
+            }
+        }
+    }
+}
+
+fn file_response(
+    state: &AppState,
+    metadata: &FileMetadata,
+    signed_params: &SignedUrlParams,
+    body: Body,
+    content_length: u64,
+    range: Option<(u64, u64, u64)>,
+    encoding: Option<&str>,
+) -> Response {
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CONTENT_TYPE, served_content_type(metadata).parse().unwrap());
+
+    let disposition = if is_viewable_mime(&metadata.mime_type, &state.config) && signed_params.mode == "inline" {
+        content_disposition("inline", served_name(metadata, &state.config))
+    } else {
+        let name = metadata.download_name.as_deref().unwrap_or(served_name(metadata, &state.config));
+        content_disposition("attachment", &render_download_name(&state.config, &metadata.file_id, name))
+    };
+    headers.insert(header::CONTENT_DISPOSITION, disposition.parse().unwrap());
+    headers.insert(header::CONTENT_LENGTH, content_length.to_string().parse().unwrap());
+    if let Some((start, end, size)) = range {
+        headers.insert(header::CONTENT_RANGE, format!("bytes {}-{}/{}", start, end, size).parse().unwrap());
+    }
+    headers.insert(header::CACHE_CONTROL, cache_control_for(&state.config, signed_params, &metadata.mime_type, metadata.expires_at));
+    insert_expiry_headers(&mut headers, signed_params, metadata.expires_at);
+    insert_validators(&mut headers, metadata, encoding);
+    insert_vary(&mut headers, metadata, &signed_params.mode, &state.config);
+    match encoding {
+        Some(encoding) => {
+            headers.insert(header::CONTENT_ENCODING, encoding.parse().unwrap());
+        }
+        None => {
+            headers.insert(header::ACCEPT_RANGES, "bytes".parse().unwrap());
+        }
+    }
+    if let (Some(width), Some(height)) = (metadata.width, metadata.height) {
+        headers.insert("x-image-width", width.into());
+        headers.insert("x-image-height", height.into());
+    }
+
+    apply_response_headers(&mut headers, &state.config);
+
+    info!("📤 {} | {} | {}", metadata.original_name, metadata.mime_type, encoding.unwrap_or("identity"));
+
+    let status = if range.is_some() { StatusCode::PARTIAL_CONTENT } else { StatusCode::OK };
+    let mut response = (status, headers, body).into_response();
+    response.extensions_mut().insert(ServedOwner(metadata.owner.clone()));
+    response
+}
+
+async fn file_meta_handler(
+    State(state): State<AppState>,
+    ClientIp(client): ClientIp,
+    AxumPath(file_id): AxumPath<String>,
+    Query(params): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> Result<Json<FileMetaResponse>, ApiError> {
+    let signed_params = verify_signed_request(&state, client, &file_id, &params)?;
+
+    let metadata = state
+        .file_registry
+        .get(&file_id)
+        .await
+        .filter(|m| m.expires_at > Utc::now().timestamp())
+        .ok_or_else(|| ApiError::new(ErrorCode::FileNotFound))?;
+    check_signed_type(&signed_params, &metadata)?;
+    check_signed_owner(&signed_params, &metadata)?;
+    check_password(&state, client, &metadata, &headers)?;
+
+    Ok(Json(FileMetaResponse {
+        id: metadata.file_id,
+        name: metadata.original_name,
+        size: metadata.size,
+        mime: metadata.mime_type,
+        width: metadata.width,
+        height: metadata.height,
+        checksum: metadata.checksum,
+        uploaded_at: metadata.uploaded_at,
+        expires_at: metadata.expires_at,
+        download_name: metadata.download_name,
+        description: metadata.description,
+        download_count: metadata.download_count,
+    }))
+}
+
+async fn viewer_handler(
+    State(state): State<AppState>,
+    ClientIp(client): ClientIp,
+    AxumPath(file_id): AxumPath<String>,
+    Query(params): Query<HashMap<String, String>>,
+    RawQuery(raw_query): RawQuery,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    if !state.config.viewer {
+        return Err(ApiError::new(ErrorCode::FileNotFound));
+    }
+    let signed_params = verify_signed_request(&state, client, &file_id, &params)?;
+
+    let metadata = state
+        .file_registry
+        .get(&file_id)
+        .await
+        .filter(|m| m.expires_at > Utc::now().timestamp())
+        .ok_or_else(|| ApiError::new(ErrorCode::FileNotFound))?;
+    check_signed_type(&signed_params, &metadata)?;
+    check_signed_owner(&signed_params, &metadata)?;
+
+    let base_url = request_base_url(&headers, &state.config);
+    if !is_viewable_mime(&metadata.mime_type, &state.config) {
+        let bound_ip = signed_params.ip.is_some().then_some(client);
+        let limited = signed_params.uses.is_some() || state.config.max_link_uses > 0;
+        if limited {
+            consume_link_use(&state, &signed_params)?;
+        }
+        let link = LinkOptions::new("attachment", metadata.expires_at)
+            .with_ip(bound_ip)
+            .with_uses(limited.then_some(1));
+        let location = generate_signed_url(&base_url, &metadata, link, &state.config);
+        return Ok((StatusCode::FOUND, [(header::LOCATION, location)]).into_response());
+    }
+
+    let src = match raw_query.filter(|q| !q.is_empty()) {
+        Some(query) => format!("{}/file/{}?{}", state.config.route_prefix, file_id, query),
+        None => format!("{}/file/{}", state.config.route_prefix, file_id),
+    };
+    let src = html_escape(&src);
+    let title = html_escape(&metadata.original_name);
+    let media = match metadata.mime_type.split('/').next() {
+        Some("video") => format!("<video src=\"{}\" controls autoplay playsinline></video>", src),
+        Some("audio") => format!("<audio src=\"{}\" controls autoplay></audio>", src),
+        Some("text") => format!("<iframe src=\"{}\" sandbox title=\"{}\"></iframe>", src, title),
+        _ => format!("<img src=\"{}\" alt=\"{}\">", src, title),
+    };
+    let media = match &metadata.description {
+        Some(description) => format!("<figure>{}<figcaption>{}</figcaption></figure>", media, html_escape(description)),
+        None => media,
+    };
+    let page = format!(
+        "<!doctype html><html><head><meta charset=\"utf-8\"><meta name=\"viewport\" content=\"width=device-width, initial-scale=1\"><title>{}</title><style>html,body{{margin:0;height:100%;background:#111;display:flex;align-items:center;justify-content:center}}img,video{{max-width:100%;max-height:100vh}}iframe{{width:100vw;height:100vh;border:0;background:#fff}}figure{{margin:0;text-align:center}}figcaption{{color:#ccc;font:14px/1.4 sans-serif;padding:8px;white-space:pre-wrap}}</style></head><body>{}</body></html>",
+        title, media
+    );
+
+    info!("👁️ view | {} | {}", metadata.original_name, file_id);
+
+    let mut response = Html(page).into_response();
+    response.headers_mut().insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    response
+        .headers_mut()
+        .insert(header::REFERRER_POLICY, HeaderValue::from_static("no-referrer"));
+    Ok(response)
+}
+
+fn html_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+async fn serve_thumbnail(
+    State(state): State<AppState>,
+    ClientIp(client): ClientIp,
+    AxumPath(file_id): AxumPath<String>,
+    Query(params): Query<HashMap<String, String>>,
+    request_headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let signed_params = verify_signed_request(&state, client, &file_id, &params)?;
+
+    if state.config.thumbnails == ThumbnailMode::Off {
+        return Err(ApiError::new(ErrorCode::ThumbnailUnavailable));
+    }
+
+    check_referer(&request_headers, "inline", &state.config)?;
+    let _reader = state.readers.acquire(&file_id);
+    let metadata = state
+        .file_registry
+        .get(&file_id)
+        .await
+        .filter(|m| m.expires_at > Utc::now().timestamp())
+        .ok_or_else(|| ApiError::new(ErrorCode::FileNotFound))?;
+
+    check_signed_type(&signed_params, &metadata)?;
+    check_signed_owner(&signed_params, &metadata)?;
+    check_password(&state, client, &metadata, &request_headers)?;
+
+    let thumb_path = match metadata.thumb_path {
+        Some(path) => path,
+        None if state.config.thumbnails == ThumbnailMode::Lazy && is_thumbnailable_mime(&metadata.mime_type) => {
+            let path = create_thumbnail(&metadata.disk_path, &metadata.mime_type, state.config.thumbnail_size)
+                .await
+                .ok_or_else(|| ApiError::new(ErrorCode::ThumbnailUnavailable))?;
+            let recorded = path.clone();
+            match state.file_registry.update(&file_id, move |entry| entry.thumb_path = Some(recorded)).await {
+                Some(()) => {}
+                None => {
+                    let _ = fs::remove_file(&path).await;
+                    return Err(ApiError::new(ErrorCode::FileNotFound));
+                }
+            }
+            path
+        }
+        None => {
+            return Err(ApiError::new(ErrorCode::ThumbnailUnavailable));
+        }
+    };
+
+    let thumb_content = fs::read(&thumb_path).await.map_err(|_| {
+        error!("❌ thumb_read_failed | {}", file_id);
+        ApiError::new(ErrorCode::ReadFailed)
+    })?;
+
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CONTENT_TYPE, "image/png".parse().unwrap());
+    headers.insert(header::CONTENT_DISPOSITION, "inline".parse().unwrap());
+    headers.insert(header::CONTENT_LENGTH, thumb_content.len().to_string().parse().unwrap());
+    headers.insert(header::CACHE_CONTROL, cache_control_for(&state.config, &signed_params, "image/png", metadata.expires_at));
+    apply_response_headers(&mut headers, &state.config);
+
+    info!("🖼️ thumb | {} | {}", metadata.original_name, file_id);
+
+    Ok((StatusCode::OK, headers, thumb_content).into_response())
+}
+
+async fn qr_handler(
+    State(state): State<AppState>,
+    ClientIp(client): ClientIp,
+    AxumPath(file_id): AxumPath<String>,
+    Query(params): Query<HashMap<String, String>>,
+    request_headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let signed_params = verify_signed_request(&state, client, &file_id, &params)?;
+
+    let svg = match params.get("format").map(|v| v.to_ascii_lowercase()).as_deref() {
+        None | Some("png") => false,
+        Some("svg") => true,
+        Some(_) => return Err(ApiError::new(ErrorCode::InvalidFormat).with_details(serde_json::json!({ "allowed": ["png", "svg"] }))),
+    };
+    let size = params
+        .get("size")
+        .and_then(|v| v.parse::<u32>().ok())
+        .unwrap_or(QR_DEFAULT_SIZE)
+        .clamp(QR_MIN_SIZE, QR_MAX_SIZE);
+
+    let metadata = state
+        .file_registry
+        .get(&file_id)
+        .await
+        .filter(|m| m.expires_at > Utc::now().timestamp())
+        .ok_or_else(|| ApiError::new(ErrorCode::FileNotFound))?;
+    check_signed_type(&signed_params, &metadata)?;
+    check_signed_owner(&signed_params, &metadata)?;
+
+    let base_url = request_base_url(&request_headers, &state.config);
+    let link = if signed_params.signature.is_empty() {
+        generate_signed_url(&base_url, &metadata, LinkOptions::new(&signed_params.mode, metadata.expires_at), &state.config)
+    } else {
+        format!(
+            "{}/file/{}?{}",
+            base_url,
+            file_id,
+            serde_urlencoded::to_string(&signed_params).unwrap_or_default()
+        )
+    };
+
+    let rendered = tokio::task::spawn_blocking(move || -> Option<Vec<u8>> {
+        let code = qrcode::QrCode::new(link.as_bytes()).ok()?;
+        if svg {
+            return Some(
+                code.render::<qrcode::render::svg::Color>()
+                    .min_dimensions(size, size)
+                    .build()
+                    .into_bytes(),
+            );
+        }
+        let image = code.render::<image::Luma<u8>>().min_dimensions(size, size).build();
+        let mut out = std::io::Cursor::new(Vec::new());
+        image.write_to(&mut out, image::ImageFormat::Png).ok()?;
+        Some(out.into_inner())
+    })
+    .await
+    .ok()
+    .flatten()
+    .ok_or_else(|| {
+        error!("❌ qr_failed | {}", file_id);
+        ApiError::new(ErrorCode::QrFailed)
+    })?;
+
+    let content_type = if svg { "image/svg+xml" } else { "image/png" };
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CONTENT_TYPE, content_type.parse().unwrap());
+    headers.insert(header::CONTENT_DISPOSITION, "inline".parse().unwrap());
+    headers.insert(header::CONTENT_LENGTH, rendered.len().to_string().parse().unwrap());
+    headers.insert(header::CACHE_CONTROL, "no-store".parse().unwrap());
+    apply_response_headers(&mut headers, &state.config);
+
+    info!("🔳 qr | {} | {} | {}px", file_id, content_type, size);
+
+    Ok((StatusCode::OK, headers, rendered).into_response())
+}
+
+async fn events_handler(
+    State(state): State<AppState>,
+    Query(query): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+    upgrade: Option<WebSocketUpgrade>,
+) -> Result<Response, ApiError> {
+    require_admin(&headers, &state.config)?;
+    let upgrade = upgrade.ok_or_else(|| ApiError::new(ErrorCode::UpgradeRequired))?;
+
+    let owner = query.get("owner").filter(|v| !v.is_empty()).cloned();
+    let events = state.events.subscribe();
+    Ok(upgrade.on_upgrade(move |socket| stream_events(socket, events, owner)))
+}
+
+async fn stream_events(mut socket: WebSocket, mut events: broadcast::Receiver<FileEvent>, owner: Option<String>) {
+    loop {
+        let message = tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) if owner.as_ref().is_some_and(|o| *o != event.owner) => continue,
+                Ok(event) => serde_json::to_string(&event).unwrap_or_default(),
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("⚠️ events_lagged | {} dropped", skipped);
+                    serde_json::json!({"event": "lagged", "skipped": skipped}).to_string()
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            incoming = socket.recv() => match incoming {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => continue,
+            },
+        };
+        if socket.send(Message::Text(message)).await.is_err() {
+            break;
+        }
+    }
+}
+
+async fn stats_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<StatsResponse>, ApiError> {
+    require_admin(&headers, &state.config)?;
+    Ok(Json(collect_stats(&state).await))
+}
+
+async fn config_handler(State(state): State<AppState>, headers: HeaderMap) -> Result<Response, ApiError> {
+    require_admin(&headers, &state.config)?;
+    Ok(Json(&*state.config).into_response())
+}
+
+async fn metrics_handler(State(state): State<AppState>, headers: HeaderMap) -> Result<Response, ApiError> {
+    require_admin(&headers, &state.config)?;
+    Ok(([(header::CONTENT_TYPE, "text/plain; version=0.0.4; charset=utf-8")], state.metrics.render()).into_response())
+}
+
+async fn collect_stats(state: &AppState) -> StatsResponse {
+    let now = Utc::now().timestamp();
+    let mut stats = StatsResponse {
+        total_files: 0,
+        total_bytes: 0,
+        bytes_per_owner: HashMap::new(),
+        files_per_owner: state.owner_files.lock().unwrap().clone(),
+        expiring_next_minute: 0,
+        oldest_upload: None,
+        newest_upload: None,
+        free_disk_bytes: None,
+        pool_free_bytes: state
+            .config
+            .storage_pools
+            .iter()
+            .map(|pool| (pool.clone(), free_disk_space(pool)))
+            .collect(),
+        stored_bytes: state.stored_bytes.load(Ordering::Relaxed),
+        max_total_bytes: state.config.max_total_bytes,
+        max_files_per_owner: state.config.max_files_per_owner,
+        signature_blocked_ips: state.signature_failures.as_ref().map_or(0, |tracker| tracker.blocked()),
+        open_connections: state.connections.as_ref().map(|limit| limit.open()),
+        refused_connections: state.connections.as_ref().map_or(0, |limit| limit.refused.load(Ordering::Relaxed)),
+    };
+    stats.free_disk_bytes = stats.pool_free_bytes.values().flatten().copied().reduce(|a, b| a + b);
+
+    {
+        for metadata in state.file_registry.all().await {
+            stats.total_files += 1;
+            stats.total_bytes += metadata.size;
+            *stats.bytes_per_owner.entry(metadata.owner.clone()).or_insert(0) += metadata.size;
+            if metadata.expires_at <= now + 60 {
+                stats.expiring_next_minute += 1;
+            }
+            stats.oldest_upload = Some(stats.oldest_upload.map_or(metadata.uploaded_at, |t| t.min(metadata.uploaded_at)));
+            stats.newest_upload = Some(stats.newest_upload.map_or(metadata.uploaded_at, |t| t.max(metadata.uploaded_at)));
+        }
+    }
+
+    stats
+}
+
+async fn checksum_handler(
+    State(state): State<AppState>,
+    ClientIp(client): ClientIp,
+    AxumPath(hex): AxumPath<String>,
+    Query(params): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let checksum = hex.to_ascii_lowercase();
+    if checksum.len() != 64 || !checksum.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(ApiError::new(ErrorCode::InvalidChecksum));
+    }
+
+    let is_admin = require_admin(&headers, &state.config).is_ok();
+    let owner = if is_admin { None } else { authenticate_owner(&headers, &state)? };
+    if !is_admin && owner.is_none() && (!state.config.api_keys.is_empty() || state.jwt.is_some()) {
+        return Err(ApiError::new(ErrorCode::Unauthorized));
+    }
+
+    let mode = params.get("mode").map(String::as_str).unwrap_or("inline");
+    if !matches!(mode, "inline" | "attachment") {
+        return Err(ApiError::new(ErrorCode::InvalidMode));
+    }
+
+    let metadata = state
+        .file_registry
+        .find_by_checksum(&checksum, Utc::now().timestamp())
+        .await
+        .into_iter()
+        .find(|m| owner.as_ref().is_none_or(|o| *o == m.owner))
+        .ok_or_else(|| ApiError::new(ErrorCode::FileNotFound))?;
+
+    let base_url = request_base_url(&headers, &state.config);
+    let link = LinkOptions::new(mode, metadata.expires_at).with_ip(metadata.bind_ip.then_some(client));
+    let location = generate_signed_url(&base_url, &metadata, link, &state.config);
+
+    info!("🔗 sha256 | {} | {}", checksum, metadata.file_id);
+
+    let mut response_headers = HeaderMap::new();
+    response_headers.insert(header::LOCATION, location.parse().unwrap());
+    response_headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    Ok((StatusCode::FOUND, response_headers).into_response())
+}
+
+async fn purge_handler(
+    State(state): State<AppState>,
+    ClientIp(client): ClientIp,
+    Query(params): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> Result<Json<PurgeResponse>, ApiError> {
+    require_admin(&headers, &state.config)?;
+    let owner = params.get("owner").filter(|o| !o.is_empty()).cloned();
+
+    let (protected, targets): (Vec<FileMetadata>, Vec<FileMetadata>) = state
+        .file_registry
+        .all()
+        .await
+        .into_iter()
+        .filter(|m| owner.as_ref().is_none_or(|o| *o == m.owner))
+        .partition(|m| m.worm);
+    let protected = protected.len();
+    if protected > 0 && params.get("skip_protected").map(String::as_str) != Some("true") {
+        return Err(ApiError::new(ErrorCode::WormProtected).with_details(serde_json::json!({ "protected": protected })));
+    }
+    let targets: Vec<(String, u64)> = targets.into_iter().map(|m| (m.file_id, m.size)).collect();
+    let deleted = targets.len();
+    let bytes = targets.iter().map(|(_, size)| size).sum();
+
+    warn!(
+        "🧨 purge | owner={} | {} files | {} bytes | {} worm_protected",
+        owner.as_deref().unwrap_or("*"),
+        deleted,
+        bytes,
+        protected
+    );
+
+    futures_util::stream::iter(targets)
+        .for_each_concurrent(PURGE_CONCURRENCY, |(file_id, _)| {
+            let state = state.clone();
+            async move { delete_file(&state, &file_id, "purged").await }
+        })
+        .await;
+
+    if let Some(audit_tx) = &state.audit_tx {
+        let record = AuditRecord {
+            timestamp: Utc::now().timestamp(),
+            action: "purge",
+            file_id: "*".to_string(),
+            owner: owner.clone().unwrap_or_else(|| "*".to_string()),
+            client_ip: client.to_string(),
+            status: StatusCode::OK.as_u16(),
+            result: "ok".to_string(),
+            bytes,
+            files: Some(deleted),
+            request_id: headers
+                .get("x-request-id")
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or_default()
+                .to_string(),
+        };
+        if audit_tx.send(record).is_err() {
+            warn!("⚠️ audit_log_closed");
+        }
+    }
+
+    Ok(Json(PurgeResponse { deleted, bytes, protected }))
+}
+
+async fn verify_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Result<Json<SignedUrlParams>, JsonRejection>,
+) -> Result<Json<VerifyResponse>, ApiError> {
+    require_admin(&headers, &state.config)?;
+    let Json(params) = body.map_err(|e| json_error(e, &state.config))?;
+
+    let string_to_sign = string_to_sign(&params);
+    let expected_signature = sign_params(&params, signing_key(&state.config, &params.owner))?;
+    let valid = constant_time_eq(expected_signature.as_bytes(), params.signature.as_bytes());
+
+    info!("🔎 verify | {} | valid={}", params.id, valid);
+
+    Ok(Json(VerifyResponse {
+        valid,
+        expected_signature,
+        provided_signature: params.signature,
+        string_to_sign,
+    }))
+}
+
+async fn exists_handler(
+    State(state): State<AppState>,
+    AxumPath(file_id): AxumPath<String>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let is_admin = require_admin(&headers, &state.config).is_ok();
+    let owner = if is_admin { None } else { authenticate_owner(&headers, &state)? };
+    if !is_admin && owner.is_none() {
+        return Err(ApiError::new(ErrorCode::Unauthorized));
+    }
+
+    let now = Utc::now().timestamp();
+    let metadata = state
+        .file_registry
+        .get(&file_id)
+        .await
+        .filter(|m| m.expires_at > now && owner.as_ref().is_none_or(|o| *o == m.owner));
+    Ok(match metadata {
+        Some(metadata) => Json(ExistsResponse { exists: true, expires_at: Some(metadata.expires_at) }).into_response(),
+        None => (StatusCode::NOT_FOUND, Json(ExistsResponse { exists: false, expires_at: None })).into_response(),
+    })
+}
+
+async fn extend_handler(
+    State(state): State<AppState>,
+    AxumPath(file_id): AxumPath<String>,
+    headers: HeaderMap,
+    body: Result<Json<ExtendRequest>, JsonRejection>,
+) -> Result<Json<ExtendResponse>, ApiError> {
+    let Json(request) = body.map_err(|e| json_error(e, &state.config))?;
+
+    let is_admin = require_admin(&headers, &state.config).is_ok();
+    let owner = if is_admin { None } else { authenticate_owner(&headers, &state)? };
+    if !is_admin && owner.is_none() {
+        return Err(ApiError::new(ErrorCode::Unauthorized));
+    }
+
+    let now = Utc::now().timestamp();
+    let grace = state.config.grace_period as i64;
+    let metadata = state
+        .file_registry
+        .get(&file_id)
+        .await
+        .filter(|m| m.expires_at.saturating_add(grace) > now)
+        .ok_or_else(|| ApiError::new(ErrorCode::FileNotFound))?;
+
+    if let Some(owner) = &owner {
+        if *owner != metadata.owner {
+            return Err(ApiError::new(ErrorCode::NotOwner));
+        }
+    }
+
+    let max_lifetime = state.config.max_file_lifetime as i64;
+    let extra = i64::try_from(request.seconds).unwrap_or(i64::MAX);
+    let expires_at = state
+        .file_registry
+        .update(&file_id, move |entry| {
+            let revived = entry.expires_at.max(now).saturating_add(extra).min(entry.uploaded_at + max_lifetime);
+            if revived > now {
+                entry.expires_at = revived;
+            }
+            entry.expires_at
+        })
+        .await
+        .ok_or_else(|| ApiError::new(ErrorCode::FileNotFound))?;
+    if expires_at <= now {
+        return Err(ApiError::new(ErrorCode::FileExpired)
+            .with_details(serde_json::json!({ "max_lifetime": state.config.max_file_lifetime })));
+    }
+
+    schedule_expiry(&state, &file_id, expires_at);
+    if metadata.expires_at <= now {
+        info!("♻️ revived | {} | {}", file_id, expires_at);
+    } else {
+        info!("⏳ extended | {} | {}", file_id, expires_at);
+    }
+
+    Ok(Json(ExtendResponse {
+        id: file_id.clone(),
+        expires_at,
+    }))
+}
+
+async fn delete_file_handler(
+    State(state): State<AppState>,
+    ClientIp(client): ClientIp,
+    AxumPath(file_id): AxumPath<String>,
+    headers: HeaderMap,
+) -> Result<StatusCode, ApiError> {
+    let is_admin = require_admin(&headers, &state.config).is_ok();
+    let owner = if is_admin { None } else { authenticate_owner(&headers, &state)? };
+    let token = headers
+        .get("x-delete-token")
+        .and_then(|v| v.to_str().ok())
+        .map(str::trim)
+        .filter(|_| state.config.delete_tokens);
+    if !is_admin && owner.is_none() && token.is_none() {
+        return Err(ApiError::new(ErrorCode::Unauthorized));
+    }
+
+    let metadata = state
+        .file_registry
+        .get(&file_id)
+        .await
+        .filter(|m| m.expires_at > Utc::now().timestamp())
+        .ok_or_else(|| ApiError::new(ErrorCode::FileNotFound))?;
+    let actor = match (&owner, token) {
+        _ if is_admin => "admin".to_string(),
+        (Some(owner), _) if *owner != metadata.owner => return Err(ApiError::new(ErrorCode::NotOwner)),
+        (Some(owner), _) => owner.clone(),
+        (None, Some(token))
+            if delete_token(&metadata, &state.config)
+                .is_some_and(|expected| constant_time_eq(expected.as_bytes(), token.as_bytes())) =>
+        {
+            "token".to_string()
+        }
+        (None, _) => return Err(ApiError::new(ErrorCode::Unauthorized)),
+    };
+    if metadata.worm {
+        return Err(ApiError::new(ErrorCode::WormProtected));
+    }
+
+    delete_file(&state, &file_id, "deleted").await;
+    info!("🧹 delete | {} | {}", file_id, actor);
+
+    if let Some(audit_tx) = &state.audit_tx {
+        let record = AuditRecord {
+            timestamp: Utc::now().timestamp(),
+            action: "delete",
+            file_id: file_id.clone(),
+            owner: metadata.owner.clone(),
+            client_ip: client.to_string(),
+            status: StatusCode::NO_CONTENT.as_u16(),
+            result: "ok".to_string(),
+            bytes: metadata.size,
+            files: None,
+            request_id: headers
+                .get("x-request-id")
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or_default()
+                .to_string(),
+        };
+        if audit_tx.send(record).is_err() {
+            warn!("⚠️ audit_log_closed");
+        }
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+fn delete_token(metadata: &FileMetadata, config: &Config) -> Option<String> {
+    config.delete_tokens.then(|| {
+        let data = format!("delete\n{}\n{}\n{}", metadata.file_id, metadata.uploaded_at, metadata.request_id);
+        compute_hmac(&data, &config.secret_key)
+    })
+}
+
+async fn sign_batch_handler(
+    State(state): State<AppState>,
+    ClientIp(client): ClientIp,
+    headers: HeaderMap,
+    body: Result<Json<Vec<SignRequest>>, JsonRejection>,
+) -> Result<Json<SignBatchResponse>, ApiError> {
+    let is_admin = require_admin(&headers, &state.config).is_ok();
+    let owner = if is_admin { None } else { authenticate_owner(&headers, &state)? };
+    if !is_admin && owner.is_none() {
+        return Err(ApiError::new(ErrorCode::Unauthorized));
+    }
+
+    let Json(requests) = body.map_err(|e| json_error(e, &state.config))?;
+    if requests.len() > MAX_SIGN_BATCH {
+        return Err(ApiError::new(ErrorCode::BatchTooLarge).with_details(serde_json::json!({ "max": MAX_SIGN_BATCH })));
+    }
+
+    let base_url = request_base_url(&headers, &state.config);
+    let now = Utc::now().timestamp();
+    let mut results = Vec::with_capacity(requests.len());
+    for request in requests {
+        let source = match &request.url {
+            Some(_) if request.ip.is_some() || request.uses.is_some() => Err(ErrorCode::InvalidBody),
+            Some(_) if !state.config.signing => Ok(None),
+            Some(url) => resign_source(url, &request.file_id, now, &state.config).map(Some),
+            None => Ok(None),
+        };
+        let signed = if !matches!(request.mode.as_str(), "inline" | "attachment") {
+            Err(ErrorCode::InvalidMode)
+        } else if request.uses == Some(0) {
+            Err(ErrorCode::InvalidUses)
+        } else {
+            match (source, state.file_registry.get(&request.file_id).await.filter(|m| m.expires_at > now)) {
+                (Err(code), _) => Err(code),
+                (_, None) => Err(ErrorCode::FileNotFound),
+                (_, Some(metadata)) if owner.as_ref().is_some_and(|o| *o != metadata.owner) => Err(ErrorCode::NotOwner),
+                (Ok(Some(source)), Some(metadata)) => {
+                    let expires_at = source.expires.parse::<i64>().unwrap_or_default().min(metadata.expires_at);
+                    let link = LinkOptions::new(&request.mode, expires_at)
+                        .with_ip(source.ip.as_deref().and_then(|ip| ip.parse().ok()))
+                        .with_uses(source.uses.as_deref().and_then(|uses| uses.parse().ok()))
+                        .with_nonce(Some(&source.nonce));
+                    Ok(generate_signed_url(&base_url, &metadata, link, &state.config))
+                }
+                (Ok(None), Some(metadata)) => {
+                    let bound_ip = request.ip.or(metadata.bind_ip.then_some(client));
+                    let link = LinkOptions::new(&request.mode, metadata.expires_at)
+                        .with_ip(bound_ip)
+                        .with_uses(request.uses);
+                    Ok(generate_signed_url(&base_url, &metadata, link, &state.config))
+                }
+            }
+        };
+        results.push(match signed {
+            Ok(url) => SignResult { file_id: request.file_id, status: "ok", url: Some(url), error: None },
+            Err(code) => SignResult {
+                file_id: request.file_id,
+                status: "error",
+                url: None,
+                error: Some(ErrorResponse { code, message: code.message().into_owned(), details: None }),
+            },
+        });
+    }
+
+    info!("✍️ sign_batch | {} | {} links", owner.as_deref().unwrap_or("admin"), results.len());
+    Ok(Json(SignBatchResponse { results }))
+}
+
+fn resign_source(url: &str, file_id: &str, now: i64, config: &Config) -> Result<SignedUrlParams, ErrorCode> {
+    let query = url.split_once('?').map(|(_, query)| query).unwrap_or_default();
+    let params: HashMap<String, String> = serde_urlencoded::from_str(query).map_err(|_| ErrorCode::InvalidBody)?;
+    let source = parse_signed_params(&params).map_err(|e| e.body.code)?;
+    if !verify_signature(&source, config).map_err(|e| e.body.code)? {
+        return Err(ErrorCode::InvalidSignature);
+    }
+    if source.id != file_id {
+        return Err(ErrorCode::IdMismatch);
+    }
+    match source.expires.parse::<i64>() {
+        Ok(expires_at) if expires_at > now => Ok(source),
+        Ok(_) => Err(ErrorCode::LinkExpired),
+        Err(_) => Err(ErrorCode::InvalidExpires),
+    }
+}
+
+fn authenticate_owner(headers: &HeaderMap, state: &AppState) -> Result<Option<String>, ApiError> {
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(str::trim);
+    if let (Some(jwt), Some(token)) = (&state.jwt, bearer) {
+        if token.split('.').count() == 3 {
+            return jwt.owner(token).map(Some);
+        }
+    }
+
+    let Some(provided) = bearer.or_else(|| headers.get("x-api-key").and_then(|v| v.to_str().ok())) else {
+        return Ok(None);
+    };
+
+    state
+        .config
+        .api_keys
+        .iter()
+        .find(|(key, _)| constant_time_eq(key.as_bytes(), provided.trim().as_bytes()))
+        .map(|(_, owner)| Some(owner.clone()))
+        .ok_or_else(|| ApiError::new(ErrorCode::InvalidApiKey))
+}
+
+fn require_admin(headers: &HeaderMap, config: &Config) -> Result<(), ApiError> {
+    let provided = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .or_else(|| headers.get("x-admin-token").and_then(|v| v.to_str().ok()));
+
+    match (&config.admin_token, provided) {
+        (Some(expected), Some(provided)) if constant_time_eq(expected.as_bytes(), provided.trim().as_bytes()) => Ok(()),
+        _ => Err(ApiError::new(ErrorCode::Unauthorized)),
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn free_disk_space(path: &str) -> Option<u64> {
+    nix::sys::statvfs::statvfs(path)
+        .map(|stat| stat.blocks_available() * stat.fragment_size())
+        .map_err(|e| warn!("⚠️ statvfs_failed | {} | {}", path, e))
+        .ok()
+}
+
+fn effective_expiry(params: &SignedUrlParams, file_expires_at: i64) -> i64 {
+    let link_expires_at = params.expires.parse::<i64>().unwrap_or(file_expires_at);
+    file_expires_at.min(link_expires_at)
+}
+
+fn insert_expiry_headers(headers: &mut HeaderMap, params: &SignedUrlParams, file_expires_at: i64) {
+    let expires_at = effective_expiry(params, file_expires_at);
+    headers.insert("x-expires-at", expires_at.into());
+    headers.insert("x-expires-in", (expires_at - Utc::now().timestamp()).max(0).into());
+}
+
+const CACHE_POLICIES: [&str; 5] = ["immutable", "public", "private", "no-cache", "no-store"];
+
+fn cache_control_for(config: &Config, params: &SignedUrlParams, mime_type: &str, file_expires_at: i64) -> HeaderValue {
+    let remaining = (effective_expiry(params, file_expires_at) - Utc::now().timestamp()).max(0);
+    let max_age = config.max_cache_age.map_or(remaining, |cap| remaining.min(cap));
+    let policy = config
+        .cache_policy
+        .iter()
+        .find(|(pattern, _)| mime_matches(mime_type, std::slice::from_ref(pattern)))
+        .map(|(_, policy)| policy.as_str());
+
+    let value = match (policy, &config.cache_control) {
+        (Some("no-store"), _) => "no-store".to_string(),
+        (Some("no-cache"), _) => "no-cache".to_string(),
+        (Some(_), _) | (None, None) if remaining == 0 => "no-store".to_string(),
+        _ if remaining < config.cache_revalidate_below => "no-cache".to_string(),
+        (Some("private"), _) => format!("private, max-age={}", max_age),
+        (Some("public"), _) => format!("public, max-age={}", max_age),
+        (Some(_), _) => format!("public, max-age={}, immutable", max_age),
+        (None, Some(template)) => template.replace("{max_age}", &max_age.to_string()),
+        (None, None) => format!("public, max-age={}, immutable", max_age),
+    };
+    HeaderValue::from_str(&value).unwrap_or_else(|_| HeaderValue::from_static("no-store"))
+}
+
+fn unsigned_params(file_id: &str, params: &HashMap<String, String>) -> Result<SignedUrlParams, ApiError> {
+    let mode = params.get("sz-mode").cloned().unwrap_or_else(|| "inline".to_string());
+    if !matches!(mode.as_str(), "inline" | "attachment") {
+        return Err(ApiError::new(ErrorCode::InvalidMode));
+    }
+    Ok(SignedUrlParams {
+        version: String::new(),
+        owner: String::new(),
+        date: String::new(),
+        expires: String::new(),
+        region: String::new(),
+        mode,
+        file_type: String::new(),
+        id: file_id.to_string(),
+        nonce: String::new(),
+        ip: None,
+        uses: None,
+        signature: String::new(),
+    })
+}
+
+fn insert_vary(headers: &mut HeaderMap, metadata: &FileMetadata, mode: &str, config: &Config) {
+    let mut vary = Vec::new();
+    if !metadata.encodings.is_empty() {
+        vary.push("accept-encoding");
+    }
+    if referer_checked(mode, config) {
+        vary.extend(["origin", "referer"]);
+    }
+    if !vary.is_empty() {
+        headers.insert(header::VARY, HeaderValue::from_str(&vary.join(", ")).unwrap());
+    }
+}
+
+fn referer_checked(mode: &str, config: &Config) -> bool {
+    !config.allowed_referers.is_empty() && (mode != "attachment" || !config.referer_allow_attachment)
+}
+
+fn check_referer(headers: &HeaderMap, mode: &str, config: &Config) -> Result<(), ApiError> {
+    if !referer_checked(mode, config) {
+        return Ok(());
+    }
+
+    let source = headers
+        .get(header::ORIGIN)
+        .or_else(|| headers.get(header::REFERER))
+        .and_then(|v| v.to_str().ok())
+        .map(str::trim)
+        .filter(|v| !v.is_empty() && *v != "null");
+    let Some(source) = source else {
+        if config.referer_allow_empty {
+            return Ok(());
+        }
+        return Err(ApiError::new(ErrorCode::RefererNotAllowed));
+    };
+
+    let host = referer_host(source.split_once("://").map_or(source, |(_, rest)| rest));
+    let own_host = headers
+        .get(header::HOST)
+        .and_then(|v| v.to_str().ok())
+        .map(referer_host);
+    let allowed = own_host.as_deref() == Some(host.as_str())
+        || config.allowed_referers.iter().any(|pattern| match pattern.strip_prefix("*.") {
+            Some(domain) => host.strip_suffix(domain).is_some_and(|sub| sub.ends_with('.')),
+            None => *pattern == host,
+        });
+    if allowed {
+        return Ok(());
+    }
+    Err(ApiError::new(ErrorCode::RefererNotAllowed).with_details(serde_json::json!({"referer": host})))
+}
+
+fn referer_host(authority: &str) -> String {
+    let authority = authority.split(['/', '?', '#']).next().unwrap_or_default();
+    let authority = authority.rsplit_once('@').map_or(authority, |(_, host)| host);
+    let host = match authority.rsplit_once(':') {
+        Some((host, port)) if !host.contains(':') && port.bytes().all(|b| b.is_ascii_digit()) => host,
+        Some((host, port)) if host.ends_with(']') && port.bytes().all(|b| b.is_ascii_digit()) => host,
+        _ => authority,
+    };
+    host.to_ascii_lowercase()
+}
+
+fn check_signed_owner(signed_params: &SignedUrlParams, metadata: &FileMetadata) -> Result<(), ApiError> {
+    if signed_params.signature.is_empty() || signed_params.owner == metadata.owner {
+        return Ok(());
+    }
+    warn!("⚠️ owner_mismatch | {} | signed={} | stored={}", metadata.file_id, signed_params.owner, metadata.owner);
+    Err(ApiError::new(ErrorCode::OwnerMismatch))
+}
+
+fn check_signed_type(signed_params: &SignedUrlParams, metadata: &FileMetadata) -> Result<(), ApiError> {
+    if signed_params.signature.is_empty() || signed_params.file_type.trim().eq_ignore_ascii_case(&metadata.mime_type) {
+        return Ok(());
+    }
+    warn!("⚠️ type_mismatch | {} | signed={} | stored={}", metadata.file_id, signed_params.file_type, metadata.mime_type);
+    Err(ApiError::new(ErrorCode::TypeMismatch))
+}
+
+fn verify_signed_request(
+    state: &AppState,
+    client: IpAddr,
+    file_id: &str,
+    params: &HashMap<String, String>,
+) -> Result<SignedUrlParams, ApiError> {
+    if !state.config.signing {
+        return unsigned_params(file_id, params);
+    }
+
+    let client_key = client.to_string();
+    if let Some(tracker) = &state.signature_failures {
+        tracker.check(&client_key).map_err(|retry_after| {
+            ApiError::new(ErrorCode::SignatureBlocked)
+                .with_details(serde_json::json!({ "limit": tracker.limit }))
+                .with_retry_after(retry_after)
+        })?;
+    }
+
+    let signed_params = parse_signed_params(params)?;
+
+    if !matches!(signed_params.mode.as_str(), "inline" | "attachment") {
+        warn!("⚠️ invalid_mode | {} | {}", file_id, signed_params.mode);
+        return Err(ApiError::new(ErrorCode::InvalidMode));
+    }
+
+    if !verify_signature(&signed_params, &state.config)? {
+        warn!("⚠️ invalid_sig | {} | {}", file_id, client);
+        if state.signature_failures.as_ref().is_some_and(|tracker| tracker.record_failure(&client_key)) {
+            warn!("🚫 signature_blocked | {} | {}s", client, state.config.signature_fail_cooldown);
+        }
+        return Err(ApiError::new(ErrorCode::InvalidSignature));
+    }
+    if let Some(tracker) = &state.signature_failures {
+        tracker.record_success(&client_key);
+    }
+
+    let expires_timestamp = signed_params.expires.parse::<i64>().map_err(|_| ApiError::new(ErrorCode::InvalidExpires))?;
+
+    if Utc::now().timestamp() > expires_timestamp {
+        warn!("⚠️ expired | {}", file_id);
+        return Err(ApiError::new(ErrorCode::LinkExpired));
+    }
+
+    if signed_params.id != file_id {
+        return Err(ApiError::new(ErrorCode::IdMismatch));
+    }
+
+    if let Some(bound) = &signed_params.ip {
+        if bound.parse::<IpAddr>().map(|ip| ip.to_canonical()).ok() != Some(client) {
+            warn!("⚠️ ip_mismatch | {} | bound {} | from {}", file_id, bound, client);
+            return Err(ApiError::new(ErrorCode::IpMismatch));
+        }
+    }
+
+    Ok(signed_params)
+}
+
+fn parse_cidr(cidr: &str) -> Option<(IpAddr, u8)> {
+    let (addr, prefix) = match cidr.split_once('/') {
+        Some((addr, prefix)) => (addr.parse::<IpAddr>().ok()?, Some(prefix.parse::<u8>().ok()?)),
+        None => (cidr.parse::<IpAddr>().ok()?, None),
+    };
+    let max = if addr.is_ipv4() { 32 } else { 128 };
+    let prefix = prefix.unwrap_or(max);
+    (prefix <= max).then_some((addr, prefix))
+}
+
+fn cidr_contains((network, prefix): (IpAddr, u8), ip: IpAddr) -> bool {
+    match (network, ip.to_canonical()) {
+        (IpAddr::V4(network), IpAddr::V4(ip)) => {
+            let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+            u32::from(network) & mask == u32::from(ip) & mask
+        }
+        (IpAddr::V6(network), IpAddr::V6(ip)) => {
+            let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+            u128::from(network) & mask == u128::from(ip) & mask
+        }
+        _ => false,
+    }
+}
+
+fn is_trusted_proxy(config: &Config, ip: IpAddr) -> bool {
+    config
+        .trusted_proxies
+        .iter()
+        .filter_map(|cidr| parse_cidr(cidr))
+        .any(|network| cidr_contains(network, ip))
+}
+
+fn forwarded_hops(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    let forwarded: Vec<&str> = headers.get_all(header::FORWARDED).iter().filter_map(|v| v.to_str().ok()).collect();
+    if !forwarded.is_empty() {
+        return forwarded
+            .iter()
+            .flat_map(|value| value.split(','))
+            .map(|element| {
+                element
+                    .split(';')
+                    .filter_map(|pair| pair.split_once('='))
+                    .find(|(key, _)| key.trim().eq_ignore_ascii_case("for"))
+                    .and_then(|(_, node)| parse_forwarded_node(node))
+            })
+            .collect();
+    }
+    headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(parse_forwarded_node)
+        .collect()
+}
+
+fn parse_forwarded_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    if let Some(rest) = node.strip_prefix('[') {
+        return rest.split(']').next()?.parse().ok();
+    }
+    node.parse().ok().or_else(|| {
+        node.rsplit_once(':')
+            .and_then(|(addr, _)| addr.parse::<Ipv4Addr>().ok())
+            .map(IpAddr::V4)
+    })
+}
+
+fn client_ip(config: &Config, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+    let peer = peer.to_canonical();
+    if !is_trusted_proxy(config, peer) {
+        return peer;
+    }
+    let mut client = peer;
+    for hop in forwarded_hops(headers).into_iter().rev() {
+        let Some(hop) = hop else {
+            break;
+        };
+        client = hop.to_canonical();
+        if !is_trusted_proxy(config, client) {
+            break;
+        }
+    }
+    client
+}
+
+fn request_base_url(headers: &HeaderMap, config: &Config) -> String {
+    format!("{}{}", request_origin(headers, config), config.route_prefix)
+}
+
+fn request_origin(headers: &HeaderMap, config: &Config) -> String {
+    let fallback = || {
+        config
+            .base_url
+            .clone()
+            .unwrap_or_else(|| "http://localhost:3000".to_string())
+    };
+
+    if config.base_url.is_some() && !config.trust_host {
+        return fallback();
+    }
+
+    let host = match headers
+        .get(header::HOST)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.trim().to_ascii_lowercase())
+    {
+        Some(host) if is_valid_host(&host) => host,
+        _ => return fallback(),
+    };
+
+    if !config.trusted_hosts.is_empty() {
+        let bare = strip_port(&host);
+        if !config.trusted_hosts.iter().any(|t| t == &host || t == bare) {
+            warn!("⚠️ untrusted_host | {}", host);
+            return fallback();
+        }
+    }
+
+    let scheme = forwarded_proto(headers).unwrap_or_else(|| {
+        match config.base_url.as_deref().and_then(|url| url.split_once("://")) {
+            Some((scheme, _)) => scheme.to_ascii_lowercase(),
+            None if config.tls_cert.is_some() => "https".to_string(),
+            None => "http".to_string(),
+        }
+    });
+
+    format!("{}://{}", scheme, host)
+}
+
+fn forwarded_proto(headers: &HeaderMap) -> Option<String> {
+    let forwarded = headers
+        .get(header::FORWARDED)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(',').next())
+        .and_then(|element| {
+            element
+                .split(';')
+                .filter_map(|pair| pair.split_once('='))
+                .find(|(key, _)| key.trim().eq_ignore_ascii_case("proto"))
+                .map(|(_, proto)| proto.trim().trim_matches('"').to_string())
+        });
+    forwarded
+        .or_else(|| {
+            headers
+                .get("x-forwarded-proto")
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.split(',').next())
+                .map(|v| v.trim().to_string())
+        })
+        .map(|v| v.to_ascii_lowercase())
+        .filter(|v| v == "http" || v == "https")
+}
+
+fn is_valid_host(host: &str) -> bool {
+    !host.is_empty()
+        && host.len() <= 255
+        && host
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | ':' | '[' | ']'))
+}
+
+fn strip_port(host: &str) -> &str {
+    if host.starts_with('[') {
+        return host.split_once(']').map(|(h, _)| &host[..h.len() + 1]).unwrap_or(host);
+    }
+    host.rsplit_once(':').map(|(h, _)| h).unwrap_or(host)
+}
+
+fn generate_signed_url(base_url: &str, metadata: &FileMetadata, link: LinkOptions, config: &Config) -> String {
+    let file_id = &metadata.file_id;
+    if metadata.public || !config.signing {
+        let route = if metadata.public { "p" } else { "file" };
+        return match link.mode {
+            "inline" => format!("{}/{}/{}", base_url, route, file_id),
+            _ => format!("{}/{}/{}?sz-mode={}", base_url, route, file_id, link.mode),
+        };
+    }
+
+    format!(
+        "{}/file/{}?{}",
+        base_url,
+        file_id,
+        signed_query(&metadata.owner, file_id, &metadata.mime_type, &link, config)
+    )
+}
+
+fn signed_query(owner: &str, id: &str, file_type: &str, link: &LinkOptions, config: &Config) -> String {
+    let mut params = SignedUrlParams {
+        version: config.signing_version.clone(),
+        owner: owner.to_string(),
+        date: Utc::now().format("%Y%m%d").to_string(),
+        expires: link.expires_at.to_string(),
+        region: "global".to_string(),
+        mode: link.mode.to_string(),
+        file_type: file_type.to_string(),
+        id: id.to_string(),
+        nonce: link.nonce.map(str::to_string).unwrap_or_else(|| Uuid::new_v4().to_string()),
+        ip: link.bound_ip.map(|ip| ip.to_canonical().to_string()),
+        uses: link.max_uses.map(|uses| uses.to_string()),
+        signature: String::new(),
+    };
+    params.signature = sign_params(&params, signing_key(config, &params.owner)).unwrap_or_default();
+    serde_urlencoded::to_string(&params).unwrap_or_default()
+}
+
+const GROUP_LINK_TYPE: &str = "application/json";
+
+fn group_link(base_url: &str, group_id: &str, owner: &str, expires_at: i64, config: &Config) -> String {
+    if !config.signing {
+        return format!("{}/group/{}", base_url, group_id);
+    }
+    let link = LinkOptions::new("inline", expires_at);
+    format!("{}/group/{}?{}", base_url, group_id, signed_query(owner, group_id, GROUP_LINK_TYPE, &link, config))
+}
+
+fn share_link(base_url: &str, metadata: &FileMetadata, bound_ip: Option<IpAddr>, config: &Config) -> Option<String> {
+    if config.share_ttl == 0 || metadata.public || !config.signing {
+        return None;
+    }
+    let expires_at = metadata.uploaded_at.saturating_add(config.share_ttl as i64).min(metadata.expires_at);
+    let link = LinkOptions::new("inline", expires_at).with_ip(bound_ip);
+    Some(generate_signed_url(base_url, metadata, link, config))
+}
+
+fn compute_hmac(data: &str, secret: &str) -> String {
+    type HmacSha256 = Hmac<Sha256>;
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(data.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+fn string_to_sign(params: &SignedUrlParams) -> String {
+    if params.version == "v3" {
+        return canonical_query(params);
+    }
+    let mut signed = format!(
+        "{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}",
+        params.version, params.owner, params.date, params.expires,
+        params.region, params.mode, params.file_type, params.id, params.nonce
+    );
+    for (key, value) in [("sz-ip", &params.ip), ("sz-uses", &params.uses)] {
+        if let Some(value) = value {
+            signed.push_str(&format!("\n{}={}", key, value));
+        }
+    }
+    signed
+}
+
+fn canonical_query(params: &SignedUrlParams) -> String {
+    let mut pairs: Vec<(&str, &str)> = vec![
+        ("sz-version", &params.version),
+        ("sz-owner", &params.owner),
+        ("sz-date", &params.date),
+        ("sz-expires", &params.expires),
+        ("sz-region", &params.region),
+        ("sz-mode", &params.mode),
+        ("sz-type", &params.file_type),
+        ("sz-id", &params.id),
+        ("sz-nonce", &params.nonce),
+    ];
+    for (key, value) in [("sz-ip", &params.ip), ("sz-uses", &params.uses)] {
+        if let Some(value) = value {
+            pairs.push((key, value));
+        }
+    }
+    pairs.sort_unstable();
+    serde_urlencoded::to_string(&pairs).unwrap_or_default()
+}
+
+const SIGNATURE_VERSIONS: [&str; 3] = ["v1", "v2", "v3"];
+
+fn sign_params(params: &SignedUrlParams, secret: &str) -> Result<String, ApiError> {
+    let data = string_to_sign(params);
+    match params.version.as_str() {
+        "v1" | "v3" => Ok(compute_hmac(&data, secret)),
+        "v2" => {
+            let mut mac = Hmac::<sha2::Sha512>::new_from_slice(secret.as_bytes()).unwrap();
+            mac.update(data.as_bytes());
+            Ok(hex::encode(mac.finalize().into_bytes()))
+        }
+        _ => Err(ApiError::new(ErrorCode::UnsupportedVersion)
+            .with_details(serde_json::json!({ "supported": SIGNATURE_VERSIONS }))),
+    }
+}
+
+fn verify_signature(params: &SignedUrlParams, config: &Config) -> Result<bool, ApiError> {
+    Ok(sign_params(params, signing_key(config, &params.owner))? == params.signature)
+}
+
+fn signing_key<'a>(config: &'a Config, owner: &str) -> &'a str {
+    config.owner_signing_keys.get(owner).unwrap_or(&config.secret_key)
+}
+
+const MAX_SIGNED_PARAM_LEN: usize = 512;
+
+const SIGNED_PARAMS: [&str; 12] = [
+    "sz-version", "sz-owner", "sz-date", "sz-expires", "sz-region", "sz-mode",
+    "sz-type", "sz-id", "sz-nonce", "sz-ip", "sz-uses", "sz-signature",
+];
+
+fn parse_signed_params(
+    params: &HashMap<String, String>,
+) -> Result<SignedUrlParams, ApiError> {
+    for key in SIGNED_PARAMS {
+        if params.get(key).is_some_and(|value| value.len() > MAX_SIGNED_PARAM_LEN) {
+            warn!("⚠️ parameter_too_long | {}", key);
+            return Err(ApiError::new(ErrorCode::ParameterTooLong(key))
+                .with_details(serde_json::json!({ "parameter": key, "limit": MAX_SIGNED_PARAM_LEN })));
+        }
+    }
+
+    let get_param = |key: &'static str| {
+        params.get(key).cloned().ok_or_else(|| {
+            ApiError::new(ErrorCode::MissingParameter(key))
+        })
+    };
+
+    let signed = SignedUrlParams {
+        version: get_param("sz-version")?,
+        owner: get_param("sz-owner")?,
+        date: get_param("sz-date")?,
+        expires: get_param("sz-expires")?,
+        region: get_param("sz-region")?,
+        mode: get_param("sz-mode")?,
+        file_type: get_param("sz-type")?,
+        id: get_param("sz-id")?,
+        nonce: get_param("sz-nonce")?,
+        ip: params.get("sz-ip").cloned(),
+        uses: params.get("sz-uses").cloned(),
+        signature: get_param("sz-signature")?,
+    };
+
+    if !SIGNATURE_VERSIONS.contains(&signed.version.as_str()) {
+        return Err(ApiError::new(ErrorCode::UnsupportedVersion)
+            .with_details(serde_json::json!({ "supported": SIGNATURE_VERSIONS })));
+    }
+    if signed.expires.parse::<i64>().is_err() {
+        return Err(ApiError::new(ErrorCode::InvalidExpires));
+    }
+    if signed.version == "v3" {
+        if let Some(extra) = params.keys().find(|key| key.starts_with("sz-") && !SIGNED_PARAMS.contains(&key.as_str())) {
+            warn!("⚠️ unexpected_parameter | {}", extra);
+            return Err(ApiError::new(ErrorCode::UnexpectedParameter).with_details(serde_json::json!({ "parameter": extra })));
+        }
+    }
+    let malformed = if signed.date.len() != 8 || !signed.date.bytes().all(|b| b.is_ascii_digit()) {
+        Some("sz-date")
+    } else if signed.ip.as_deref().is_some_and(|ip| ip.parse::<IpAddr>().is_err()) {
+        Some("sz-ip")
+    } else if signed.uses.as_deref().is_some_and(|uses| !uses.parse::<u64>().is_ok_and(|uses| uses > 0)) {
+        Some("sz-uses")
+    } else if !signed.signature.bytes().all(|b| b.is_ascii_hexdigit()) {
+        Some("sz-signature")
+    } else {
+        None
+    };
+    if let Some(key) = malformed {
+        warn!("⚠️ invalid_parameter | {}", key);
+        return Err(ApiError::new(ErrorCode::InvalidParameter(key)));
+    }
+    Ok(signed)
+}
+
+const MIME_CLASSES: [&str; 5] = ["image", "video", "audio", "text", "application"];
+
+fn class_size_limit(mime_type: &str, config: &Config) -> Option<(&'static str, u64)> {
+    let class = mime_type.split('/').next()?;
+    config
+        .class_size_limits
+        .get_key_value(class)
+        .map(|(class, limit)| (*class, *limit))
+}
+
+const TRAILER_DRAIN_LIMIT: usize = 65536;
+
+const METADATA_FIELDS: [&str; 12] = [
+    "content_type",
+    "ttl",
+    "expires_at",
+    "owner",
+    "password",
+    "public",
+    "download_name",
+    "worm",
+    "size",
+    "description",
+    "bind_ip",
+    "max_concurrent",
+];
+
+fn parse_expires_at(value: &str, max_lifetime: u64) -> Result<i64, ApiError> {
+    let now = Utc::now().timestamp();
+    match value.trim().parse::<i64>() {
+        Ok(at) if at > now => Ok(at.min(now.saturating_add(max_lifetime as i64))),
+        _ => Err(ApiError::new(ErrorCode::InvalidExpiresAt).with_details(serde_json::json!({ "now": now }))),
+    }
+}
+
+fn expiry_ttl(requested_expiry: Option<i64>, default_ttl: u64) -> u64 {
+    match requested_expiry {
+        Some(at) => at.saturating_sub(Utc::now().timestamp()).max(1) as u64,
+        None => default_ttl,
+    }
+}
+
+async fn within_idle<F: std::future::Future>(secs: u64, file_id: &str, next: F) -> Result<F::Output, ApiError> {
+    if secs == 0 {
+        return Ok(next.await);
+    }
+    tokio::time::timeout(Duration::from_secs(secs), next).await.map_err(|_| {
+        warn!("⏱️ upload_stalled | {} | no data for {}s", file_id, secs);
+        ApiError::new(ErrorCode::UploadTimeout).with_details(serde_json::json!({ "idle_timeout": secs }))
+    })
+}
+
+fn conflicting_field(field: &str) -> ApiError {
+    ApiError::new(ErrorCode::ConflictingField).with_details(serde_json::json!({ "field": field }))
+}
+
+fn check_password(state: &AppState, client: IpAddr, metadata: &FileMetadata, headers: &HeaderMap) -> Result<(), ApiError> {
+    let Some(expected) = &metadata.password_hash else {
+        return Ok(());
+    };
+    let provided = headers
+        .get("x-file-password")
+        .and_then(|v| v.to_str().ok())
+        .ok_or_else(|| ApiError::new(ErrorCode::PasswordRequired))?;
+    let attempt_key = format!("{}|{}", metadata.file_id, client);
+    if let Some(tracker) = &state.password_failures {
+        tracker.check(&attempt_key).map_err(|retry_after| {
+            ApiError::new(ErrorCode::PasswordBlocked)
+                .with_details(serde_json::json!({ "limit": tracker.limit }))
+                .with_retry_after(retry_after)
+        })?;
+    }
+    if constant_time_eq(compute_hmac(provided, &state.config.secret_key).as_bytes(), expected.as_bytes()) {
+        if let Some(tracker) = &state.password_failures {
+            tracker.record_success(&attempt_key);
+        }
+        Ok(())
+    } else {
+        warn!("⚠️ invalid_password | {}", metadata.file_id);
+        if state.password_failures.as_ref().is_some_and(|tracker| tracker.record_failure(&attempt_key)) {
+            warn!("🚫 password_blocked | {} | {} | {}s", metadata.file_id, client, state.config.password_fail_cooldown);
+        }
+        Err(ApiError::new(ErrorCode::InvalidPassword))
+    }
+}
+
+fn check_rate_limit(limiter: &Option<Arc<RateLimiter>>, scope: &str, key: &str) -> Result<(), ApiError> {
+    let Some(limiter) = limiter else {
+        return Ok(());
+    };
+    limiter.check(key).map_err(|retry_after| {
+        warn!("🚦 rate_limited | {} | {} | retry in {}s", scope, key, retry_after);
+        ApiError::new(ErrorCode::RateLimited)
+            .with_details(serde_json::json!({ "limit": scope, "per_minute": limiter.per_minute }))
+            .with_retry_after(retry_after)
+    })
+}
+
+fn check_declared_size(
+    state: &AppState,
+    pool: &str,
+    size: u64,
+    max_file_size: u64,
+    class_limit: Option<(&'static str, u64)>,
+) -> Result<(), ApiError> {
+    if size > max_file_size {
+        return Err(file_too_large(max_file_size));
+    }
+    if let Some((class, limit)) = class_limit.filter(|(_, limit)| size > *limit) {
+        return Err(class_too_large(class, limit));
+    }
+    if size < state.config.min_file_size {
+        return Err(ApiError::new(ErrorCode::FileTooSmall)
+            .with_details(serde_json::json!({ "size": size, "min_file_size": state.config.min_file_size })));
+    }
+    if let Some(max_total) = state.config.max_total_bytes.filter(|_| !state.config.evict_on_full) {
+        if state.stored_bytes.load(Ordering::Relaxed).saturating_add(size) > max_total {
+            return Err(ApiError::new(ErrorCode::StorageCapReached)
+                .with_details(serde_json::json!({ "max_total_bytes": max_total })));
+        }
+    }
+    if free_disk_space(pool).is_some_and(|free| free < size.saturating_add(state.config.pool_reserve)) {
+        warn!("⚠️ declared_size_exceeds_free_space | {} | {} bytes", pool, size);
+        return Err(ApiError::new(ErrorCode::InsufficientStorage));
+    }
+    Ok(())
+}
+
+fn check_not_expired(metadata: &FileMetadata, config: &Config) -> Result<(), ApiError> {
+    let now = Utc::now().timestamp();
+    if metadata.expires_at > now {
+        return Ok(());
+    }
+    let remove_at = metadata.expires_at.saturating_add(config.grace_period as i64);
+    if remove_at <= now {
+        return Err(ApiError::new(ErrorCode::FileNotFound));
+    }
+    Err(ApiError::new(ErrorCode::FileExpired).with_details(serde_json::json!({ "removed_at": remove_at })))
+}
+
+fn file_too_large(limit: u64) -> ApiError {
+    ApiError::new(ErrorCode::FileTooLarge).with_details(serde_json::json!({ "limit": limit }))
+}
+
+fn class_too_large(class: &'static str, limit: u64) -> ApiError {
+    warn!("⚠️ class_limit_exceeded | {} | {} bytes", class, limit);
+    ApiError::new(ErrorCode::ClassTooLarge(class)).with_details(serde_json::json!({ "class": class, "limit": limit }))
+}
+
+fn multipart_error(e: MultipartError, code: ErrorCode) -> ApiError {
+    if e.status() == StatusCode::PAYLOAD_TOO_LARGE {
+        ApiError::new(ErrorCode::FileTooLarge)
+    } else {
+        ApiError::new(code)
+    }
+}
+
+fn json_error(e: JsonRejection, config: &Config) -> ApiError {
+    if e.status() == StatusCode::PAYLOAD_TOO_LARGE {
+        ApiError::new(ErrorCode::BodyTooLarge).with_details(serde_json::json!({ "limit": config.max_json_body }))
+    } else {
+        ApiError::new(ErrorCode::InvalidBody)
+    }
+}
+
+fn check_extension(filename: &str, config: &Config) -> Result<(), ApiError> {
+    if config.allowed_extensions.is_empty() && config.blocked_extensions.is_empty() {
+        return Ok(());
+    }
+    let extension = std::path::Path::new(filename)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    let blocked = config.blocked_extensions.contains(&extension)
+        || (!config.allowed_extensions.is_empty() && !config.allowed_extensions.contains(&extension));
+    if blocked {
+        warn!("⚠️ extension_not_allowed | {} | {}", filename, extension);
+        return Err(ApiError::new(ErrorCode::ExtensionNotAllowed).with_details(serde_json::json!({ "extension": extension })));
+    }
+    Ok(())
+}
+
+fn is_allowed_mime(mime_type: &str, config: &Config) -> bool {
+    if config.allowed_mime_types.is_empty() {
+        return true;
+    }
+    mime_matches(mime_type, &config.allowed_mime_types)
+}
+
+fn mime_essence(mime_type: &str) -> String {
+    mime_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase()
+}
+
+fn mime_matches(mime_type: &str, patterns: &[String]) -> bool {
+    let essence = mime_essence(mime_type);
+    patterns.iter().any(|pattern| match pattern.strip_suffix("/*") {
+        Some(class) => essence.split('/').next() == Some(class),
+        None => *pattern == essence,
+    })
+}
+
+fn sniff_mime(header_bytes: &[u8]) -> Option<String> {
+    if let Ok(format) = image::guess_format(header_bytes) {
+        return Some(format.to_mime_type().to_string());
+    }
+    MIME_SIGNATURES
+        .iter()
+        .find(|(offset, magic, _)| header_bytes.get(*offset..*offset + magic.len()) == Some(*magic))
+        .map(|(_, _, mime)| mime.to_string())
+}
+
+fn correct_mime(config: &Config, file_id: &str, mime_type: String, header_bytes: &[u8]) -> String {
+    if !config.sniff_mime || !config.sniff_generic_types.contains(&mime_essence(&mime_type)) {
+        return mime_type;
+    }
+    match sniff_mime(header_bytes) {
+        Some(sniffed) => {
+            info!("🔎 mime_corrected | {} | {} -> {}", file_id, mime_type, sniffed);
+            sniffed
+        }
+        None => mime_type,
+    }
+}
+
+fn is_viewable_mime(mime_type: &str, config: &Config) -> bool {
+    let essence = mime_essence(mime_type);
+    !FORCE_ATTACHMENT_MIME.contains(&essence.as_str())
+        && (mime_matches(mime_type, &config.inline_mime_types) || (config.inline_text && essence.starts_with("text/")))
+}
+
+fn text_charset(mime_type: &str, head: &[u8], total_size: u64) -> Option<String> {
+    if !mime_essence(mime_type).starts_with("text/") {
+        return None;
+    }
+    let charset = match head {
+        [0xEF, 0xBB, 0xBF, ..] => "utf-8",
+        [0xFF, 0xFE, ..] => "utf-16le",
+        [0xFE, 0xFF, ..] => "utf-16be",
+        _ => {
+            let mut detector = chardetng::EncodingDetector::new();
+            detector.feed(head, total_size <= head.len() as u64);
+            return Some(detector.guess(None, true).name().to_ascii_lowercase());
+        }
+    };
+    Some(charset.to_string())
+}
+
+fn served_content_type(metadata: &FileMetadata) -> String {
+    match &metadata.charset {
+        Some(charset) if !metadata.mime_type.contains(';') => format!("{}; charset={}", metadata.mime_type, charset),
+        _ => metadata.mime_type.clone(),
+    }
+}
+
+struct PartialUpload {
+    paths: Vec<PathBuf>,
+    committed: bool,
+}
+
+impl PartialUpload {
+    fn new(disk_path: &std::path::Path) -> Self {
+        Self {
+            paths: vec![disk_path.to_path_buf(), disk_path.with_extension("strip.tmp")],
+            committed: false,
+        }
+    }
+
+    fn track(&mut self, path: impl Into<PathBuf>) {
+        self.paths.push(path.into());
+    }
+
+    fn commit(&mut self) {
+        self.committed = true;
+    }
+}
+
+impl Drop for PartialUpload {
+    fn drop(&mut self) {
+        if self.committed {
+            return;
+        }
+        for path in &self.paths {
+            match std::fs::remove_file(path) {
+                Ok(_) => info!("🧹 partial_removed | {}", path.display()),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => error!("❌ partial_cleanup_failed | {} | {}", path.display(), e),
+            }
+        }
+    }
+}
+
+async fn scan_upload(state: &AppState, file_id: &str, original_filename: &str, disk_path: &std::path::Path) -> Result<(), ApiError> {
+    let Some(clamav_addr) = &state.config.clamav_addr else {
+        return Ok(());
+    };
+    let scan = tokio::time::timeout(
+        Duration::from_secs(state.config.clamav_timeout),
+        clamav_scan(clamav_addr, disk_path),
+    )
+    .await
+    .unwrap_or_else(|_| Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "clamd timed out")));
+
+    match scan {
+        Ok(ScanVerdict::Clean) => Ok(()),
+        Ok(ScanVerdict::Infected(signature)) => {
+            warn!("🦠 infected | {} | {} | {}", original_filename, file_id, signature);
+            let details = serde_json::json!({ "signature": signature });
+            quarantine_rejected(state, disk_path, file_id, original_filename, "infected", details).await;
+            Err(ApiError::new(ErrorCode::Infected))
+        }
+        Err(e) if state.config.clamav_fail_closed => {
+            error!("❌ scan_failed | {} | {}", file_id, e);
+            Err(ApiError::new(ErrorCode::ScanFailed))
+        }
+        Err(e) => {
+            warn!("⚠️ scan_failed | {} | {} | fail-open", file_id, e);
+            Ok(())
+        }
+    }
+}
+
+async fn sync_upload(config: &Config, disk_path: &std::path::Path) -> Result<(), ApiError> {
+    if config.fsync == FsyncPolicy::None {
+        return Ok(());
+    }
+    let synced = async {
+        let file = File::open(disk_path).await?;
+        if config.fsync == FsyncPolicy::Full {
+            file.sync_all().await?;
+            if let Some(dir) = disk_path.parent() {
+                File::open(dir).await?.sync_all().await?;
+            }
+        } else {
+            file.sync_data().await?;
+        }
+        Ok::<_, std::io::Error>(())
+    };
+    synced.await.map_err(|e| {
+        error!("❌ fsync_failed | {} | {}", disk_path.display(), e);
+        ApiError::new(ErrorCode::FlushFailed)
+    })
+}
+
+async fn register_upload(state: &AppState, metadata: &FileMetadata, partial: &mut PartialUpload) -> Result<(), ApiError> {
+    if !try_reserve_owner_file(state, &metadata.owner) {
+        let limit = state.config.max_files_per_owner.unwrap_or_default();
+        warn!("⚠️ owner_file_limit | {} | {} files", metadata.owner, limit);
+        return Err(ApiError::new(ErrorCode::OwnerFileLimit).with_details(serde_json::json!({ "limit": limit })));
+    }
+    if !reserve_storage(state, metadata.size).await {
+        release_owner_file(state, &metadata.owner);
+        let max_total = state.config.max_total_bytes.unwrap_or_default();
+        error!("❌ storage_cap_reached | {} | {} of {} bytes used", metadata.file_id, state.stored_bytes.load(Ordering::Relaxed), max_total);
+        return Err(ApiError::new(ErrorCode::StorageCapReached)
+            .with_details(serde_json::json!({ "max_total_bytes": max_total })));
+    }
+
+    if let Some(max_entries) = state.config.max_registry_entries {
+        evict_for_capacity(state, max_entries).await;
+    }
+
+    if let Err(e) = state.file_registry.insert(metadata.clone()).await {
+        error!("❌ registry_insert_failed | {} | {}", metadata.file_id, e);
+        release_storage(state, metadata.size);
+        release_owner_file(state, &metadata.owner);
+        return Err(ApiError::new(ErrorCode::RegistryFailed));
+    }
+    partial.commit();
+    schedule_expiry(state, &metadata.file_id, metadata.expires_at);
+    publish_event(state, || FileEvent {
+        event: "upload",
+        file_id: metadata.file_id.clone(),
+        owner: metadata.owner.clone(),
+        size: Some(metadata.size),
+        mime: Some(metadata.mime_type.clone()),
+        status: None,
+        reason: None,
+        timestamp: metadata.uploaded_at,
+    });
+    Ok(())
+}
+
+enum ScanVerdict {
+    Clean,
+    Infected(String),
+}
+
+async fn clamav_scan(addr: &str, disk_path: &std::path::Path) -> std::io::Result<ScanVerdict> {
+    if addr.starts_with('/') {
+        #[cfg(unix)]
+        {
+            let stream = tokio::net::UnixStream::connect(addr).await?;
+            return clamav_instream(stream, disk_path).await;
+        }
+        #[cfg(not(unix))]
+        return Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "unix sockets unsupported"));
+    }
+    let stream = tokio::net::TcpStream::connect(addr).await?;
+    clamav_instream(stream, disk_path).await
+}
+
+async fn clamav_instream<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: S,
+    disk_path: &std::path::Path,
+) -> std::io::Result<ScanVerdict> {
+    stream.write_all(b"zINSTREAM\0").await?;
+
+    let mut file = File::open(disk_path).await?;
+    let mut chunk = vec![0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut chunk).await?;
+        if n == 0 {
+            break;
+        }
+        stream.write_all(&(n as u32).to_be_bytes()).await?;
+        stream.write_all(&chunk[..n]).await?;
+    }
+    stream.write_all(&0u32.to_be_bytes()).await?;
+    stream.flush().await?;
+
+    let mut reply = Vec::new();
+    let mut buf = [0u8; 256];
+    loop {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        reply.extend_from_slice(&buf[..n]);
+        if reply.contains(&0) || reply.len() > 4096 {
+            break;
+        }
+    }
+    let reply = String::from_utf8_lossy(&reply);
+    let reply = reply.trim_end_matches(['\0', '\n']).trim();
+
+    if reply.ends_with("OK") {
+        Ok(ScanVerdict::Clean)
+    } else if let Some(found) = reply.strip_suffix("FOUND") {
+        let signature = found.trim().trim_start_matches("stream:").trim();
+        Ok(ScanVerdict::Infected(signature.to_string()))
+    } else {
+        Err(std::io::Error::other(reply.to_string()))
+    }
+}
+
+fn is_thumbnailable_mime(mime_type: &str) -> bool {
+    image::ImageFormat::from_mime_type(mime_type).is_some_and(|f| f.reading_enabled())
+}
+
+async fn create_thumbnail(disk_path: &str, mime_type: &str, max_size: u32) -> Option<String> {
+    let source = disk_path.to_string();
+    let format = image::ImageFormat::from_mime_type(mime_type)?;
+    let encoded = tokio::task::spawn_blocking(move || -> Result<Vec<u8>, image::ImageError> {
+        let reader = std::io::BufReader::new(std::fs::File::open(&source)?);
+        let thumb = image::load(reader, format)?.thumbnail(max_size, max_size);
+        let mut out = std::io::Cursor::new(Vec::new());
+        thumb.write_to(&mut out, image::ImageFormat::Png)?;
+        Ok(out.into_inner())
+    })
+    .await;
+
+    let encoded = match encoded {
+        Ok(Ok(bytes)) => bytes,
+        Ok(Err(e)) => {
+            warn!("⚠️ thumb_failed | {} | {}", disk_path, e);
+            return None;
+        }
+        Err(e) => {
+            error!("❌ thumb_task_failed | {} | {}", disk_path, e);
+            return None;
+        }
+    };
+
+    let thumb_path = format!("{}.thumb.png", disk_path.trim_end_matches(".bin"));
+    let tmp_path = format!("{}.tmp", thumb_path);
+    if let Err(e) = fs::write(&tmp_path, &encoded).await {
+        error!("❌ thumb_write_failed | {} | {}", thumb_path, e);
+        return None;
+    }
+    if let Err(e) = fs::rename(&tmp_path, &thumb_path).await {
+        error!("❌ thumb_write_failed | {} | {}", thumb_path, e);
+        let _ = fs::remove_file(&tmp_path).await;
+        return None;
+    }
+    Some(thumb_path)
+}
+
+fn transcode_path(disk_path: &str, format: &str) -> String {
+    format!("{}.transcode.{}", disk_path.trim_end_matches(".bin"), format)
+}
+
+fn requested_transcode<'a>(
+    params: &HashMap<String, String>,
+    metadata: &FileMetadata,
+    config: &'a Config,
+) -> Result<Option<&'a str>, ApiError> {
+    let Some(requested) = params.get("format").map(|v| v.trim().to_ascii_lowercase()) else {
+        return Ok(None);
+    };
+    if config.transcode_formats.is_empty() {
+        return Ok(None);
+    }
+    let format = config
+        .transcode_formats
+        .iter()
+        .find(|f| **f == requested)
+        .ok_or_else(|| ApiError::new(ErrorCode::InvalidFormat).with_details(serde_json::json!({ "allowed": config.transcode_formats })))?;
+    let target = image::ImageFormat::from_extension(format).map(|f| f.to_mime_type());
+    let fits = metadata.width.zip(metadata.height).is_some_and(|(w, h)| w.max(h) <= config.transcode_max_dimension);
+    if !is_thumbnailable_mime(&metadata.mime_type) || target == Some(metadata.mime_type.as_str()) || !fits {
+        return Ok(None);
+    }
+    Ok(Some(format))
+}
+
+async fn transcoded_variant(state: &AppState, metadata: &FileMetadata, format: &str) -> Option<(FileMetadata, String)> {
+    let target = image::ImageFormat::from_extension(format)?;
+    let path = transcode_path(&metadata.disk_path, format);
+    if !metadata.transcodes.iter().any(|f| f == format) {
+        transcode_image(&metadata.disk_path, &metadata.mime_type, target, &path).await?;
+        let recorded = format.to_string();
+        let updated = state
+            .file_registry
+            .update(&metadata.file_id, move |entry| {
+                if !entry.transcodes.contains(&recorded) {
+                    entry.transcodes.push(recorded);
+                }
+            })
+            .await;
+        if updated.is_none() {
+            let _ = fs::remove_file(&path).await;
+            return None;
+        }
+        info!("🎞️ transcoded | {} | {} -> {}", metadata.file_id, metadata.mime_type, format);
+    }
+    let mut variant = metadata.clone();
+    variant.mime_type = target.to_mime_type().to_string();
+    variant.checksum = format!("{}-{}", metadata.checksum, format);
+    variant.original_name = std::path::Path::new(&metadata.original_name)
+        .with_extension(target.extensions_str().first().copied().unwrap_or(format))
+        .to_string_lossy()
+        .into_owned();
+    variant.download_name = None;
+    variant.encodings.clear();
+    Some((variant, path))
+}
+
+async fn transcode_image(disk_path: &str, mime_type: &str, target: image::ImageFormat, path: &str) -> Option<()> {
+    let source = disk_path.to_string();
+    let format = image::ImageFormat::from_mime_type(mime_type)?;
+    let encoded = tokio::task::spawn_blocking(move || -> Result<Vec<u8>, image::ImageError> {
+        let reader = std::io::BufReader::new(std::fs::File::open(&source)?);
+        let mut decoded = image::load(reader, format)?;
+        if target == image::ImageFormat::Jpeg {
+            decoded = image::DynamicImage::ImageRgb8(decoded.to_rgb8());
+        }
+        let mut out = std::io::Cursor::new(Vec::new());
+        decoded.write_to(&mut out, target)?;
+        Ok(out.into_inner())
+    })
+    .await;
+
+    let encoded = match encoded {
+        Ok(Ok(bytes)) => bytes,
+        Ok(Err(e)) => {
+            warn!("⚠️ transcode_failed | {} | {}", disk_path, e);
+            return None;
+        }
+        Err(e) => {
+            error!("❌ transcode_task_failed | {} | {}", disk_path, e);
+            return None;
+        }
+    };
+
+    let tmp_path = format!("{}.{}.tmp", path, Uuid::new_v4().simple());
+    if let Err(e) = fs::write(&tmp_path, &encoded).await {
+        error!("❌ transcode_write_failed | {} | {}", path, e);
+        return None;
+    }
+    if let Err(e) = fs::rename(&tmp_path, path).await {
+        error!("❌ transcode_write_failed | {} | {}", path, e);
+        let _ = fs::remove_file(&tmp_path).await;
+        return None;
+    }
+    Some(())
+}
+
+const PRECOMPRESSED_ENCODINGS: [&str; 2] = ["br", "gzip"];
+
+fn is_compressible_mime(mime_type: &str) -> bool {
+    mime_type.starts_with("text/")
+        || mime_type.ends_with("+json")
+        || mime_type.ends_with("+xml")
+        || matches!(
+            mime_type,
+            "application/json" | "application/javascript" | "application/xml" | "application/wasm"
+        )
+}
+
+fn variant_path(disk_path: &str, encoding: &str) -> String {
+    let extension = if encoding == "br" { "br" } else { "gz" };
+    format!("{}.{}", disk_path.trim_end_matches(".bin"), extension)
+}
+
+async fn precompress_variants(disk_path: &str, size: u64) -> Vec<String> {
+    let mut encodings = Vec::new();
+    for encoding in PRECOMPRESSED_ENCODINGS {
+        let source = disk_path.to_string();
+        let target = variant_path(disk_path, encoding);
+        let written = tokio::task::spawn_blocking(move || -> std::io::Result<u64> {
+            let mut input = std::io::BufReader::new(std::fs::File::open(&source)?);
+            let output = std::fs::File::create(&target)?;
+            if encoding == "br" {
+                let mut writer = brotli::CompressorWriter::new(output, 64 * 1024, 9, 22);
+                std::io::copy(&mut input, &mut writer)?;
+                writer.into_inner().sync_all()?;
+            } else {
+                let mut writer = flate2::write::GzEncoder::new(output, flate2::Compression::best());
+                std::io::copy(&mut input, &mut writer)?;
+                writer.finish()?.sync_all()?;
+            }
+            Ok(std::fs::metadata(&target)?.len())
+        })
+        .await;
+
+        match written {
+            Ok(Ok(compressed)) if compressed < size => encodings.push(encoding.to_string()),
+            Ok(Ok(_)) => {
+                let _ = fs::remove_file(variant_path(disk_path, encoding)).await;
+            }
+            Ok(Err(e)) => {
+                warn!("⚠️ precompress_failed | {} | {} | {}", disk_path, encoding, e);
+                let _ = fs::remove_file(variant_path(disk_path, encoding)).await;
+            }
+            Err(e) => error!("❌ precompress_task_failed | {} | {}", disk_path, e),
+        }
+    }
+    encodings
+}
+
+fn parse_accept_encoding(headers: &HeaderMap) -> Vec<(String, f32)> {
+    headers
+        .get_all(header::ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter_map(|item| {
+            let mut parts = item.split(';');
+            let coding = parts.next()?.trim().to_ascii_lowercase();
+            if coding.is_empty() {
+                return None;
+            }
+            let quality = match parts.find_map(|p| {
+                let (key, value) = p.split_once('=')?;
+                key.trim().eq_ignore_ascii_case("q").then(|| value.trim())
+            }) {
+                Some(q) => q.parse::<f32>().ok().filter(|q| q.is_finite())?.clamp(0.0, 1.0),
+                None => 1.0,
+            };
+            Some((coding, quality))
+        })
+        .collect()
+}
+
+fn coding_quality(accepted: &[(String, f32)], coding: &str) -> Option<f32> {
+    accepted
+        .iter()
+        .find(|(c, _)| c == coding)
+        .or_else(|| accepted.iter().find(|(c, _)| c == "*"))
+        .map(|(_, q)| *q)
+}
+
+fn negotiate_encoding<'a>(headers: &HeaderMap, available: &'a [String]) -> Option<&'a str> {
+    if available.is_empty() {
+        return None;
+    }
+    let accepted = parse_accept_encoding(headers);
+    if accepted.is_empty() {
+        return None;
+    }
+
+    let (encoding, quality) = PRECOMPRESSED_ENCODINGS
+        .iter()
+        .filter(|encoding| available.iter().any(|a| a == *encoding))
+        .filter_map(|encoding| {
+            let quality = coding_quality(&accepted, encoding)?;
+            (quality > 0.0).then_some((*encoding, quality))
+        })
+        .fold(None, |best: Option<(&str, f32)>, candidate| match best {
+            Some(current) if current.1 >= candidate.1 => Some(current),
+            _ => Some(candidate),
+        })?;
+    let identity = coding_quality(&accepted, "identity").unwrap_or(0.0);
+    if identity > quality {
+        return None;
+    }
+    available.iter().find(|a| *a == encoding).map(String::as_str)
+}
+
+fn is_strippable_mime(mime_type: &str) -> bool {
+    matches!(mime_type, "image/jpeg" | "image/png" | "image/webp")
+}
+
+async fn strip_image_metadata(disk_path: &std::path::Path, mime_type: &str) -> Option<(u64, String)> {
+    let source = disk_path.to_path_buf();
+    let mime = mime_type.to_string();
+    let stripped = tokio::task::spawn_blocking(move || -> Result<Option<Vec<u8>>, Box<dyn std::error::Error + Send + Sync>> {
+        let original = img_parts::Bytes::from(std::fs::read(&source)?);
+        let original_len = original.len();
+        let encoded = match mime.as_str() {
+            "image/jpeg" => {
+                let mut jpeg = img_parts::jpeg::Jpeg::from_bytes(original)?;
+                jpeg.remove_segments_by_marker(img_parts::jpeg::markers::APP1);
+                jpeg.remove_segments_by_marker(img_parts::jpeg::markers::APP13);
+                jpeg.encoder().bytes()
+            }
+            "image/png" => {
+                let mut png = img_parts::png::Png::from_bytes(original)?;
+                if png.chunk_by_type(*b"acTL").is_some() {
+                    return Ok(None);
+                }
+                for kind in [*b"eXIf", *b"tEXt", *b"zTXt", *b"iTXt"] {
+                    png.remove_chunks_by_type(kind);
+                }
+                png.encoder().bytes()
+            }
+            "image/webp" => {
+                let mut webp = img_parts::webp::WebP::from_bytes(original)?;
+                if webp.has_chunk(img_parts::webp::CHUNK_ANIM) {
+                    return Ok(None);
+                }
+                img_parts::ImageEXIF::set_exif(&mut webp, None);
+                webp.encoder().bytes()
+            }
+            _ => return Ok(None),
+        };
+        if encoded.len() == original_len {
+            return Ok(None);
+        }
+        Ok(Some(encoded.to_vec()))
+    })
+    .await;
+
+    let stripped = match stripped {
+        Ok(Ok(Some(bytes))) => bytes,
+        Ok(Ok(None)) => return None,
+        Ok(Err(e)) => {
+            warn!("⚠️ exif_strip_failed | {} | {}", disk_path.display(), e);
+            return None;
+        }
+        Err(e) => {
+            error!("❌ exif_task_failed | {} | {}", disk_path.display(), e);
+            return None;
+        }
+    };
+
+    let tmp_path = disk_path.with_extension("strip.tmp");
+    if let Err(e) = fs::write(&tmp_path, &stripped).await {
+        warn!("⚠️ exif_strip_failed | {} | {}", disk_path.display(), e);
+        let _ = fs::remove_file(&tmp_path).await;
+        return None;
+    }
+    if let Err(e) = fs::rename(&tmp_path, disk_path).await {
+        warn!("⚠️ exif_strip_failed | {} | {}", disk_path.display(), e);
+        let _ = fs::remove_file(&tmp_path).await;
+        return None;
+    }
+    Some((stripped.len() as u64, hex::encode(Sha256::digest(&stripped))))
+}
+
+fn image_dimensions(mime_type: &str, header_bytes: &[u8]) -> (Option<u32>, Option<u32>) {
+    if !mime_type.starts_with("image/") {
+        return (None, None);
+    }
+    match imagesize::blob_size(header_bytes) {
+        Ok(size) => (u32::try_from(size.width).ok(), u32::try_from(size.height).ok()),
+        Err(e) => {
+            warn!("⚠️ image_size_failed | {} | {}", mime_type, e);
+            (None, None)
+        }
+    }
+}
+
+fn sanitize_filename(filename: &str, max_len: usize) -> String {
+    let cleaned: String = filename.chars()
+        .filter(|c| c.is_alphanumeric() || *c == '.' || *c == '-' || *c == '_')
+        .collect();
+    truncate_filename(cleaned, max_len)
+}
+
+fn sanitize_description(description: &str) -> String {
+    description
+        .replace("\r\n", "\n")
+        .chars()
+        .filter(|c| !matches!(c, '\u{202a}'..='\u{202e}' | '\u{2066}'..='\u{2069}' | '\u{200e}' | '\u{200f}'))
+        .map(|c| if c.is_control() && c != '\n' { ' ' } else { c })
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+fn sanitize_download_name(name: &str, max_len: usize) -> String {
+    let cleaned: String = name
+        .chars()
+        .filter(|c| !c.is_control() && !matches!(c, '/' | '\\' | '"'))
+        .collect();
+    let cleaned = cleaned.trim().trim_start_matches('.').to_string();
+    truncate_filename(cleaned, max_len)
+}
+
+const NAME_TEMPLATE_PLACEHOLDERS: [&str; 3] = ["id", "name", "ext"];
+
+fn validate_name_template(template: &str) -> Result<(), String> {
+    let mut rest = template;
+    while let Some(open) = rest.find(['{', '}']) {
+        if rest[open..].starts_with('}') {
+            return Err(format!("unmatched '}}' in SPTZX_DOWNLOAD_NAME_TEMPLATE '{}'", template));
+        }
+        let Some(close) = rest[open..].find('}') else {
+            return Err(format!("unclosed '{{' in SPTZX_DOWNLOAD_NAME_TEMPLATE '{}'", template));
+        };
+        let placeholder = &rest[open + 1..open + close];
+        if !NAME_TEMPLATE_PLACEHOLDERS.contains(&placeholder) {
+            return Err(format!(
+                "unknown placeholder '{{{}}}' in SPTZX_DOWNLOAD_NAME_TEMPLATE (use {{id}}, {{name}} or {{ext}})",
+                placeholder
+            ));
+        }
+        rest = &rest[open + close + 1..];
+    }
+    Ok(())
+}
+
+fn served_name<'a>(metadata: &'a FileMetadata, config: &Config) -> &'a str {
+    match (config.download_filename, &metadata.client_name) {
+        (DownloadFilename::Original, Some(name)) => name,
+        _ => &metadata.original_name,
+    }
+}
+
+fn render_download_name(config: &Config, file_id: &str, name: &str) -> String {
+    if config.download_name_template == "{name}" {
+        return name.to_string();
+    }
+
+    let ext = match name.rfind('.') {
+        Some(dot) if dot > 0 => &name[dot + 1..],
+        _ => "",
+    };
+    let mut rendered = String::new();
+    let mut rest = config.download_name_template.as_str();
+    while let Some(open) = rest.find('{') {
+        let close = rest[open..].find('}').map_or(rest.len(), |c| open + c);
+        rendered.push_str(&rest[..open]);
+        rendered.push_str(match &rest[open + 1..close] {
+            "id" => file_id,
+            "ext" => ext,
+            _ => name,
+        });
+        rest = &rest[(close + 1).min(rest.len())..];
+    }
+    rendered.push_str(rest);
+    let rendered = sanitize_download_name(&rendered, config.max_filename_len);
+    if rendered.is_empty() {
+        name.to_string()
+    } else {
+        rendered
+    }
+}
+
+fn truncate_filename(cleaned: String, max_len: usize) -> String {
+    if cleaned.len() <= max_len {
+        return cleaned;
+    }
+
+    let (stem, extension) = match cleaned.rfind('.') {
+        Some(dot) if dot > 0 && cleaned.len() - dot <= max_len / 2 => cleaned.split_at(dot),
+        _ => (cleaned.as_str(), ""),
+    };
+    let mut end = max_len - extension.len();
+    while !stem.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}{}", &stem[..end], extension)
+}
+
+fn content_disposition(kind: &str, filename: &str) -> String {
+    let fallback: String = filename
+        .chars()
+        .map(|c| if c.is_ascii_graphic() || c == ' ' { c } else { '_' })
+        .collect();
+    if fallback == filename {
+        return format!("{}; filename=\"{}\"", kind, filename);
+    }
+
+    let encoded: String = filename
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'!' | b'#' | b'$' | b'&' | b'+' | b'-' | b'.' | b'^' | b'_' | b'`' | b'|' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect();
+    format!("{}; filename=\"{}\"; filename*=UTF-8''{}", kind, fallback, encoded)
+}
+
+async fn delete_file(state: &AppState, file_id: &str, reason: &'static str) {
+    if let Some(metadata) = state.file_registry.remove(file_id).await {
+        release_storage(state, metadata.size);
+        release_owner_file(state, &metadata.owner);
+        publish_event(state, || FileEvent {
+            event: if reason == "expired" { "expire" } else { "delete" },
+            file_id: metadata.file_id.clone(),
+            owner: metadata.owner.clone(),
+            size: Some(metadata.size),
+            mime: None,
+            status: None,
+            reason: Some(reason),
+            timestamp: Utc::now().timestamp(),
+        });
+        if let Some(hook) = state.delete_webhook.as_ref().filter(|_| reason != "archive_rollback") {
+            hook.notify(&metadata, reason);
+        }
+        if let Some(metadata) = state.readers.defer_delete(metadata) {
+            unlink_files(&metadata).await;
+        }
+    }
+}
+
+async fn unlink_files(metadata: &FileMetadata) {
+    remove_derived_files(metadata).await;
+    match fs::remove_file(&metadata.disk_path).await {
+        Ok(_) => info!("🗑️ {} | {} | req={}", metadata.original_name, metadata.file_id, metadata.request_id),
+        Err(e) => error!("❌ delete_failed | {} | {}", metadata.file_id, e),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Reconciled {
+    Kept,
+    Missing,
+    Expired,
+}
+
+async fn reconcile_registry(state: AppState, existing: Vec<FileMetadata>) {
+    let started = Instant::now();
+    let now = Utc::now().timestamp();
+    let outcomes: Vec<Reconciled> = futures_util::stream::iter(existing)
+        .map(|metadata| {
+            let state = state.clone();
+            async move {
+                if !fs::try_exists(&metadata.disk_path).await.unwrap_or(true) {
+                    warn!("⚠️ desync | {} | {} missing on disk, dropping entry", metadata.file_id, metadata.disk_path);
+                    if let Some(stale) = state.file_registry.remove(&metadata.file_id).await {
+                        remove_derived_files(&stale).await;
+                    }
+                    return Reconciled::Missing;
+                }
+                state.stored_bytes.fetch_add(metadata.size, Ordering::Relaxed);
+                *state.owner_files.lock().unwrap().entry(metadata.owner.clone()).or_insert(0) += 1;
+                if metadata.expires_at.saturating_add(state.config.grace_period as i64) <= now {
+                    delete_file(&state, &metadata.file_id, "expired").await;
+                    return Reconciled::Expired;
+                }
+                schedule_expiry(&state, &metadata.file_id, metadata.expires_at);
+                Reconciled::Kept
+            }
+        })
+        .buffer_unordered(state.config.cleanup_concurrency)
+        .collect()
+        .await;
+    let count = |outcome| outcomes.iter().filter(|o| **o == outcome).count();
+    state.ready.store(true, Ordering::Release);
+    info!(
+        "✅ reconciled | {} files | {} missing | {} expired | {}ms",
+        count(Reconciled::Kept),
+        count(Reconciled::Missing),
+        count(Reconciled::Expired),
+        started.elapsed().as_millis()
+    );
+}
+
+async fn remove_derived_files(metadata: &FileMetadata) {
+    if let Some(thumb_path) = &metadata.thumb_path {
+        let _ = fs::remove_file(thumb_path).await;
+    }
+    for encoding in &metadata.encodings {
+        let _ = fs::remove_file(variant_path(&metadata.disk_path, encoding)).await;
+    }
+    for format in &metadata.transcodes {
+        let _ = fs::remove_file(transcode_path(&metadata.disk_path, format)).await;
+    }
+}
+
+fn try_reserve_storage(state: &AppState, size: u64, max_total: u64) -> bool {
+    state
+        .stored_bytes
+        .fetch_update(Ordering::AcqRel, Ordering::Acquire, |used| {
+            used.checked_add(size).filter(|total| *total <= max_total)
+        })
+        .is_ok()
+}
+
+fn publish_event(state: &AppState, event: impl FnOnce() -> FileEvent) {
+    if state.events.receiver_count() > 0 {
+        let _ = state.events.send(event());
+    }
+}
+
+fn try_reserve_owner_file(state: &AppState, owner: &str) -> bool {
+    let mut counts = state.owner_files.lock().unwrap();
+    let count = counts.entry(owner.to_string()).or_insert(0);
+    if state.config.max_files_per_owner.is_some_and(|limit| *count >= limit) {
+        return false;
+    }
+    *count += 1;
+    true
+}
+
+fn release_owner_file(state: &AppState, owner: &str) {
+    let mut counts = state.owner_files.lock().unwrap();
+    if let Some(count) = counts.get_mut(owner) {
+        *count = count.saturating_sub(1);
+        if *count == 0 {
+            counts.remove(owner);
+        }
+    }
+}
+
+fn release_storage(state: &AppState, size: u64) {
+    let _ = state
+        .stored_bytes
+        .fetch_update(Ordering::AcqRel, Ordering::Acquire, |used| Some(used.saturating_sub(size)));
+}
+
+async fn reserve_storage(state: &AppState, size: u64) -> bool {
+    let max_total = state.config.max_total_bytes.unwrap_or(u64::MAX);
+    if try_reserve_storage(state, size, max_total) {
+        return true;
+    }
+    let strategy = state.config.cleanup_strategy;
+    if strategy == CleanupStrategy::Ttl && !state.config.evict_on_full {
+        return false;
+    }
+
+    let mut candidates: Vec<FileMetadata> = state
+        .file_registry
+        .all()
+        .await
+        .into_iter()
+        .filter(|m| !m.worm)
+        .collect();
+    match strategy {
+        CleanupStrategy::Ttl => candidates.sort_by_key(|m| m.expires_at),
+        CleanupStrategy::Lru => candidates.sort_by_key(|m| m.last_accessed.unwrap_or(m.uploaded_at)),
+        CleanupStrategy::Size => candidates.sort_by_key(|m| m.uploaded_at),
+    }
+    for metadata in candidates {
+        warn!(
+            "♻️ evicted | {} | storage_cap {:?} ({} bytes used, {} needed) | uploaded_at={} | last_accessed={:?}",
+            metadata.file_id,
+            strategy,
+            state.stored_bytes.load(Ordering::Relaxed),
+            size,
+            metadata.uploaded_at,
+            metadata.last_accessed
+        );
+        delete_file(state, &metadata.file_id, "storage_cap").await;
+        if try_reserve_storage(state, size, max_total) {
+            return true;
+        }
+    }
+    false
+}
+
+async fn evict_for_capacity(state: &AppState, max_entries: usize) {
+    let len = state.file_registry.len().await;
+    if len < max_entries {
+        return;
+    }
+    for file_id in state.file_registry.oldest(len + 1 - max_entries).await {
+        warn!("♻️ evicted | {} | registry_full ({} entries)", file_id, len);
+        delete_file(state, &file_id, "registry_full").await;
+    }
+}
+
+fn schedule_expiry(state: &AppState, file_id: &str, expires_at: i64) {
+    if state.expiry_tx.send((file_id.to_string(), expires_at)).is_err() {
+        warn!("⚠️ expiry_timer_closed | {}", file_id);
+    }
+}
+
+async fn run_expiry_timer(state: AppState, mut rx: mpsc::UnboundedReceiver<(String, i64)>) {
+    let mut queue: DelayQueue<String> = DelayQueue::new();
+    let mut keys: HashMap<String, delay_queue::Key> = HashMap::new();
+
+    loop {
+        tokio::select! {
+            command = rx.recv() => {
+                let Some((file_id, expires_at)) = command else { break };
+                let remove_at = expires_at.saturating_add(state.config.grace_period as i64);
+                let delay = Duration::from_secs((remove_at - Utc::now().timestamp()).max(0) as u64);
+                match keys.get(&file_id) {
+                    Some(key) => queue.reset(key, delay),
+                    None => {
+                        let key = queue.insert(file_id.clone(), delay);
+                        keys.insert(file_id, key);
+                    }
+                }
+            }
+            Some(expired) = queue.next(), if !queue.is_empty() => {
+                let file_id = expired.into_inner();
+                keys.remove(&file_id);
+                let expires_at = state.file_registry.get(&file_id).await.map(|m| m.expires_at);
+                let grace = state.config.grace_period as i64;
+                match expires_at {
+                    Some(expires_at) if expires_at.saturating_add(grace) > Utc::now().timestamp() => {
+                        schedule_expiry(&state, &file_id, expires_at);
+                    }
+                    Some(_) => delete_file(&state, &file_id, "expired").await,
+                    None => {}
+                }
+            }
+        }
+    }
+}
+
+async fn run_audit_log(file: File, mut rx: mpsc::UnboundedReceiver<AuditRecord>) {
+    let mut writer = BufWriter::new(file);
+    while let Some(record) = rx.recv().await {
+        let mut next = Some(record);
+        while let Some(record) = next {
+            let mut line = serde_json::to_vec(&record).unwrap_or_default();
+            line.push(b'\n');
+            if let Err(e) = writer.write_all(&line).await {
+                error!("❌ audit_write_failed | {}", e);
+            }
+            next = rx.try_recv().ok();
+        }
+        if let Err(e) = writer.flush().await {
+            error!("❌ audit_write_failed | {}", e);
+        }
+    }
+}
+
+async fn evict_idle_buckets(state: AppState) {
+    let mut interval = tokio::time::interval(Duration::from_secs(60));
+    loop {
+        interval.tick().await;
+        for (scope, limiter) in [("ip", &state.ip_limiter), ("owner", &state.owner_limiter)] {
+            if let Some(limiter) = limiter {
+                let evicted = limiter.evict_idle();
+                if evicted > 0 {
+                    info!("🧹 rate_buckets_evicted | {} | {}", scope, evicted);
+                }
+            }
+        }
+        if let Some(tracker) = &state.signature_failures {
+            let evicted = tracker.evict_idle();
+            if evicted > 0 {
+                info!("🧹 signature_failures_evicted | {}", evicted);
+            }
+        }
+        if let Some(tracker) = &state.password_failures {
+            let evicted = tracker.evict_idle();
+            if evicted > 0 {
+                info!("🧹 password_failures_evicted | {}", evicted);
+            }
+        }
+    }
+}
+
+fn mark_storage_writable(state: &AppState, pool: &str, writable: bool, reason: &str) {
+    let mut unwritable = state.unwritable_pools.lock().unwrap();
+    if writable {
+        if unwritable.remove(pool) {
+            info!("✅ storage_writable | {}", pool);
+        }
+    } else if unwritable.insert(pool.to_string()) {
+        error!("❌ storage_unwritable | {} | {}", pool, reason);
+    }
+}
+
+async fn probe_storage(state: &AppState) {
+    for pool in &state.config.storage_pools {
+        let probe = PathBuf::from(pool).join(".sptzx-probe");
+        match fs::write(&probe, b"ok").await {
+            Ok(_) => {
+                let _ = fs::remove_file(&probe).await;
+                mark_storage_writable(state, pool, true, "");
+            }
+            Err(e) => mark_storage_writable(state, pool, false, &e.to_string()),
+        }
+    }
+}
+
+fn select_pool(state: &AppState) -> Option<String> {
+    let pools = &state.config.storage_pools;
+    let unwritable = state.unwritable_pools.lock().unwrap().clone();
+    let eligible = |pool: &String| {
+        !unwritable.contains(pool)
+            && free_disk_space(pool).is_some_and(|free| free > state.config.pool_reserve)
+    };
+
+    match state.config.pool_strategy {
+        PoolStrategy::MostFree => pools
+            .iter()
+            .filter(|pool| !unwritable.contains(*pool))
+            .filter_map(|pool| free_disk_space(pool).map(|free| (pool, free)))
+            .filter(|(_, free)| *free > state.config.pool_reserve)
+            .max_by_key(|(_, free)| *free)
+            .map(|(pool, _)| pool.clone()),
+        PoolStrategy::RoundRobin => {
+            let start = state.pool_cursor.fetch_add(1, Ordering::Relaxed);
+            (0..pools.len())
+                .map(|offset| &pools[(start + offset) % pools.len()])
+                .find(|pool| eligible(pool))
+                .cloned()
+        }
+    }
+}
+
+async fn enforce_free_space_floor(state: &AppState) {
+    let floor = state.config.min_free_bytes;
+    if floor == 0 {
+        return;
+    }
+
+    for pool in &state.config.storage_pools {
+        let Some(free) = free_disk_space(pool) else {
+            continue;
+        };
+        if free >= floor {
+            continue;
+        }
+
+        let root = PathBuf::from(pool);
+        let mut candidates: Vec<FileMetadata> = state
+            .file_registry
+            .all()
+            .await
+            .into_iter()
+            .filter(|m| !m.worm && PathBuf::from(&m.disk_path).starts_with(&root))
+            .collect();
+        candidates.sort_by_key(|m| m.uploaded_at);
+
+        let deficit = floor - free;
+        let mut reclaimed = 0u64;
+        for metadata in candidates {
+            if reclaimed >= deficit {
+                break;
+            }
+            warn!(
+                "🚨 early_eviction | {} | {} | {} bytes | uploaded_at={} | free={} < floor={}",
+                pool, metadata.file_id, metadata.size, metadata.uploaded_at, free, floor
+            );
+            reclaimed += metadata.size;
+            delete_file(state, &metadata.file_id, "free_space").await;
+        }
+
+        if reclaimed < deficit {
+            error!("❌ free_space_floor_unmet | {} | reclaimed {} of {} bytes", pool, reclaimed, deficit);
+        }
+    }
+}
+
+async fn cleanup_expired_files(state: AppState) {
+    let mut interval = tokio::time::interval(Duration::from_secs(state.config.cleanup_interval));
+    loop {
+        interval.tick().await;
+        probe_storage(&state).await;
+        prune_pipes(&state);
+        run_cleanup_pass(&state).await;
+    }
+}
+
+async fn run_cleanup_pass(state: &AppState) -> usize {
+    let started = Instant::now();
+    let now = Utc::now().timestamp();
+    let to_delete = state.file_registry.expired(now.saturating_sub(state.config.grace_period as i64)).await;
+    let expired = futures_util::stream::iter(to_delete)
+        .map(|file_id| async move { delete_file(state, &file_id, "expired").await })
+        .buffer_unordered(state.config.cleanup_concurrency)
+        .count()
+        .await;
+    if expired > 0 {
+        info!("🧹 cleanup | {} expired | {}ms | concurrency {}", expired, started.elapsed().as_millis(), state.config.cleanup_concurrency);
+    }
+    if state.config.max_total_bytes.is_some() {
+        reserve_storage(state, 0).await;
+    }
+    enforce_free_space_floor(state).await;
+    prune_quarantine(state).await;
+    state.link_uses.lock().unwrap().retain(|_, uses| uses.expires_at > now);
+    expired
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    const BOUNDARY: &str = "sptzx-test-boundary";
+
+    struct TestServer {
+        addr: SocketAddr,
+        state: AppState,
+        dir: PathBuf,
+        _expiry_rx: mpsc::UnboundedReceiver<(String, i64)>,
+    }
+
+    impl TestServer {
+        async fn start(configure: impl FnOnce(&mut Config)) -> Self {
+            let dir = env::temp_dir().join(format!("sptzx-test-{}", Uuid::new_v4()));
+            std::fs::create_dir_all(&dir).unwrap();
+            let mut config = Config::from_env();
+            config.upload_dir = dir.to_string_lossy().into_owned();
+            config.storage_pools = vec![config.upload_dir.clone()];
+            configure(&mut config);
+            let (expiry_tx, expiry_rx) = mpsc::unbounded_channel();
+            let state = AppState::new(Arc::new(config), expiry_tx, None, None).unwrap();
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let app = build_router(&state);
+            tokio::spawn(async move { axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await });
+            Self { addr, state, dir, _expiry_rx: expiry_rx }
+        }
+
+        fn url(&self, path: &str) -> String {
+            format!("http://{}{}", self.addr, path)
+        }
+
+        async fn upload(&self, headers: &[(&str, &str)], parts: &[(&str, Option<&str>, &[u8])]) -> reqwest::Response {
+            let mut request = reqwest::Client::new()
+                .post(self.url("/upload"))
+                .header(header::CONTENT_TYPE, format!("multipart/form-data; boundary={}", BOUNDARY))
+                .body(multipart(parts));
+            for (name, value) in headers {
+                request = request.header(*name, *value);
+            }
+            request.send().await.unwrap()
+        }
+
+        fn stored_files(&self) -> Vec<String> {
+            std::fs::read_dir(&self.dir)
+                .unwrap()
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                .collect()
+        }
+
+        async fn raw(&self, head: &str, body: &[u8]) -> String {
+            let mut stream = tokio::net::TcpStream::connect(self.addr).await.unwrap();
+            let _ = stream.write_all(head.as_bytes()).await;
+            let _ = stream.write_all(body).await;
+            let mut response = Vec::new();
+            let _ = stream.read_to_end(&mut response).await;
+            String::from_utf8_lossy(&response).into_owned()
+        }
+    }
+
+    impl Drop for TestServer {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    fn multipart(parts: &[(&str, Option<&str>, &[u8])]) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (name, filename, data) in parts {
+            body.extend_from_slice(format!("--{}\r\nContent-Disposition: form-data; name=\"{}\"", BOUNDARY, name).as_bytes());
+            if let Some(filename) = filename {
+                body.extend_from_slice(format!("; filename=\"{}\"\r\nContent-Type: text/plain", filename).as_bytes());
+            }
+            body.extend_from_slice(b"\r\n\r\n");
+            body.extend_from_slice(data);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(format!("--{}--\r\n", BOUNDARY).as_bytes());
+        body
+    }
+
+    fn chunked(body: &[u8], chunk_size: usize) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        for chunk in body.chunks(chunk_size) {
+            encoded.extend_from_slice(format!("{:x}\r\n", chunk.len()).as_bytes());
+            encoded.extend_from_slice(chunk);
+            encoded.extend_from_slice(b"\r\n");
+        }
+        encoded.extend_from_slice(b"0\r\n\r\n");
+        encoded
+    }
+
+    fn chunked_upload_head(server: &TestServer) -> String {
+        format!(
+            "POST /upload HTTP/1.1\r\nHost: {}\r\nContent-Type: multipart/form-data; boundary={}\r\n\
+             Transfer-Encoding: chunked\r\nConnection: close\r\n\r\n",
+            server.addr, BOUNDARY
+        )
+    }
+
+    #[tokio::test]
+    async fn chunked_upload_within_limit_is_stored() {
+        let server = TestServer::start(|config| config.max_file_size = 16384).await;
+        let body = chunked(&multipart(&[("file", Some("small.txt"), &[b'a'; 8192])]), 512);
+        let response = server.raw(&chunked_upload_head(&server), &body).await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        let files = server.state.file_registry.all().await;
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].size, 8192);
+    }
+
+    #[tokio::test]
+    async fn chunked_upload_over_limit_is_rejected_and_removed() {
+        let server = TestServer::start(|config| config.max_file_size = 1024).await;
+        let body = chunked(&multipart(&[("file", Some("big.txt"), &[b'a'; 8192])]), 512);
+        let response = server.raw(&chunked_upload_head(&server), &body).await;
+        assert!(response.starts_with("HTTP/1.1 413"), "{}", response);
+        assert!(server.stored_files().is_empty(), "{:?}", server.stored_files());
+        assert!(server.state.file_registry.all().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn second_file_part_is_rejected() {
+        let server = TestServer::start(|_| {}).await;
+        let response = server.upload(&[], &[("file", Some("a.txt"), b"first"), ("file", Some("b.txt"), b"second")]).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["code"], "multiple_files");
+        assert!(server.stored_files().is_empty(), "{:?}", server.stored_files());
+        assert!(server.state.file_registry.all().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn idempotent_retry_replays_the_same_file() {
+        let server = TestServer::start(|config| config.trusted_proxies = vec!["127.0.0.1/32".to_string()]).await;
+        let parts: &[(&str, Option<&str>, &[u8])] = &[("file", Some("a.txt"), b"hello")];
+        let client_a = [("idempotency-key", "retry-1"), ("x-forwarded-for", "203.0.113.1")];
+        let first = server.upload(&client_a, parts).await;
+        assert_eq!(first.status(), StatusCode::OK);
+        assert!(first.headers().get("idempotent-replayed").is_none());
+        let first: serde_json::Value = first.json().await.unwrap();
+
+        let replay = server.upload(&client_a, parts).await;
+        assert_eq!(replay.headers().get("idempotent-replayed").map(|v| v.as_bytes()), Some(&b"true"[..]));
+        let replay: serde_json::Value = replay.json().await.unwrap();
+        assert_eq!(replay["id"], first["id"]);
+
+        let client_b = [("idempotency-key", "retry-1"), ("x-forwarded-for", "203.0.113.2")];
+        let other: serde_json::Value = server.upload(&client_b, parts).await.json().await.unwrap();
+        assert_ne!(other["id"], first["id"]);
+        assert_eq!(server.state.file_registry.all().await.len(), 2);
+    }
+
+    #[test]
+    fn idempotency_key_is_reserved_until_completed_or_dropped() {
+        let cache = Arc::new(IdempotencyCache::new(60, 10));
+        let reservation = cache.reserve("alice", "key").unwrap().unwrap();
+        let busy = cache.reserve("alice", "key").err().unwrap();
+        assert_eq!(busy.body.code.as_str(), "idempotency_in_flight");
+        assert!(cache.reserve("bob", "key").unwrap().is_some());
+        drop(reservation);
+        assert!(cache.get("alice", "key").is_none());
+        let reservation = cache.reserve("alice", "key").unwrap().unwrap();
+        reservation.complete(UploadResponse {
+            id: "f1".to_string(),
+            name: "a.txt".to_string(),
+            size: 5,
+            mime: "text/plain".to_string(),
+            width: None,
+            height: None,
+            view: String::new(),
+            download: String::new(),
+            share: None,
+            ttl: 60,
+            uploaded_at: 0,
+            expires_at: 60,
+            checksum: String::new(),
+            owner: "alice".to_string(),
+            delete_token: None,
+        });
+        assert_eq!(cache.get("alice", "key").map(|r| r.id), Some("f1".to_string()));
+    }
+
+    fn signed_link(config: &Config, owner: &str, file_type: &str) -> HashMap<String, String> {
+        let link = LinkOptions::new("inline", Utc::now().timestamp() + 300);
+        serde_urlencoded::from_str(&signed_query(owner, "file-1", file_type, &link, config)).unwrap()
+    }
+
+    fn link_verifies(query: &HashMap<String, String>, config: &Config) -> Result<bool, ApiError> {
+        verify_signature(&parse_signed_params(query)?, config)
+    }
+
+    #[test]
+    fn signed_links_round_trip_reserved_characters() {
+        let mut config = Config::from_env();
+        config.signing_version = "v1".to_string();
+        let query = signed_link(&config, "team a+b", "application/vnd.ms-excel");
+        assert_eq!(query["sz-owner"], "team a+b");
+        assert_eq!(query["sz-type"], "application/vnd.ms-excel");
+        assert!(link_verifies(&query, &config).unwrap());
+
+        let mut tampered = query.clone();
+        tampered.insert("sz-owner".to_string(), "team a b".to_string());
+        assert!(!link_verifies(&tampered, &config).unwrap());
+        let mut tampered = query;
+        tampered.insert("sz-type".to_string(), "text/html".to_string());
+        assert!(!link_verifies(&tampered, &config).unwrap());
+    }
+
+    #[test]
+    fn each_signature_version_verifies_its_own_algorithm() {
+        let mut config = Config::from_env();
+        for (version, signature_len) in [("v1", 64), ("v2", 128)] {
+            config.signing_version = version.to_string();
+            let query = signed_link(&config, "alice", "image/png");
+            assert_eq!(query["sz-version"], version);
+            assert_eq!(query["sz-signature"].len(), signature_len);
+            assert!(link_verifies(&query, &config).unwrap(), "{}", version);
+
+            let mut downgraded = query.clone();
+            downgraded.insert("sz-version".to_string(), if version == "v1" { "v2" } else { "v1" }.to_string());
+            assert!(!link_verifies(&downgraded, &config).unwrap(), "{}", version);
+            let mut tampered = query;
+            tampered.insert("sz-expires".to_string(), (Utc::now().timestamp() + 86400).to_string());
+            assert!(!link_verifies(&tampered, &config).unwrap(), "{}", version);
+        }
+
+        let mut unknown = signed_link(&config, "alice", "image/png");
+        unknown.insert("sz-version".to_string(), "v9".to_string());
+        let error = link_verifies(&unknown, &config).err().unwrap();
+        assert_eq!(error.body.code.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(error.body.code.as_str(), "unsupported_version");
+    }
+
+    #[tokio::test]
+    async fn flipping_the_signed_mode_invalidates_the_link() {
+        let mut config = Config::from_env();
+        for version in ["v1", "v2", "v3"] {
+            config.signing_version = version.to_string();
+            let inline = signed_link(&config, "alice", "image/png");
+            assert_eq!(inline["sz-mode"], "inline");
+            assert!(link_verifies(&inline, &config).unwrap(), "{}", version);
+            let mut flipped = inline;
+            flipped.insert("sz-mode".to_string(), "attachment".to_string());
+            assert!(!link_verifies(&flipped, &config).unwrap(), "{}", version);
+        }
+
+        let server = TestServer::start(|_| {}).await;
+        let upload: serde_json::Value = server.upload(&[], &[("file", Some("a.txt"), b"hello")]).await.json().await.unwrap();
+        let view = upload["view"].as_str().unwrap();
+        let download = upload["download"].as_str().unwrap();
+        assert!(view.contains("sz-mode=inline") && download.contains("sz-mode=attachment"));
+        for (url, status, code) in [
+            (view.replace("sz-mode=inline", "sz-mode=attachment"), StatusCode::FORBIDDEN, "invalid_signature"),
+            (download.replace("sz-mode=attachment", "sz-mode=inline"), StatusCode::FORBIDDEN, "invalid_signature"),
+            (view.replace("sz-mode=inline", "sz-mode=download"), StatusCode::BAD_REQUEST, "invalid_mode"),
+        ] {
+            let response = reqwest::get(&url).await.unwrap();
+            assert_eq!(response.status(), status, "{}", url);
+            assert_eq!(response.json::<serde_json::Value>().await.unwrap()["code"], code, "{}", url);
+        }
+        assert_eq!(reqwest::get(view).await.unwrap().status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn v3_links_survive_reordering_and_reject_extra_params() {
+        let server = TestServer::start(|config| config.signing_version = "v3".to_string()).await;
+        let upload: serde_json::Value = server.upload(&[], &[("file", Some("a.txt"), b"hello")]).await.json().await.unwrap();
+        let view = upload["view"].as_str().unwrap();
+        let (path, query) = view.split_once('?').unwrap();
+        let reversed: Vec<&str> = query.split('&').rev().collect();
+
+        let response = reqwest::get(format!("{}?{}", path, reversed.join("&"))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.text().await.unwrap(), "hello");
+
+        let response = reqwest::get(format!("{}&sz-extra=1", view)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["code"], "unexpected_parameter");
+
+        let response = reqwest::get(view.replace("sz-mode=inline", "sz-mode=attachment")).await.unwrap();
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["code"], "invalid_signature");
+    }
+
+    #[tokio::test]
+    async fn wrong_passwords_lock_out_until_the_cooldown_ends() {
+        let server = TestServer::start(|config| {
+            config.password_fail_limit = 3;
+            config.password_fail_cooldown = 1;
+        })
+        .await;
+        let parts: &[(&str, Option<&str>, &[u8])] = &[("password", None, b"open sesame"), ("file", Some("a.txt"), b"secret")];
+        let upload: serde_json::Value = server.upload(&[], parts).await.json().await.unwrap();
+        let view = upload["view"].as_str().unwrap();
+        let client = reqwest::Client::new();
+        let fetch = |password: &'static str| client.get(view).header("x-file-password", password).send();
+
+        for _ in 0..3 {
+            let body: serde_json::Value = fetch("guess").await.unwrap().json().await.unwrap();
+            assert_eq!(body["code"], "invalid_password");
+        }
+        let blocked = fetch("open sesame").await.unwrap();
+        assert_eq!(blocked.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(blocked.headers().contains_key(header::RETRY_AFTER));
+
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        let unlocked = fetch("open sesame").await.unwrap();
+        assert_eq!(unlocked.status(), StatusCode::OK);
+        assert_eq!(unlocked.text().await.unwrap(), "secret");
+    }
+
+    async fn webhook_receiver() -> (String, mpsc::UnboundedReceiver<(HeaderMap, String)>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let app = Router::new().route(
+            "/hook",
+            post(move |headers: HeaderMap, body: String| {
+                let tx = tx.clone();
+                async move {
+                    let _ = tx.send((headers, body));
+                    StatusCode::NO_CONTENT
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (url, rx)
+    }
+
+    #[tokio::test]
+    async fn delete_fires_a_signed_webhook_but_rollbacks_do_not() {
+        let (hook_url, mut notices) = webhook_receiver().await;
+        let server = TestServer::start(move |config| {
+            config.delete_tokens = true;
+            config.delete_webhook_url = Some(hook_url);
+            config.delete_webhook_secret = Some("webhook-secret".to_string());
+        })
+        .await;
+        let upload: serde_json::Value = server.upload(&[], &[("file", Some("a.txt"), b"hello")]).await.json().await.unwrap();
+        let file_id = upload["id"].as_str().unwrap();
+        let response = reqwest::Client::new()
+            .delete(server.url(&format!("/file/{}", file_id)))
+            .header("x-delete-token", upload["delete_token"].as_str().unwrap())
+            .send()
+            .await
+            .unwrap();
+        assert!(response.status().is_success(), "{}", response.status());
+
+        let (headers, body) = tokio::time::timeout(Duration::from_secs(5), notices.recv()).await.unwrap().unwrap();
+        let notice: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(notice["file_id"], file_id);
+        assert_eq!(notice["reason"], "manual");
+        assert_eq!(notice["cause"], "deleted");
+        let timestamp = headers["x-sptzx-timestamp"].to_str().unwrap();
+        let expected = format!("sha256={}", compute_hmac(&format!("{}.{}", timestamp, body), "webhook-secret"));
+        assert_eq!(headers["x-sptzx-signature"].to_str().unwrap(), expected);
+
+        let upload: serde_json::Value = server.upload(&[], &[("file", Some("b.txt"), b"world")]).await.json().await.unwrap();
+        delete_file(&server.state, upload["id"].as_str().unwrap(), "archive_rollback").await;
+        assert!(tokio::time::timeout(Duration::from_millis(300), notices.recv()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn range_requests_cover_open_mid_suffix_and_invalid_ranges() {
+        let server = TestServer::start(|_| {}).await;
+        let upload: serde_json::Value = server.upload(&[], &[("file", Some("digits.txt"), b"0123456789")]).await.json().await.unwrap();
+        let download = upload["download"].as_str().unwrap();
+        let client = reqwest::Client::new();
+
+        let full = client.get(download).send().await.unwrap();
+        assert_eq!(full.headers()[header::ACCEPT_RANGES], "bytes");
+        assert!(full.headers().contains_key(header::ETAG));
+
+        for (range, content_range, body) in [
+            ("bytes=0-", "bytes 0-9/10", "0123456789"),
+            ("bytes=2-5", "bytes 2-5/10", "2345"),
+            ("bytes=7-100", "bytes 7-9/10", "789"),
+            ("bytes=-3", "bytes 7-9/10", "789"),
+        ] {
+            let response = client.get(download).header(header::RANGE, range).send().await.unwrap();
+            assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT, "{}", range);
+            assert_eq!(response.headers()[header::CONTENT_RANGE], content_range, "{}", range);
+            assert_eq!(response.text().await.unwrap(), body, "{}", range);
+        }
+
+        for range in ["bytes=10-", "bytes=20-30", "bytes=-0"] {
+            let response = client.get(download).header(header::RANGE, range).send().await.unwrap();
+            assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE, "{}", range);
+            assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes */10", "{}", range);
+        }
+
+        let multi = client.get(download).header(header::RANGE, "bytes=0-1,4-5").send().await.unwrap();
+        assert_eq!(multi.status(), StatusCode::OK);
+        assert_eq!(multi.text().await.unwrap(), "0123456789");
+        let stale = client
+            .get(download)
+            .header(header::RANGE, "bytes=2-5")
+            .header(header::IF_RANGE, "\"stale\"")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(stale.status(), StatusCode::OK);
+        assert_eq!(stale.text().await.unwrap(), "0123456789");
+    }
+
+    fn forwarding_headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(*name, HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn client_ip_walks_forwarded_hops_through_trusted_proxies() {
+        let mut config = Config::from_env();
+        config.trusted_proxies = vec!["10.0.0.0/8".to_string()];
+        let proxy: IpAddr = "10.0.0.1".parse().unwrap();
+        let ip = |value: &str| value.parse::<IpAddr>().unwrap();
+
+        let spoofed = forwarding_headers(&[("x-forwarded-for", "198.51.100.7")]);
+        assert_eq!(client_ip(&config, ip("203.0.113.9"), &spoofed), ip("203.0.113.9"));
+
+        for (headers, expected) in [
+            (vec![("x-forwarded-for", "198.51.100.7, 10.0.0.2")], "198.51.100.7"),
+            (vec![("x-forwarded-for", "1.1.1.1, 198.51.100.7")], "198.51.100.7"),
+            (vec![("x-forwarded-for", "1.1.1.1"), ("x-forwarded-for", "198.51.100.7:8080")], "198.51.100.7"),
+            (vec![("forwarded", "for=198.51.100.7;proto=https, for=\"[2001:db8::1]:4711\"")], "2001:db8::1"),
+            (vec![("forwarded", "for=198.51.100.7"), ("x-forwarded-for", "192.0.2.1")], "198.51.100.7"),
+            (vec![("forwarded", "for=unknown")], "10.0.0.1"),
+            (vec![("x-forwarded-for", "198.51.100.7, garbage, 10.0.0.2")], "10.0.0.2"),
+            (vec![], "10.0.0.1"),
+        ] {
+            let headers = forwarding_headers(&headers);
+            assert_eq!(client_ip(&config, proxy, &headers), ip(expected), "{:?}", headers);
+        }
+    }
+
+    #[tokio::test]
+    async fn link_scheme_follows_forwarded_proto_only_from_trusted_proxies() {
+        let parts: &[(&str, Option<&str>, &[u8])] = &[("file", Some("a.txt"), b"hello")];
+        let view = |upload: serde_json::Value| upload["view"].as_str().unwrap().to_string();
+
+        let proxied = TestServer::start(|config| config.trusted_proxies = vec!["127.0.0.1/32".to_string()]).await;
+        let upload = proxied.upload(&[("forwarded", "for=198.51.100.7;proto=https")], parts).await;
+        assert!(view(upload.json().await.unwrap()).starts_with("https://"));
+        let upload = proxied.upload(&[("x-forwarded-proto", "https")], parts).await;
+        assert!(view(upload.json().await.unwrap()).starts_with("https://"));
+        let upload = proxied.upload(&[], parts).await;
+        assert!(view(upload.json().await.unwrap()).starts_with("http://"));
+
+        let direct = TestServer::start(|_| {}).await;
+        let upload = direct.upload(&[("forwarded", "proto=https"), ("x-forwarded-proto", "https")], parts).await;
+        assert!(view(upload.json().await.unwrap()).starts_with("http://"));
+    }
+
+    #[tokio::test]
+    async fn stalled_download_does_not_block_uploads_or_deletes() {
+        let server = TestServer::start(|config| config.delete_tokens = true).await;
+        let big = vec![b'x'; 32 * 1024 * 1024];
+        let upload: serde_json::Value = server.upload(&[], &[("file", Some("big.bin"), &big)]).await.json().await.unwrap();
+        let download = upload["download"].as_str().unwrap();
+        let path = &download[download.find("/file/").unwrap()..];
+
+        let mut stalled = tokio::net::TcpStream::connect(server.addr).await.unwrap();
+        let request = format!("GET {} HTTP/1.1\r\nHost: {}\r\n\r\n", path, server.addr);
+        stalled.write_all(request.as_bytes()).await.unwrap();
+        let mut head = [0u8; 1024];
+        let read = stalled.read(&mut head).await.unwrap();
+        assert!(head[..read].starts_with(b"HTTP/1.1 200"));
+
+        let work = async {
+            for n in 0..5 {
+                let name = format!("small-{}.txt", n);
+                let upload: serde_json::Value =
+                    server.upload(&[], &[("file", Some(name.as_str()), b"hello")]).await.json().await.unwrap();
+                let response = reqwest::Client::new()
+                    .delete(server.url(&format!("/file/{}", upload["id"].as_str().unwrap())))
+                    .header("x-delete-token", upload["delete_token"].as_str().unwrap())
+                    .send()
+                    .await
+                    .unwrap();
+                assert!(response.status().is_success());
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), work).await.expect("uploads stalled behind a slow download");
+        let big_id = upload["id"].as_str().unwrap();
+        let in_flight = server.state.readers.entries.lock().unwrap().get(big_id).is_some_and(|entry| entry.readers > 0);
+        assert!(in_flight, "the slow download finished before the uploads ran");
+        assert_eq!(server.state.file_registry.all().await.len(), 1);
+        drop(stalled);
+    }
+
+    #[tokio::test]
+    async fn delete_during_a_slow_read_lets_the_reader_finish_intact() {
+        let server = TestServer::start(|config| config.delete_tokens = true).await;
+        let content: Vec<u8> = (0..16 * 1024 * 1024u32).map(|n| (n % 251) as u8).collect();
+        let upload: serde_json::Value = server.upload(&[], &[("file", Some("slow.bin"), &content)]).await.json().await.unwrap();
+        let id = upload["id"].as_str().unwrap().to_string();
+        let download = upload["download"].as_str().unwrap();
+        let disk_path = server.state.file_registry.get(&id).await.unwrap().disk_path;
+
+        let mut reader = tokio::net::TcpStream::connect(server.addr).await.unwrap();
+        let path = &download[download.find("/file/").unwrap()..];
+        let request = format!("GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", path, server.addr);
+        reader.write_all(request.as_bytes()).await.unwrap();
+        let mut received = vec![0u8; 64 * 1024];
+        let read = reader.read(&mut received).await.unwrap();
+        received.truncate(read);
+        assert!(received.starts_with(b"HTTP/1.1 200"));
+
+        let response = reqwest::Client::new()
+            .delete(server.url(&format!("/file/{}", id)))
+            .header("x-delete-token", upload["delete_token"].as_str().unwrap())
+            .send()
+            .await
+            .unwrap();
+        assert!(response.status().is_success());
+        assert!(server.state.file_registry.get(&id).await.is_none());
+        assert_eq!(reqwest::get(download).await.unwrap().status(), StatusCode::NOT_FOUND);
+        assert!(std::path::Path::new(&disk_path).exists(), "the unlink should wait for the reader");
+
+        let mut chunk = vec![0u8; 256 * 1024];
+        loop {
+            let read = reader.read(&mut chunk).await.unwrap();
+            if read == 0 {
+                break;
+            }
+            received.extend_from_slice(&chunk[..read]);
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        let body_start = received.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+        assert!(received[body_start..] == content[..], "the slow read came back truncated or corrupted");
+
+        for _ in 0..100 {
+            if !std::path::Path::new(&disk_path).exists() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(!std::path::Path::new(&disk_path).exists(), "the deferred unlink never ran");
+    }
+
+    fn zip_of(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        use std::io::Write;
+        let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        for (name, data) in entries {
+            writer.start_file(*name, zip::write::SimpleFileOptions::default()).unwrap();
+            writer.write_all(data).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[tokio::test]
+    async fn archive_upload_modes_handle_one_failing_entry() {
+        let archive = zip_of(&[("good.txt", b"hello world"), ("tiny.txt", b"x"), ("also-good.txt", b"hello again")]);
+
+        let strict = TestServer::start(|config| config.min_file_size = 5).await;
+        let response = reqwest::Client::new().post(strict.url("/upload/archive")).body(archive.clone()).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["code"], "file_too_small");
+        assert!(strict.state.file_registry.all().await.is_empty());
+        assert!(strict.stored_files().is_empty(), "{:?}", strict.stored_files());
+
+        let lenient = TestServer::start(|config| {
+            config.min_file_size = 5;
+            config.multi_upload_mode = MultiUploadMode::BestEffort;
+        })
+        .await;
+        let response = reqwest::Client::new().post(lenient.url("/upload/archive")).body(archive).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::MULTI_STATUS);
+        let body: serde_json::Value = response.json().await.unwrap();
+        let files = body["files"].as_array().unwrap();
+        let summary: Vec<(&str, u64)> =
+            files.iter().map(|f| (f["name"].as_str().unwrap(), f["status"].as_u64().unwrap())).collect();
+        assert_eq!(summary, [("good.txt", 200), ("tiny.txt", 400), ("also-good.txt", 200)]);
+        assert_eq!(files[1]["error"]["code"], "file_too_small");
+        assert!(files[1].get("file").is_none());
+        assert_eq!(lenient.state.file_registry.all().await.len(), 2);
+        assert_eq!(lenient.stored_files().len(), 2, "{:?}", lenient.stored_files());
+    }
+
+    #[tokio::test]
+    async fn content_id_hits_only_reuse_matching_live_entries() {
+        let server = TestServer::start(|config| config.id_from_content = true).await;
+        let plain: &[(&str, Option<&str>, &[u8])] = &[("file", Some("a.txt"), b"same bytes")];
+        let protected: &[(&str, Option<&str>, &[u8])] = &[("password", None, b"pw"), ("file", Some("a.txt"), b"same bytes")];
+        let id = |upload: serde_json::Value| upload["id"].as_str().unwrap().to_string();
+
+        let first = id(server.upload(&[], plain).await.json().await.unwrap());
+        assert_eq!(id(server.upload(&[], plain).await.json().await.unwrap()), first);
+
+        let locked = id(server.upload(&[], protected).await.json().await.unwrap());
+        assert_ne!(locked, first);
+        assert!(server.state.file_registry.get(&locked).await.unwrap().password_hash.is_some());
+        assert!(server.state.file_registry.get(&first).await.unwrap().password_hash.is_none());
+
+        let past = Utc::now().timestamp() - 1;
+        server.state.file_registry.update(&first, move |entry| entry.expires_at = past).await;
+        let revived = id(server.upload(&[], plain).await.json().await.unwrap());
+        assert_ne!(revived, first);
+        assert_eq!(server.state.file_registry.get(&first).await.unwrap().expires_at, past);
+    }
+
+    #[tokio::test]
+    async fn verify_pass_separates_unreadable_from_missing_files() {
+        let server = TestServer::start(|_| {}).await;
+        let mut paths = Vec::new();
+        for name in ["intact.txt", "unreadable.txt", "missing.txt"] {
+            let upload: serde_json::Value = server.upload(&[], &[("file", Some(name), b"hello")]).await.json().await.unwrap();
+            paths.push(server.state.file_registry.get(upload["id"].as_str().unwrap()).await.unwrap().disk_path);
+        }
+        std::fs::remove_file(&paths[1]).unwrap();
+        std::fs::create_dir(&paths[1]).unwrap();
+        std::fs::remove_file(&paths[2]).unwrap();
+
+        let summary = run_verify_pass(&server.state, None).await;
+        assert_eq!((summary.checked, summary.mismatched, summary.missing, summary.unreadable), (3, 0, 1, 1));
+    }
+
+    #[tokio::test]
+    async fn group_links_cap_signed_query_size() {
+        let server = TestServer::start(|_| {}).await;
+        let response = reqwest::get(server.url(&format!("/group/g1?sz-id={}", "a".repeat(MAX_SIGNED_PARAM_LEN + 1)))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["code"], "parameter_too_long");
+    }
+
+    #[tokio::test]
+    async fn archive_entries_carry_their_own_checksums() {
+        let entries: [(&str, &[u8]); 3] = [("one.txt", b"first file"), ("two.txt", b"second file"), ("three.txt", b"")];
+        let server = TestServer::start(|_| {}).await;
+        let response =
+            reqwest::Client::new().post(server.url("/upload/archive?group=true")).body(zip_of(&entries)).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = response.json().await.unwrap();
+        let files = body["files"].as_array().unwrap();
+        assert_eq!(files.len(), entries.len());
+        let mut expected = HashMap::new();
+        for (file, (name, data)) in files.iter().zip(entries) {
+            let digest = hex::encode(Sha256::digest(data));
+            assert_eq!(file["name"], name);
+            assert_eq!(file["checksum"], digest.as_str());
+            let stored = server.state.file_registry.get(file["id"].as_str().unwrap()).await.unwrap();
+            assert_eq!(stored.checksum, digest);
+            expected.insert(stored.file_id, digest);
+        }
+        assert_eq!(expected.values().collect::<HashSet<_>>().len(), entries.len());
+
+        let index: serde_json::Value =
+            reqwest::get(body["group"]["url"].as_str().unwrap()).await.unwrap().json().await.unwrap();
+        let members = index["files"].as_array().unwrap();
+        assert_eq!(members.len(), entries.len());
+        for member in members {
+            assert_eq!(member["checksum"], expected[member["id"].as_str().unwrap()].as_str());
+        }
+    }
+
+    #[tokio::test]
+    async fn archive_entries_with_the_same_name_keep_their_positions() {
+        let entries: [(&str, &[u8]); 3] =
+            [("a/photo.jpg", b"first photo"), ("b/photo.jpg", b"second photo"), ("c/photo.jpg", b"third photo")];
+        let server = TestServer::start(|_| {}).await;
+        let response =
+            reqwest::Client::new().post(server.url("/upload/archive?group=true")).body(zip_of(&entries)).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = response.json().await.unwrap();
+        let files = body["files"].as_array().unwrap();
+        let ids: Vec<&str> = files.iter().map(|f| f["id"].as_str().unwrap()).collect();
+        assert_eq!(ids.iter().collect::<HashSet<_>>().len(), entries.len());
+        for (file, (_, data)) in files.iter().zip(entries) {
+            assert_eq!(file["name"], "photo.jpg");
+            let stored = server.state.file_registry.get(file["id"].as_str().unwrap()).await.unwrap();
+            assert_eq!(std::fs::read(&stored.disk_path).unwrap(), data);
+            let download = reqwest::get(file["download"].as_str().unwrap()).await.unwrap();
+            assert_eq!(download.bytes().await.unwrap(), data);
+        }
+
+        let index: serde_json::Value =
+            reqwest::get(body["group"]["url"].as_str().unwrap()).await.unwrap().json().await.unwrap();
+        let listed: Vec<&str> = index["files"].as_array().unwrap().iter().map(|m| m["id"].as_str().unwrap()).collect();
+        assert_eq!(listed, ids);
+    }
+
+    #[tokio::test]
+    async fn reconcile_removes_orphans_inside_shard_directories() {
+        let server = TestServer::start(|config| {
+            config.id_from_content = true;
+            config.sharded_layout = true;
+        })
+        .await;
+        let upload: serde_json::Value = server.upload(&[], &[("file", Some("kept.txt"), b"registered bytes")]).await.json().await.unwrap();
+        let kept = PathBuf::from(server.state.file_registry.get(upload["id"].as_str().unwrap()).await.unwrap().disk_path);
+        assert_ne!(kept.parent().unwrap(), server.dir.as_path());
+
+        let plant = |dir: PathBuf| {
+            std::fs::create_dir_all(&dir).unwrap();
+            let path = dir.join(format!("{}.bin", Uuid::new_v4()));
+            std::fs::write(&path, b"orphan").unwrap();
+            path
+        };
+        let beside_kept = plant(kept.parent().unwrap().to_path_buf());
+        let fresh_shard = plant(server.dir.join("ef").join("01"));
+        let top_level = plant(server.dir.clone());
+        let foreign = plant(server.dir.join("backups"));
+
+        run_reconcile(&server.state).await.unwrap();
+        assert!(kept.exists());
+        assert!(!beside_kept.exists());
+        assert!(!fresh_shard.exists());
+        assert!(!top_level.exists());
+        assert!(foreign.exists());
+    }
+
+    #[tokio::test]
+    async fn pipe_halves_need_the_creator_and_spooled_pipes_are_type_checked() {
+        let server = TestServer::start(|config| {
+            config.pipe = PipeMode::Spool;
+            config.api_keys = HashMap::from([("key-a".to_string(), "alice".to_string()), ("key-b".to_string(), "bob".to_string())]);
+            config.sniff_mime = true;
+            config.allowed_mime_types = vec!["application/octet-stream".to_string()];
+        })
+        .await;
+        let client = reqwest::Client::new();
+        let open = || async {
+            let pipe: serde_json::Value =
+                client.post(server.url("/pipe")).header("x-api-key", "key-a").send().await.unwrap().json().await.unwrap();
+            pipe["id"].as_str().unwrap().to_string()
+        };
+
+        let id = open().await;
+        for key in [None, Some("key-b")] {
+            let mut request = client.get(server.url(&format!("/pipe/{}", id)));
+            if let Some(key) = key {
+                request = request.header("x-api-key", key);
+            }
+            let response = request.send().await.unwrap();
+            assert_eq!(response.status(), StatusCode::FORBIDDEN);
+            let response = client.put(server.url(&format!("/pipe/{}", id))).body("x").send().await.unwrap();
+            assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        }
+
+        server.state.draining.store(true, Ordering::SeqCst);
+        let response = client.put(server.url(&format!("/pipe/{}", id))).header("x-api-key", "key-a").body("x").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        server.state.draining.store(false, Ordering::SeqCst);
+
+        let id = open().await;
+        let mut reader = tokio::net::TcpStream::connect(server.addr).await.unwrap();
+        let head = format!("GET /pipe/{} HTTP/1.1\r\nHost: localhost\r\nX-Api-Key: key-a\r\n\r\n", id);
+        reader.write_all(head.as_bytes()).await.unwrap();
+        let mut buf = [0u8; 512];
+        let _ = reader.read(&mut buf).await.unwrap();
+        drop(reader);
+
+        let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR".to_vec();
+        png.resize(4 * 1024 * 1024, 0);
+        let response = client.put(server.url(&format!("/pipe/{}", id))).header("x-api-key", "key-a").body(png).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["code"], "mime_not_allowed");
+        assert_eq!(body["details"]["mime"], "image/png");
+        assert!(server.state.file_registry.get(&id).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn unknown_text_fields_are_bounded_and_never_stored_as_the_file() {
+        let server = TestServer::start(|config| {
+            config.max_parts = 6;
+            config.max_field_size = 64;
+            config.max_fields_total = 160;
+        })
+        .await;
+        let code = |response: reqwest::Response| async move {
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+            response.json::<serde_json::Value>().await.unwrap()["code"].as_str().unwrap().to_string()
+        };
+
+        let response = server.upload(&[], &[("submit", None, b"Upload"), ("file", Some("a.txt"), b"real bytes")]).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let upload: serde_json::Value = response.json().await.unwrap();
+        let stored = server.state.file_registry.get(upload["id"].as_str().unwrap()).await.unwrap();
+        assert_eq!(std::fs::read(&stored.disk_path).unwrap(), b"real bytes");
+
+        let oversized = vec![b'x'; 65];
+        let response = server.upload(&[], &[("comment", None, &oversized), ("file", Some("a.txt"), b"real bytes")]).await;
+        assert_eq!(code(response).await, "field_too_large");
+
+        let filler = vec![b'x'; 60];
+        let many: Vec<(&str, Option<&str>, &[u8])> = ["a", "b", "c"].iter().map(|n| (*n, None, filler.as_slice())).collect();
+        assert_eq!(code(server.upload(&[], &many).await).await, "fields_too_large");
+
+        let tiny: Vec<(&str, Option<&str>, &[u8])> = ["a", "b", "c", "d", "e", "f", "g"].iter().map(|n| (*n, None, &b"x"[..])).collect();
+        assert_eq!(code(server.upload(&[], &tiny).await).await, "too_many_parts");
+
+        assert_eq!(server.state.file_registry.all().await.len(), 1);
+        assert_eq!(server.stored_files().len(), 1, "{:?}", server.stored_files());
+    }
+
+    #[tokio::test]
+    async fn options_upload_advertises_single_and_archive_file_limits() {
+        let server = TestServer::start(|config| config.archive_max_entries = 25).await;
+        let response = reqwest::Client::new().request(reqwest::Method::OPTIONS, server.url("/upload")).send().await.unwrap();
+        assert_eq!(response.headers()["sptzx-max-files"], "1");
+        assert_eq!(response.headers()["sptzx-archive-max-entries"], "25");
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["max_files"], 1);
+        assert_eq!(body["archive_max_entries"], 25);
+    }
+
+    #[tokio::test]
+    async fn serving_a_file_missing_on_disk_drops_its_entry() {
+        let server = TestServer::start(|_| {}).await;
+        let upload: serde_json::Value = server.upload(&[], &[("file", Some("gone.txt"), b"soon gone")]).await.json().await.unwrap();
+        let id = upload["id"].as_str().unwrap();
+        let stored = server.state.file_registry.get(id).await.unwrap();
+        let bytes_before = server.state.stored_bytes.load(Ordering::Relaxed);
+        std::fs::remove_file(&stored.disk_path).unwrap();
+
+        let response = reqwest::get(upload["view"].as_str().unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["code"], "file_not_found");
+        assert!(server.state.file_registry.get(id).await.is_none());
+        assert_eq!(server.state.stored_bytes.load(Ordering::Relaxed), bytes_before - stored.size);
+
+        let again = reqwest::get(upload["view"].as_str().unwrap()).await.unwrap();
+        assert_eq!(again.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn upload_aborted_mid_body_leaves_nothing_behind() {
+        let server = TestServer::start(|_| {}).await;
+        let body = multipart(&[("file", Some("big.txt"), &vec![b'a'; 256 * 1024])]);
+        let head = format!(
+            "POST /upload HTTP/1.1\r\nHost: {}\r\nContent-Type: multipart/form-data; boundary={}\r\nContent-Length: {}\r\n\r\n",
+            server.addr,
+            BOUNDARY,
+            body.len()
+        );
+        let mut stream = tokio::net::TcpStream::connect(server.addr).await.unwrap();
+        stream.write_all(head.as_bytes()).await.unwrap();
+        stream.write_all(&body[..body.len() / 2]).await.unwrap();
+        for _ in 0..100 {
+            if !server.stored_files().is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(server.stored_files().len(), 1, "the upload should be streaming to disk before the abort");
+        drop(stream);
+
+        for _ in 0..100 {
+            if server.stored_files().is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(server.stored_files().is_empty(), "{:?}", server.stored_files());
+        assert!(server.state.file_registry.all().await.is_empty());
+        assert_eq!(server.state.stored_bytes.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn metadata_fields_apply_on_either_side_of_the_file_part() {
+        let server = TestServer::start(|_| {}).await;
+        let response = server
+            .upload(
+                &[],
+                &[
+                    ("ttl", None, b"120"),
+                    ("file", Some("table.txt"), b"a,b\n1,2\n"),
+                    ("content_type", None, b"text/csv"),
+                    ("password", None, b"hunter2"),
+                    ("description", None, b"sent after the file"),
+                ],
+            )
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let upload: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(upload["ttl"], 120);
+        assert_eq!(upload["mime"], "text/csv");
+        let stored = server.state.file_registry.get(upload["id"].as_str().unwrap()).await.unwrap();
+        assert_eq!(stored.expires_at - stored.uploaded_at, 120);
+        assert!(stored.password_hash.is_some());
+        assert_eq!(stored.description.as_deref(), Some("sent after the file"));
+
+        let code = |response: reqwest::Response| async move {
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+            response.json::<serde_json::Value>().await.unwrap()["code"].as_str().unwrap().to_string()
+        };
+        let late_ttl = server.upload(&[], &[("file", Some("a.txt"), b"hello"), ("ttl", None, b"soon")]).await;
+        assert_eq!(code(late_ttl).await, "invalid_ttl");
+        let twice = server.upload(&[], &[("ttl", None, b"60"), ("file", Some("a.txt"), b"hello"), ("ttl", None, b"120")]).await;
+        assert_eq!(code(twice).await, "duplicate_field");
+        let against_header = server
+            .upload(&[("x-content-type", "text/plain")], &[("file", Some("a.txt"), b"hello"), ("content_type", None, b"text/csv")])
+            .await;
+        assert_eq!(code(against_header).await, "conflicting_field");
+        assert_eq!(server.state.file_registry.all().await.len(), 1);
+        assert_eq!(server.stored_files().len(), 1, "{:?}", server.stored_files());
+    }
+
+    #[test]
+    fn multibyte_filenames_truncate_on_char_boundaries_at_the_limit() {
+        let max_len = 16;
+        let stem_budget = max_len - ".txt".len();
+        for (c, width) in [('é', 2), ('中', 3), ('𝔸', 4)] {
+            assert_eq!(c.len_utf8(), width);
+            let fits = "a".repeat(stem_budget - width);
+            let exact = format!("{}{}.txt", fits, c);
+            assert_eq!(exact.len(), max_len);
+            assert_eq!(sanitize_filename(&exact, max_len), exact);
+
+            let straddles = "a".repeat(stem_budget - width + 1);
+            let truncated = sanitize_filename(&format!("{}{}{}.txt", straddles, c, c), max_len);
+            assert_eq!(truncated, format!("{}.txt", straddles), "{}", c);
+            assert_eq!(truncated.len(), max_len - width + 1);
+
+            let only_multibyte = sanitize_filename(&format!("{}.txt", c.to_string().repeat(10)), max_len);
+            assert!(only_multibyte.ends_with(".txt"));
+            assert_eq!(only_multibyte.len(), ".txt".len() + stem_budget / width * width, "{}", c);
+            assert!(only_multibyte.len() <= max_len);
+        }
+    }
+
+    #[tokio::test]
+    async fn worm_files_refuse_delete_and_purge_but_still_expire() {
+        let server = TestServer::start(|config| {
+            config.allow_worm = true;
+            config.delete_tokens = true;
+            config.admin_token = Some("admin-secret".to_string());
+            config.grace_period = 0;
+        })
+        .await;
+        let upload: serde_json::Value =
+            server.upload(&[], &[("worm", None, b"true"), ("file", Some("audit.log"), b"retained")]).await.json().await.unwrap();
+        let id = upload["id"].as_str().unwrap().to_string();
+        let disk_path = server.state.file_registry.get(&id).await.unwrap().disk_path;
+        assert!(server.state.file_registry.get(&id).await.unwrap().worm);
+
+        let client = reqwest::Client::new();
+        let attempts = [
+            client.delete(server.url(&format!("/file/{}", id))).header("x-delete-token", upload["delete_token"].as_str().unwrap()),
+            client.delete(server.url(&format!("/file/{}", id))).header("x-admin-token", "admin-secret"),
+            client.delete(server.url("/admin/files")).header("x-admin-token", "admin-secret"),
+        ];
+        for attempt in attempts {
+            let response = attempt.send().await.unwrap();
+            assert_eq!(response.status(), StatusCode::FORBIDDEN);
+            assert_eq!(response.json::<serde_json::Value>().await.unwrap()["code"], "worm_protected");
+        }
+        assert!(server.state.file_registry.get(&id).await.is_some());
+        assert!(std::path::Path::new(&disk_path).exists());
+
+        let past = Utc::now().timestamp() - 1;
+        server.state.file_registry.update(&id, move |entry| entry.expires_at = past).await;
+        assert_eq!(run_cleanup_pass(&server.state).await, 1);
+        assert!(server.state.file_registry.get(&id).await.is_none());
+        assert!(!std::path::Path::new(&disk_path).exists());
+    }
+
+    #[tokio::test]
+    async fn signed_type_must_match_the_stored_mime() {
+        let server = TestServer::start(|_| {}).await;
+        let upload: serde_json::Value = server.upload(&[], &[("file", Some("notes.txt"), b"plain words")]).await.json().await.unwrap();
+        let id = upload["id"].as_str().unwrap();
+        let stored = server.state.file_registry.get(id).await.unwrap();
+        let link = LinkOptions::new("inline", stored.expires_at);
+
+        let matching = signed_query(&stored.owner, id, &stored.mime_type.to_ascii_uppercase(), &link, &server.state.config);
+        let response = reqwest::get(server.url(&format!("/file/{}?{}", id, matching))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let resigned = signed_query(&stored.owner, id, "text/html", &link, &server.state.config);
+        let response = reqwest::get(server.url(&format!("/file/{}?{}", id, resigned))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["code"], "type_mismatch");
+    }
+
+    #[tokio::test]
+    async fn restart_purges_past_due_entries_and_reschedules_the_rest() {
+        let server = TestServer::start(|config| {
+            config.metadata_backend = MetadataBackend::Sqlite;
+            config.metadata_path = format!("{}/registry.db", config.upload_dir);
+            config.grace_period = 0;
+        })
+        .await;
+        let mut ids = Vec::new();
+        for name in ["valid.txt", "expired.txt"] {
+            let upload: serde_json::Value =
+                server.upload(&[], &[("ttl", None, b"600"), ("file", Some(name), b"persisted")]).await.json().await.unwrap();
+            ids.push(upload["id"].as_str().unwrap().to_string());
+        }
+        let (valid, expired) = (server.state.file_registry.get(&ids[0]).await.unwrap(), ids[1].clone());
+        let expired_path = server.state.file_registry.get(&expired).await.unwrap().disk_path;
+        let past = Utc::now().timestamp() - 30;
+        server.state.file_registry.update(&expired, move |entry| entry.expires_at = past).await;
+
+        let (expiry_tx, mut expiry_rx) = mpsc::unbounded_channel();
+        let restarted = AppState::new(server.state.config.clone(), expiry_tx, None, None).unwrap();
+        reconcile_registry(restarted.clone(), restarted.file_registry.all().await).await;
+
+        assert!(restarted.ready.load(Ordering::Acquire));
+        assert!(restarted.file_registry.get(&expired).await.is_none());
+        assert!(!std::path::Path::new(&expired_path).exists());
+        assert_eq!(restarted.file_registry.get(&valid.file_id).await.unwrap().expires_at, valid.expires_at);
+        assert_eq!(expiry_rx.try_recv().unwrap(), (valid.file_id.clone(), valid.expires_at));
+        assert!(expiry_rx.try_recv().is_err(), "only the live entry should get a timer");
+        assert_eq!(restarted.stored_bytes.load(Ordering::Relaxed), valid.size);
+    }
+
+    #[tokio::test]
+    async fn write_timeout_cuts_stalled_connections_but_not_slow_readers() {
+        let acceptor = ConnectionAcceptor { limit: None, plaintext: true, write_timeout: Some(Duration::from_millis(300)) };
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let connect = || async {
+            let client = tokio::net::TcpStream::connect(addr).await.unwrap();
+            let (accepted, _) = listener.accept().await.unwrap();
+            let (stream, ()) = acceptor.accept(accepted, ()).await.unwrap();
+            (client, stream)
+        };
+
+        let (_stalled_client, mut stalled) = connect().await;
+        let started = Instant::now();
+        let error = stalled.write_all(&vec![0u8; 64 * 1024 * 1024]).await.unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::TimedOut);
+        assert!(started.elapsed() < Duration::from_secs(10));
+
+        let (mut slow_client, mut slow) = connect().await;
+        let payload = vec![7u8; 8 * 1024 * 1024];
+        let reader = tokio::spawn(async move {
+            let mut received = 0;
+            let mut buf = vec![0u8; 256 * 1024];
+            loop {
+                let read = slow_client.read(&mut buf).await.unwrap();
+                if read == 0 {
+                    return received;
+                }
+                received += read;
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        });
+        slow.write_all(&payload).await.expect("a reader that keeps draining must not time out");
+        slow.shutdown().await.unwrap();
+        drop(slow);
+        assert_eq!(reader.await.unwrap(), payload.len());
+    }
+
+    #[test]
+    fn accept_encoding_q_values_pick_the_preferred_precompressed_variant() {
+        let both = vec!["gzip".to_string(), "br".to_string()];
+        let gzip_only = vec!["gzip".to_string()];
+        let negotiate = |accept: &str, available: &[String]| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::ACCEPT_ENCODING, HeaderValue::from_str(accept).unwrap());
+            negotiate_encoding(&headers, available).map(str::to_string)
+        };
+        let cases: [(&str, &[String], Option<&str>); 9] = [
+            ("gzip;q=0.5, br;q=0.9, *;q=0", &both, Some("br")),
+            ("gzip;q=0.5, br;q=0, *;q=0", &both, Some("gzip")),
+            ("gzip;q=0.5, br;q=0.9, *;q=0", &gzip_only, Some("gzip")),
+            ("br;q=0.9, *;q=0", &gzip_only, None),
+            ("gzip;q=0.8, br;q=0.8", &both, Some("br")),
+            ("identity;q=0, gzip;q=0.1", &both, Some("gzip")),
+            ("identity;q=0, *;q=0.2", &both, Some("br")),
+            ("identity, gzip;q=0.5", &both, None),
+            ("identity;q=0", &both, None),
+        ];
+        for (accept, available, expected) in cases {
+            assert_eq!(negotiate(accept, available).as_deref(), expected, "{}", accept);
+        }
+    }
+
+
+    #[tokio::test]
+    async fn extend_revives_within_the_grace_period_and_not_after_it() {
+        let server = TestServer::start(|config| {
+            config.admin_token = Some("admin-secret".to_string());
+            config.grace_period = 600;
+        })
+        .await;
+        let client = reqwest::Client::new();
+        let mut uploaded = Vec::new();
+        for name in ["lapsed.txt", "gone.txt"] {
+            let upload: serde_json::Value = server.upload(&[], &[("file", Some(name), b"grace body")]).await.json().await.unwrap();
+            let id = upload["id"].as_str().unwrap().to_string();
+            let disk_path = server.state.file_registry.get(&id).await.unwrap().disk_path;
+            uploaded.push((id, disk_path));
+        }
+        let [(lapsed, lapsed_path), (gone, gone_path)] = <[(String, String); 2]>::try_from(uploaded).unwrap();
+
+        let now = Utc::now().timestamp();
+        server.state.file_registry.update(&lapsed, move |entry| entry.expires_at = now - 60).await;
+        server.state.file_registry.update(&gone, move |entry| entry.expires_at = now - 601).await;
+
+        let extend = |id: &str| {
+            client
+                .post(server.url(&format!("/file/{}/extend", id)))
+                .header("x-admin-token", "admin-secret")
+                .json(&serde_json::json!({ "seconds": 300 }))
+                .send()
+        };
+        let response = extend(&lapsed).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert!(body["expires_at"].as_i64().unwrap() > now);
+
+        let response = extend(&gone).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.json::<serde_json::Value>().await.unwrap()["code"], "file_not_found");
+
+        assert_eq!(run_cleanup_pass(&server.state).await, 1);
+        let stored = server.state.file_registry.get(&lapsed).await.unwrap();
+        let query = signed_query(&stored.owner, &lapsed, &stored.mime_type, &LinkOptions::new("inline", stored.expires_at), &server.state.config);
+        let response = reqwest::get(server.url(&format!("/file/{}?{}", lapsed, query))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.bytes().await.unwrap().as_ref(), b"grace body");
+        assert!(std::path::Path::new(&lapsed_path).exists());
+
+        assert!(server.state.file_registry.get(&gone).await.is_none());
+        assert!(!std::path::Path::new(&gone_path).exists());
+        assert_eq!(extend(&gone).await.unwrap().status(), StatusCode::NOT_FOUND);
+    }
+
+
+    #[tokio::test]
+    async fn signed_queries_over_the_length_cap_are_rejected_before_parsing() {
+        let server = TestServer::start(|config| config.max_signed_query = 1024).await;
+        let upload: serde_json::Value = server.upload(&[], &[("file", Some("capped.txt"), b"capped")]).await.json().await.unwrap();
+        let id = upload["id"].as_str().unwrap();
+
+        let oversize_value = format!("sz-version=v2&sz-signature={}", "f".repeat(8 * 1024));
+        let many_params = (0..200).map(|i| format!("sz-x{}=1", i)).collect::<Vec<_>>().join("&");
+        for query in [oversize_value, many_params] {
+            assert!(query.len() > 1024);
+            for path in [format!("/file/{}", id), format!("/file/{}/meta", id)] {
+                let response = reqwest::get(server.url(&format!("{}?{}", path, query))).await.unwrap();
+                assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+                let body: serde_json::Value = response.json().await.unwrap();
+                assert_eq!(body["code"], "query_too_long");
+                assert_eq!(body["details"]["limit"], 1024);
+            }
+        }
+    }
+
+
+    #[tokio::test]
+    async fn chunked_uploads_verify_a_trailer_checksum() {
+        let server = TestServer::start(|_| {}).await;
+        let data = b"checked after the last chunk";
+        let digest = hex::encode(Sha256::digest(data));
+        let head = chunked_upload_head(&server).replace("Transfer-Encoding", "Trailer: X-Checksum-SHA256\r\nTransfer-Encoding");
+        let with_trailer = |checksum: &str| {
+            let mut body = chunked(&multipart(&[("file", Some("trailed.txt"), data)]), 16);
+            body.truncate(body.len() - 2);
+            body.extend_from_slice(format!("X-Checksum-SHA256: {}\r\n\r\n", checksum).as_bytes());
+            body
+        };
+
+        let response = server.raw(&head, &with_trailer(&digest)).await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        let files = server.state.file_registry.all().await;
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].checksum, digest);
+
+        let wrong = hex::encode(Sha256::digest(b"something else"));
+        let response = server.raw(&head, &with_trailer(&wrong)).await;
+        assert!(response.starts_with("HTTP/1.1 422"), "{}", response);
+        assert!(response.contains("checksum_mismatch"), "{}", response);
+        assert!(response.contains(&wrong), "{}", response);
+        assert_eq!(server.state.file_registry.all().await.len(), 1);
+        assert_eq!(server.stored_files().len(), 1);
+    }
+
+
+    #[test]
+    fn owner_signing_keys_do_not_cross_owners_or_fall_back() {
+        let mut config = Config::from_env();
+        config.owner_signing_keys = HashMap::from([
+            ("alice".to_string(), "alice-signing-key".to_string()),
+            ("bob".to_string(), "bob-signing-key".to_string()),
+        ]);
+        assert!(link_verifies(&signed_link(&config, "alice", "text/plain"), &config).unwrap());
+        assert!(link_verifies(&signed_link(&config, "bob", "text/plain"), &config).unwrap());
+
+        let mut alice_key_for_bob = config.clone();
+        alice_key_for_bob.owner_signing_keys.insert("bob".to_string(), "alice-signing-key".to_string());
+        assert!(!link_verifies(&signed_link(&alice_key_for_bob, "bob", "text/plain"), &config).unwrap());
+
+        let mut tampered = signed_link(&config, "alice", "text/plain");
+        tampered.insert("sz-owner".to_string(), "bob".to_string());
+        assert!(!link_verifies(&tampered, &config).unwrap());
+
+        let mut global_only = config.clone();
+        global_only.owner_signing_keys.clear();
+        assert!(!link_verifies(&signed_link(&global_only, "alice", "text/plain"), &config).unwrap());
+        assert!(!link_verifies(&signed_link(&config, "alice", "text/plain"), &global_only).unwrap());
+        assert!(link_verifies(&signed_link(&global_only, "carol", "text/plain"), &config).unwrap());
+    }
+
+
+    #[tokio::test]
+    async fn malformed_signed_fields_get_their_own_error_codes() {
+        let server = TestServer::start(|config| config.signing_version = "v3".to_string()).await;
+        let upload: serde_json::Value = server.upload(&[], &[("file", Some("fields.txt"), b"field check")]).await.json().await.unwrap();
+        let id = upload["id"].as_str().unwrap();
+        let stored = server.state.file_registry.get(id).await.unwrap();
+        let link = LinkOptions::new("inline", stored.expires_at);
+        let valid: HashMap<String, String> =
+            serde_urlencoded::from_str(&signed_query(&stored.owner, id, &stored.mime_type, &link, &server.state.config)).unwrap();
+
+        let cases = [
+            ("sz-expires", "tomorrow", "invalid_expires"),
+            ("sz-uses", "0", "invalid_sz-uses"),
+            ("sz-uses", "many", "invalid_sz-uses"),
+            ("sz-ip", "300.1.2.3", "invalid_sz-ip"),
+            ("sz-mode", "download", "invalid_mode"),
+            ("sz-version", "v9", "unsupported_version"),
+            ("sz-date", "2026-10-14", "invalid_sz-date"),
+            ("sz-signature", "not-hex", "invalid_sz-signature"),
+            ("sz-colour", "blue", "unexpected_parameter"),
+        ];
+        for (key, value, code) in cases {
+            let mut query = valid.clone();
+            query.insert(key.to_string(), value.to_string());
+            let url = format!("/file/{}?{}", id, serde_urlencoded::to_string(&query).unwrap());
+            let response = reqwest::get(server.url(&url)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}={}", key, value);
+            assert_eq!(response.json::<serde_json::Value>().await.unwrap()["code"], code, "{}={}", key, value);
+        }
+
+        let url = format!("/file/{}?{}", id, serde_urlencoded::to_string(&valid).unwrap());
+        assert_eq!(reqwest::get(server.url(&url)).await.unwrap().status(), StatusCode::OK);
+    }
+
+
+    #[test]
+    fn cache_control_tracks_the_remaining_lifetime() {
+        let mut config = Config::from_env();
+        config.cache_control = None;
+        config.cache_policy.clear();
+        config.max_cache_age = None;
+        config.cache_revalidate_below = 60;
+        let now = Utc::now().timestamp();
+        let link = LinkOptions::new("inline", now + 3600);
+        let query: HashMap<String, String> =
+            serde_urlencoded::from_str(&signed_query("owner", "file-1", "text/plain", &link, &config)).unwrap();
+        let params = parse_signed_params(&query).unwrap();
+        let max_age = |config: &Config, file_expires_at: i64| {
+            let value = cache_control_for(config, &params, "text/plain", file_expires_at);
+            let value = value.to_str().unwrap().to_string();
+            let age = value.split(", ").find_map(|part| part.strip_prefix("max-age=")).map(|age| age.parse::<i64>().unwrap());
+            (value, age)
+        };
+
+        let (value, age) = max_age(&config, now + 600);
+        assert!(value.ends_with(", immutable"), "{}", value);
+        assert!((590..=600).contains(&age.unwrap()), "{}", value);
+        let (value, age) = max_age(&config, now + 86400);
+        assert!((3590..=3600).contains(&age.unwrap()), "{}", value);
+
+        config.max_cache_age = Some(120);
+        assert_eq!(max_age(&config, now + 600).1, Some(120));
+
+        config.max_cache_age = None;
+        assert!((80..=90).contains(&max_age(&config, now + 90).1.unwrap()));
+        assert_eq!(max_age(&config, now + 30).0, "no-cache");
+        assert_eq!(max_age(&config, now + 59).0, "no-cache");
+        assert_eq!(max_age(&config, now - 1).0, "no-store");
+    }
+
+
+    #[tokio::test]
+    async fn untimed_uploads_take_the_default_ttl_of_their_type() {
+        let server = TestServer::start(|config| {
+            config.file_lifetime = 3600;
+            config.max_file_lifetime = 86400;
+            config.ttl_by_type = vec![("image/*".to_string(), 600), ("application/pdf".to_string(), 7200)];
+        })
+        .await;
+        let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR\0\0\0\x01\0\0\0\x01\x08\x02\0\0\0".to_vec();
+        png.resize(64, 0);
+        let uploads: [(&str, &[u8], u64); 3] = [
+            ("photo.png", &png, 600),
+            ("report.pdf", b"%PDF-1.4\n%%EOF\n", 7200),
+            ("notes.txt", b"plain words", 3600),
+        ];
+        for (name, data, ttl) in uploads {
+            let upload: serde_json::Value = server.upload(&[], &[("file", Some(name), data)]).await.json().await.unwrap();
+            let stored = server.state.file_registry.get(upload["id"].as_str().unwrap()).await.unwrap();
+            assert_eq!((stored.expires_at - stored.uploaded_at) as u64, ttl, "{} ({})", name, stored.mime_type);
         }
     }
 }